@@ -101,7 +101,9 @@ function getLSPIC10Configurations(): any {
             overline_comment: config.get('warnings.overline_comment'),
             overcolumn_comment: config.get('warnings.overcolumn_comment')
         },
-        suppressHashDiagnostics: config.get('suppressHashDiagnostics')
+        suppressHashDiagnostics: config.get('suppressHashDiagnostics'),
+        dataPack: config.get('dataPack'),
+        gameVersion: config.get('gameVersion')
     };
 }
 
@@ -909,6 +911,105 @@ export function activate(context: vscode.ExtensionContext) {
         }
     }));
 
+    // Replace line-number jumps with label jumps across the active file
+    context.subscriptions.push(vscode.commands.registerCommand('ic10.convertJumpsToLabels', async () => {
+        const editor = vscode.window.activeTextEditor;
+        if (!editor || editor.document.languageId !== 'ic10') {
+            vscode.window.showInformationMessage('No active IC10 file');
+            return;
+        }
+
+        const uri = editor.document.uri.toString();
+        const options: ExecuteCommandParams = {
+            command: 'ic10.convertJumpsToLabels',
+            arguments: [uri]
+        };
+
+        try {
+            // null when the server refused and already said why
+            const edits = await lc.sendRequest('workspace/executeCommand', options);
+            if (edits === 0) {
+                vscode.window.showInformationMessage('No line-number jumps to convert');
+            }
+        } catch (err) {
+            vscode.window.showErrorMessage(`Failed to convert jumps: ${err instanceof Error ? err.message : String(err)}`);
+        }
+    }));
+
+    // Reassign registers so the program uses as few as possible
+    context.subscriptions.push(vscode.commands.registerCommand('ic10.allocateRegisters', async () => {
+        const editor = vscode.window.activeTextEditor;
+        if (!editor || editor.document.languageId !== 'ic10') {
+            vscode.window.showInformationMessage('No active IC10 file');
+            return;
+        }
+
+        const uri = editor.document.uri.toString();
+        const options: ExecuteCommandParams = {
+            command: 'ic10.allocateRegisters',
+            arguments: [uri]
+        };
+
+        try {
+            await lc.sendRequest('workspace/executeCommand', options);
+        } catch (err) {
+            vscode.window.showErrorMessage(`Failed to reallocate registers: ${err instanceof Error ? err.message : String(err)}`);
+        }
+    }));
+
+    // Open a minified build of the script beside it
+    context.subscriptions.push(vscode.commands.registerCommand('ic10.minify', async () => {
+        const editor = vscode.window.activeTextEditor;
+        if (!editor || editor.document.languageId !== 'ic10') {
+            vscode.window.showInformationMessage('No active IC10 file');
+            return;
+        }
+
+        const uri = editor.document.uri.toString();
+        const options: ExecuteCommandParams = {
+            command: 'ic10.minify',
+            arguments: [uri]
+        };
+
+        try {
+            const result = await lc.sendRequest('workspace/executeCommand', options) as { text: string, report: string } | null;
+            if (!result) {
+                return;
+            }
+            const document = await vscode.workspace.openTextDocument({ language: 'ic10', content: result.text });
+            await vscode.window.showTextDocument(document, vscode.ViewColumn.Beside);
+            vscode.window.showInformationMessage(`Minified: ${result.report}`);
+        } catch (err) {
+            vscode.window.showErrorMessage(`Failed to minify script: ${err instanceof Error ? err.message : String(err)}`);
+        }
+    }));
+
+    // Open the script with @include and macro directives expanded
+    context.subscriptions.push(vscode.commands.registerCommand('ic10.preprocess', async () => {
+        const editor = vscode.window.activeTextEditor;
+        if (!editor || editor.document.languageId !== 'ic10') {
+            vscode.window.showInformationMessage('No active IC10 file');
+            return;
+        }
+
+        const uri = editor.document.uri.toString();
+        const options: ExecuteCommandParams = {
+            command: 'ic10.preprocess',
+            arguments: [uri]
+        };
+
+        try {
+            const result = await lc.sendRequest('workspace/executeCommand', options) as { text: string } | null;
+            if (!result) {
+                return;
+            }
+            const document = await vscode.workspace.openTextDocument({ language: 'ic10', content: result.text });
+            await vscode.window.showTextDocument(document, vscode.ViewColumn.Beside);
+        } catch (err) {
+            vscode.window.showErrorMessage(`Failed to preprocess script: ${err instanceof Error ? err.message : String(err)}`);
+        }
+    }));
+
     // Toggle hash-related diagnostics
     context.subscriptions.push(vscode.commands.registerCommand('ic10.suppressHashDiagnostics', async () => {
         console.log('[IC10] suppressHashDiagnostics command invoked');
@@ -0,0 +1,302 @@
+//! Call hierarchy over `jal` subroutines.
+//!
+//! IC10 has no functions, so a program is split into sections at every label: a section
+//! runs from its label to the line before the next one, and code before the first label
+//! belongs to the file itself. A call is any branch-and-link (`jal`, `beqal`, `bgtzal`, ...)
+//! whose target is a known label.
+
+use std::collections::HashMap;
+
+use phf::phf_set;
+use serde_json::{json, Value};
+use tower_lsp::lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, Position, SymbolKind,
+    Url,
+};
+use tree_sitter::{Query, QueryCursor, Tree};
+
+use crate::{DefinitionData, Range};
+
+/// Instructions that store the return address in `ra` before jumping to their last operand.
+pub const CALL_INSTRUCTIONS: phf::Set<&'static str> = phf_set!(
+    "jal", "bdnsal", "bdseal", "bapal", "bapzal", "beqal", "beqzal", "bgeal", "bgezal", "bgtal",
+    "bgtzal", "bleal", "blezal", "bltal", "bltzal", "bnaal", "bnazal", "bneal", "bnezal"
+);
+
+#[derive(Debug, Clone)]
+struct Section {
+    /// Label that opens the section; `None` for code before the first label.
+    label: Option<String>,
+    /// Range of the label identifier (or the file start).
+    selection_range: Range,
+    /// Lines covered by the section, label line included.
+    range: Range,
+}
+
+#[derive(Debug, Clone)]
+struct CallSite {
+    caller: usize,
+    callee: String,
+    range: Range,
+}
+
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    sections: Vec<Section>,
+    calls: Vec<CallSite>,
+}
+
+impl CallGraph {
+    pub fn build(tree: &Tree, content: &str, labels: &HashMap<String, DefinitionData<u8>>) -> Self {
+        let mut graph = CallGraph::default();
+        let line_lengths: Vec<u32> = content.lines().map(|x| x.len() as u32).collect();
+        let line_count = line_lengths.len() as u32;
+
+        let mut cursor = QueryCursor::new();
+        let query = Query::new(
+            tree_sitter_ic10::language(),
+            "(label (identifier)@label)
+             (instruction)@instruction",
+        )
+        .unwrap();
+        let label_idx = query.capture_index_for_name("label").unwrap();
+
+        // Code before the first label forms an implicit leading section
+        graph.sections.push(Section {
+            label: None,
+            selection_range: zero_range(),
+            range: zero_range(),
+        });
+
+        for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+            let node = capture.captures[0].node;
+            if capture.captures[0].index == label_idx {
+                let name = node.utf8_text(content.as_bytes()).unwrap();
+                graph.sections.push(Section {
+                    label: Some(name.to_string()),
+                    selection_range: Range::from(node.range()),
+                    range: Range::from(node.range()),
+                });
+                continue;
+            }
+
+            let Some(operation_node) = node.child_by_field_name("operation") else {
+                continue;
+            };
+            let operation = operation_node
+                .utf8_text(content.as_bytes())
+                .unwrap()
+                .to_ascii_lowercase();
+            if !CALL_INSTRUCTIONS.contains(operation.as_str()) {
+                continue;
+            }
+            let mut tree_cursor = node.walk();
+            let Some(target) = node
+                .children_by_field_name("operand", &mut tree_cursor)
+                .last()
+                .and_then(|x| x.child(0))
+            else {
+                continue;
+            };
+            if target.kind() != "identifier" {
+                continue;
+            }
+            let callee = target.utf8_text(content.as_bytes()).unwrap();
+            if !labels.contains_key(callee) {
+                continue;
+            }
+            graph.calls.push(CallSite {
+                caller: graph.sections.len() - 1,
+                callee: callee.to_string(),
+                range: Range::from(target.range()),
+            });
+        }
+
+        // Each section ends where the next one starts
+        let starts: Vec<u32> = graph
+            .sections
+            .iter()
+            .map(|x| x.selection_range.0.start.line)
+            .collect();
+        for (idx, section) in graph.sections.iter_mut().enumerate() {
+            let start = starts[idx];
+            let end = starts.get(idx + 1).copied().unwrap_or(line_count);
+            let last_line = end.saturating_sub(1).max(start);
+            let last_column = line_lengths.get(last_line as usize).copied().unwrap_or(0);
+            section.range = Range(tower_lsp::lsp_types::Range::new(
+                Position::new(start, 0),
+                Position::new(last_line, last_column),
+            ));
+        }
+
+        graph
+    }
+
+    fn section_for_label(&self, label: Option<&str>) -> Option<usize> {
+        self.sections
+            .iter()
+            .position(|x| x.label.as_deref() == label)
+    }
+
+    fn is_called(&self, label: &str) -> bool {
+        self.calls.iter().any(|x| x.callee == label)
+    }
+
+    fn item(&self, uri: &Url, section: usize) -> CallHierarchyItem {
+        let section = &self.sections[section];
+        let (name, kind, detail) = match section.label.as_deref() {
+            Some(label) => (
+                label.to_string(),
+                SymbolKind::FUNCTION,
+                Some(if self.is_called(label) {
+                    format!(
+                        "subroutine, line {}",
+                        section.selection_range.0.start.line + 1
+                    )
+                } else {
+                    format!("line {}", section.selection_range.0.start.line + 1)
+                }),
+            ),
+            None => (
+                uri.path_segments()
+                    .and_then(|mut x| x.next_back())
+                    .unwrap_or("program")
+                    .to_string(),
+                SymbolKind::FILE,
+                None,
+            ),
+        };
+        CallHierarchyItem {
+            name,
+            kind,
+            tags: None,
+            detail,
+            uri: uri.clone(),
+            range: section.range.clone().into(),
+            selection_range: section.selection_range.clone().into(),
+            data: Some(json!({ "label": section.label })),
+        }
+    }
+
+    /// The hierarchy item for a label, if it is defined in this document.
+    pub fn prepare(&self, uri: &Url, label: &str) -> Option<CallHierarchyItem> {
+        self.section_for_label(Some(label))
+            .map(|section| self.item(uri, section))
+    }
+
+    /// Sections that call the label named by `item`, each with the ranges of its calls.
+    pub fn incoming(&self, uri: &Url, item: &CallHierarchyItem) -> Vec<CallHierarchyIncomingCall> {
+        let Some(label) = item_label(item) else {
+            return Vec::new();
+        };
+        let mut by_caller: Vec<(usize, Vec<tower_lsp::lsp_types::Range>)> = Vec::new();
+        for call in self.calls.iter().filter(|x| x.callee == label) {
+            match by_caller.iter_mut().find(|x| x.0 == call.caller) {
+                Some(entry) => entry.1.push(call.range.clone().into()),
+                None => by_caller.push((call.caller, vec![call.range.clone().into()])),
+            }
+        }
+        by_caller
+            .into_iter()
+            .map(|(caller, from_ranges)| CallHierarchyIncomingCall {
+                from: self.item(uri, caller),
+                from_ranges,
+            })
+            .collect()
+    }
+
+    /// Labels called from the section named by `item`, each with the ranges of the calls.
+    pub fn outgoing(&self, uri: &Url, item: &CallHierarchyItem) -> Vec<CallHierarchyOutgoingCall> {
+        let Some(caller) = self.section_for_label(item_label(item).as_deref()) else {
+            return Vec::new();
+        };
+        let mut by_callee: Vec<(&str, Vec<tower_lsp::lsp_types::Range>)> = Vec::new();
+        for call in self.calls.iter().filter(|x| x.caller == caller) {
+            match by_callee.iter_mut().find(|x| x.0 == call.callee) {
+                Some(entry) => entry.1.push(call.range.clone().into()),
+                None => by_callee.push((&call.callee, vec![call.range.clone().into()])),
+            }
+        }
+        by_callee
+            .into_iter()
+            .filter_map(|(callee, from_ranges)| {
+                let section = self.section_for_label(Some(callee))?;
+                Some(CallHierarchyOutgoingCall {
+                    to: self.item(uri, section),
+                    from_ranges,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The label an item was created for; `None` stands for the code before the first label.
+fn item_label(item: &CallHierarchyItem) -> Option<String> {
+    match item.data.as_ref().and_then(|x| x.get("label")) {
+        Some(Value::String(label)) => Some(label.clone()),
+        Some(_) => None,
+        None => Some(item.name.clone()),
+    }
+}
+
+fn zero_range() -> Range {
+    Range(tower_lsp::lsp_types::Range::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    fn graph(source: &str) -> CallGraph {
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let mut labels = HashMap::new();
+        for (idx, line) in source.lines().enumerate() {
+            if let Some(name) = line.trim().strip_suffix(':') {
+                labels.insert(
+                    name.to_string(),
+                    DefinitionData::new(zero_range(), idx as u8),
+                );
+            }
+        }
+        CallGraph::build(&tree, source, &labels)
+    }
+
+    const SOURCE: &str = "jal init\nmain:\njal read\nbgtal r0 5 vent\nj main\nread:\njal vent\nj ra\nvent:\nj ra\ninit:\nj ra\n";
+
+    #[test]
+    fn incoming_calls_group_by_caller() {
+        let uri = Url::parse("file:///base.ic10").unwrap();
+        let graph = graph(SOURCE);
+        let vent = graph.prepare(&uri, "vent").unwrap();
+        let mut callers: Vec<_> = graph
+            .incoming(&uri, &vent)
+            .into_iter()
+            .map(|x| x.from.name)
+            .collect();
+        callers.sort();
+        assert_eq!(callers, vec!["main", "read"]);
+
+        let init = graph.prepare(&uri, "init").unwrap();
+        let callers = graph.incoming(&uri, &init);
+        assert_eq!(callers.len(), 1);
+        assert_eq!(callers[0].from.kind, SymbolKind::FILE);
+    }
+
+    #[test]
+    fn outgoing_calls_ignore_plain_jumps() {
+        let uri = Url::parse("file:///base.ic10").unwrap();
+        let graph = graph(SOURCE);
+        let main = graph.prepare(&uri, "main").unwrap();
+        let callees: Vec<_> = graph
+            .outgoing(&uri, &main)
+            .into_iter()
+            .map(|x| x.to.name)
+            .collect();
+        assert_eq!(callees, vec!["read", "vent"]);
+        assert_eq!(main.range.start.line, 1);
+        assert_eq!(main.range.end.line, 4);
+    }
+}
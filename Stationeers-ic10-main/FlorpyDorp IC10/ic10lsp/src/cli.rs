@@ -1,4 +1,5 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use tower_lsp::lsp_types::DiagnosticSeverity;
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -9,7 +10,90 @@ pub(crate) struct Cli {
     pub host: Option<String>,
     #[arg(long)]
     pub port: Option<u16>,
-    /// Run diagnostics on the provided files and print results to stdout
+    /// Directory with Enums.json and Stationpedia data merged over the built-in game data
+    #[arg(long)]
+    pub data_pack: Option<std::path::PathBuf>,
+    /// Run diagnostics on the provided files, directories or globs and print results to stdout
     #[arg(long)]
     pub diagnose: Vec<std::path::PathBuf>,
+    /// With --diagnose, keep running and re-check scripts as they or the files they include
+    /// change, printing only changed results
+    #[arg(long, requires = "diagnose")]
+    pub watch: bool,
+    /// Output format for --diagnose
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    /// With --diagnose, read settings from this JSON file; it takes the same keys as the editor's
+    /// initializationOptions (max_lines, max_columns, max_bytes, warnings, suppressHashDiagnostics)
+    #[arg(long, requires = "diagnose")]
+    pub config: Option<std::path::PathBuf>,
+    /// With --diagnose, only report diagnostics at least this severe
+    #[arg(long, value_enum, default_value_t = Severity::Hint)]
+    pub max_severity: Severity,
+    /// Print a minified build of the file to stdout and its size against the limits to stderr
+    #[arg(long)]
+    pub minify: Option<std::path::PathBuf>,
+    /// Print the file with peephole optimizations applied to stdout and each rewrite to stderr
+    #[arg(long)]
+    pub optimize: Option<std::path::PathBuf>,
+    /// Expand `@include` and macro directives and print the resulting script to stdout
+    #[arg(long)]
+    pub preprocess: Option<std::path::PathBuf>,
+    /// Convert the given .ic10 files, directories or globs into saved-script folders of the
+    /// game's library, written to the --into directory
+    #[arg(long, requires = "into")]
+    pub export_library: Vec<std::path::PathBuf>,
+    /// Convert every saved script in this game library directory into an .ic10 file in the
+    /// --into directory
+    #[arg(long, requires = "into", conflicts_with = "export_library")]
+    pub import_library: Option<std::path::PathBuf>,
+    /// Output directory for --export-library and --import-library
+    #[arg(long)]
+    pub into: Option<std::path::PathBuf>,
+    /// List the programmable chips of a world save (its world.xml or the save folder) with their
+    /// housing, location and connected device pins
+    #[arg(long)]
+    pub save: Option<std::path::PathBuf>,
+    /// With --save, write the source of every chip to an .ic10 file in this directory
+    #[arg(long, requires = "save")]
+    pub extract_chips: Option<std::path::PathBuf>,
+    /// With --save, read the .ic10 files written by --extract-chips from this directory and put
+    /// their sources back into the chips, writing the result to --save-copy
+    #[arg(long, requires_all = ["save", "save_copy"])]
+    pub inject_chips: Option<std::path::PathBuf>,
+    /// World file written by --inject-chips; the original save is never modified
+    #[arg(long)]
+    pub save_copy: Option<std::path::PathBuf>,
+    /// With --preprocess, write a JSON source map of the expanded script to this file
+    #[arg(long, requires = "preprocess")]
+    pub source_map: Option<std::path::PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+    Sarif,
+    Gcc,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    /// Whether a diagnostic of `severity` is reported at this level.
+    pub fn includes(self, severity: Option<DiagnosticSeverity>) -> bool {
+        let limit = match self {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Info => DiagnosticSeverity::INFORMATION,
+            Severity::Hint => DiagnosticSeverity::HINT,
+        };
+        severity.unwrap_or(DiagnosticSeverity::ERROR) <= limit
+    }
 }
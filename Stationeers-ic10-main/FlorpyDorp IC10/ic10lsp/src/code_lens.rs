@@ -0,0 +1,125 @@
+//! Code lenses showing label usage and the script's size budget.
+//!
+//! Each label gets one lens with its reference count, the absolute line a numeric jump
+//! would need to reach it, and the bytes its section costs. A section runs from the label
+//! to the line before the next label. The first line carries a lens with the totals for
+//! the whole file, counted the same way as the size diagnostics.
+
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::{CodeLens, Command, Position};
+
+use crate::references::ReferenceIndex;
+use crate::{Configuration, DefinitionData};
+
+/// Bytes a line costs in game: its characters plus a two-byte line ending.
+pub fn line_cost(line: &str) -> usize {
+    line.chars().count() + 2
+}
+
+pub fn code_lenses(
+    content: &str,
+    labels: &HashMap<String, DefinitionData<u8>>,
+    references: &ReferenceIndex,
+    config: &Configuration,
+) -> Vec<CodeLens> {
+    let lines: Vec<&str> = content.lines().collect();
+    let total_bytes: usize = lines.iter().map(|x| line_cost(x)).sum();
+
+    let mut ret = vec![lens(
+        0,
+        format!(
+            "{}/{} lines, {}/{} bytes",
+            lines.len(),
+            config.max_lines,
+            total_bytes,
+            config.max_bytes
+        ),
+    )];
+
+    let mut sorted: Vec<(&String, &DefinitionData<u8>)> = labels.iter().collect();
+    sorted.sort_by_key(|(_, data)| data.range.0.start.line);
+
+    for (idx, (name, data)) in sorted.iter().enumerate() {
+        let start = data.range.0.start.line as usize;
+        let end = sorted
+            .get(idx + 1)
+            .map_or(lines.len(), |(_, next)| next.range.0.start.line as usize);
+        let section_bytes: usize = lines
+            .get(start..end.max(start))
+            .unwrap_or_default()
+            .iter()
+            .map(|x| line_cost(x))
+            .sum();
+        let count = references.count(name);
+
+        ret.push(lens(
+            start as u32,
+            format!(
+                "{} reference{} | line {} | {} bytes",
+                count,
+                if count == 1 { "" } else { "s" },
+                data.value,
+                section_bytes
+            ),
+        ));
+    }
+
+    ret
+}
+
+/// A display-only lens; the empty command keeps clients from trying to resolve it.
+fn lens(line: u32, title: String) -> CodeLens {
+    CodeLens {
+        range: tower_lsp::lsp_types::Range::new(Position::new(line, 0), Position::new(line, 0)),
+        command: Some(Command {
+            title,
+            command: String::new(),
+            arguments: None,
+        }),
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Range;
+    use tree_sitter::Parser;
+
+    #[test]
+    fn labels_report_references_line_and_section_size() {
+        let source = "alias sensor d0\nmain:\nl r0 sensor Temperature\nj main\nidle:\nyield\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let references = ReferenceIndex::build(&tree, source);
+
+        let mut labels = HashMap::new();
+        for (idx, line) in source.lines().enumerate() {
+            if let Some(name) = line.strip_suffix(':') {
+                let range = Range(tower_lsp::lsp_types::Range::new(
+                    Position::new(idx as u32, 0),
+                    Position::new(idx as u32, name.len() as u32),
+                ));
+                labels.insert(name.to_string(), DefinitionData::new(range, idx as u8));
+            }
+        }
+
+        let lenses = code_lenses(source, &labels, &references, &Configuration::default());
+        let titles: Vec<_> = lenses
+            .iter()
+            .map(|x| x.command.as_ref().unwrap().title.as_str())
+            .collect();
+
+        assert_eq!(
+            titles,
+            vec![
+                "6/128 lines, 71/4096 bytes",
+                "1 reference | line 1 | 40 bytes",
+                "0 references | line 4 | 14 bytes",
+            ]
+        );
+        assert_eq!(lenses[1].range.start.line, 1);
+    }
+}
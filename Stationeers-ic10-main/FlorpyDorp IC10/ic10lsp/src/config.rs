@@ -0,0 +1,87 @@
+//! Settings the diagnostics are computed with.
+//!
+//! The editor sends them as initializationOptions and configuration notifications, and
+//! `--diagnose --config` reads the same keys from a JSON file.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::game_versions::GameVersion;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Configuration {
+    pub max_lines: usize,
+    pub max_columns: usize,
+    pub max_bytes: usize,
+    pub warn_overline_comment: bool,
+    pub warn_overcolumn_comment: bool,
+    pub suppress_hash_diagnostics: bool,
+    // Game version scripts are checked against; `None` allows everything
+    pub game_version: Option<GameVersion>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            max_lines: 128,
+            max_columns: 90,
+            max_bytes: 4096,
+            warn_overline_comment: true,
+            warn_overcolumn_comment: true,
+            suppress_hash_diagnostics: false,
+            game_version: None,
+        }
+    }
+}
+
+impl Configuration {
+    /// Apply the settings present in `value`, which has the shape of the client's
+    /// initializationOptions and configuration notifications. Missing keys keep their value.
+    pub fn update(&mut self, value: &Value) {
+        if let Some(warnings) = value.get("warnings").and_then(Value::as_object) {
+            self.warn_overline_comment = warnings
+                .get("overline_comment")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.warn_overline_comment);
+            self.warn_overcolumn_comment = warnings
+                .get("overcolumn_comment")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.warn_overcolumn_comment);
+        }
+
+        let size = |key: &str, current: usize| {
+            value
+                .get(key)
+                .and_then(Value::as_u64)
+                .map(|x| x as usize)
+                .unwrap_or(current)
+        };
+        self.max_lines = size("max_lines", self.max_lines);
+        self.max_columns = size("max_columns", self.max_columns);
+        self.max_bytes = size("max_bytes", self.max_bytes);
+
+        self.suppress_hash_diagnostics = value
+            .get("suppressHashDiagnostics")
+            .and_then(Value::as_bool)
+            .unwrap_or(self.suppress_hash_diagnostics);
+
+        // An empty or unparsable version clears the target
+        if let Some(version) = value.get("gameVersion").and_then(Value::as_str) {
+            self.game_version = GameVersion::parse(version);
+        }
+    }
+
+    /// Load the settings from a JSON file with the same keys as the initializationOptions.
+    pub fn from_file(path: &Path) -> std::result::Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let value: Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        if !value.is_object() {
+            return Err("expected a JSON object".to_string());
+        }
+        let mut config = Self::default();
+        config.update(&value);
+        Ok(config)
+    }
+}
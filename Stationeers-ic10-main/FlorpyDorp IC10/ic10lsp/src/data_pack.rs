@@ -0,0 +1,320 @@
+//! # Runtime Game Data Packs
+//!
+//! The enum and device tables are generated into phf maps at build time, so following a game
+//! update used to mean rebuilding the server. A data pack is a directory loaded at startup whose
+//! contents are merged over those tables; entries in the pack win over built-in ones with the
+//! same name.
+//!
+//! A pack may contain any of:
+//! - `Enums.json` - same layout as the `data/Enums.json` used by the build (`scriptEnums` and
+//!   `basicEnums` families with `values` holding `value`, `description` and `deprecated`)
+//! - `stationpedia.txt` - the data extractor's `"Prefab" hash hex "Display Name"` lines
+//! - `Stationpedia.json` - the game's Stationpedia export, whose `pages` carry `PrefabName`,
+//!   `PrefabHash` and `Title`
+//! - `GameVersions.json` - versions instructions, logic types and enum members were added in or
+//!   removed from, see [`crate::game_versions`]
+//!
+//! Names are leaked once on load so lookups keep returning `&'static str` like the built-in
+//! tables do.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde_json::Value;
+
+use crate::game_versions::Availability;
+
+static ACTIVE: OnceLock<DataPack> = OnceLock::new();
+
+/// One enum member: value, description and deprecation.
+#[derive(Debug, Clone, Copy)]
+pub struct EnumEntry {
+    pub qualified: &'static str,
+    pub value: i32,
+    pub description: &'static str,
+    pub deprecated: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct DataPack {
+    enums: HashMap<&'static str, EnumEntry>,
+    prefab_hashes: HashMap<&'static str, i32>,
+    display_names: HashMap<i32, &'static str>,
+    instruction_versions: HashMap<&'static str, Availability>,
+    logic_type_versions: HashMap<&'static str, Availability>,
+    enum_versions: HashMap<&'static str, Availability>,
+}
+
+impl DataPack {
+    /// Load the pack in `dir`. Fails if the directory holds none of the known files or one of
+    /// them cannot be parsed.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let mut pack = DataPack::default();
+        let mut found = false;
+
+        let read = |name: &str| -> Result<Option<String>, String> {
+            let path = dir.join(name);
+            if !path.is_file() {
+                return Ok(None);
+            }
+            fs::read_to_string(&path)
+                .map(Some)
+                .map_err(|e| format!("Could not read {}: {}", path.display(), e))
+        };
+        let parse = |name: &str, text: &str| -> Result<Value, String> {
+            serde_json::from_str(text).map_err(|e| format!("{}: {}", name, e))
+        };
+
+        if let Some(text) = read("Enums.json")? {
+            pack.add_enums(&parse("Enums.json", &text)?);
+            found = true;
+        }
+        if let Some(text) = read("stationpedia.txt")? {
+            pack.add_stationpedia_lines(&text);
+            found = true;
+        }
+        if let Some(text) = read("Stationpedia.json")? {
+            pack.add_stationpedia_pages(&parse("Stationpedia.json", &text)?);
+            found = true;
+        }
+        if let Some(text) = read("GameVersions.json")? {
+            pack.add_versions(&parse("GameVersions.json", &text)?);
+            found = true;
+        }
+        if !found {
+            return Err(format!(
+                "{} holds no Enums.json, stationpedia.txt, Stationpedia.json or GameVersions.json",
+                dir.display()
+            ));
+        }
+        Ok(pack)
+    }
+
+    /// A pack holding only the version tables of a `GameVersions.json`.
+    pub fn from_versions(json: &Value) -> Self {
+        let mut pack = DataPack::default();
+        pack.add_versions(json);
+        pack
+    }
+
+    /// Short description of what the pack provides, for logs.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} enum members, {} prefabs",
+            self.enums.len(),
+            self.prefab_hashes.len()
+        )
+    }
+
+    pub fn enum_entry(&self, qualified: &str) -> Option<EnumEntry> {
+        self.enums.get(qualified).copied()
+    }
+
+    /// Enum member by qualified name ignoring ASCII case.
+    pub fn enum_entry_case_insensitive(&self, qualified: &str) -> Option<EnumEntry> {
+        self.enums
+            .values()
+            .find(|x| x.qualified.eq_ignore_ascii_case(qualified))
+            .copied()
+    }
+
+    pub fn enum_entries(&self) -> impl Iterator<Item = EnumEntry> + '_ {
+        self.enums.values().copied()
+    }
+
+    pub fn prefab_hash(&self, prefab: &str) -> Option<i32> {
+        self.prefab_hashes.get(prefab).copied()
+    }
+
+    pub fn display_name(&self, hash: i32) -> Option<&'static str> {
+        self.display_names.get(&hash).copied()
+    }
+
+    pub fn instruction_availability(&self, name: &str) -> Option<Availability> {
+        self.instruction_versions.get(name).copied()
+    }
+
+    pub fn logic_type_availability(&self, name: &str) -> Option<Availability> {
+        self.logic_type_versions.get(name).copied()
+    }
+
+    pub fn enum_availability(&self, qualified: &str) -> Option<Availability> {
+        self.enum_versions.get(qualified).copied()
+    }
+
+    pub fn prefabs(&self) -> impl Iterator<Item = (&'static str, i32)> + '_ {
+        self.prefab_hashes.iter().map(|(name, hash)| (*name, *hash))
+    }
+
+    fn add_enums(&mut self, json: &Value) {
+        for section in ["scriptEnums", "basicEnums"] {
+            let Some(families) = json.get(section).and_then(Value::as_object) else {
+                continue;
+            };
+            for (family, family_obj) in families {
+                let Some(values) = family_obj.get("values").and_then(Value::as_object) else {
+                    continue;
+                };
+                for (member, data) in values {
+                    let qualified = format!("{}.{}", family, member);
+                    if self.enums.contains_key(qualified.as_str()) {
+                        continue;
+                    }
+                    let qualified = leak(qualified);
+                    self.enums.insert(
+                        qualified,
+                        EnumEntry {
+                            qualified,
+                            value: data.get("value").and_then(Value::as_i64).unwrap_or(0) as i32,
+                            description: leak(
+                                data.get("description")
+                                    .and_then(Value::as_str)
+                                    .unwrap_or("")
+                                    .to_string(),
+                            ),
+                            deprecated: data
+                                .get("deprecated")
+                                .and_then(Value::as_bool)
+                                .unwrap_or(false),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    fn add_stationpedia_lines(&mut self, text: &str) {
+        for line in text.lines() {
+            let parts: Vec<&str> = line.split('"').collect();
+            // ["", prefab, " hash hex ", display, ""]
+            let [_, prefab, middle, display, ..] = parts[..] else {
+                continue;
+            };
+            let Some(Ok(hash)) = middle.split_whitespace().next().map(str::parse::<i32>) else {
+                continue;
+            };
+            self.add_prefab(prefab, hash, display);
+        }
+    }
+
+    fn add_stationpedia_pages(&mut self, json: &Value) {
+        let Some(pages) = json.get("pages").and_then(Value::as_array) else {
+            return;
+        };
+        for page in pages {
+            let prefab = page.get("PrefabName").and_then(Value::as_str);
+            let hash = page.get("PrefabHash").and_then(Value::as_i64);
+            let (Some(prefab), Some(hash)) = (prefab, hash) else {
+                continue;
+            };
+            let title = page.get("Title").and_then(Value::as_str).unwrap_or(prefab);
+            self.add_prefab(prefab, hash as i32, title);
+        }
+    }
+
+    fn add_versions(&mut self, json: &Value) {
+        for (section, table) in [
+            ("instructions", &mut self.instruction_versions),
+            ("logicTypes", &mut self.logic_type_versions),
+            ("enums", &mut self.enum_versions),
+        ] {
+            let Some(entries) = json.get(section).and_then(Value::as_object) else {
+                continue;
+            };
+            for (name, data) in entries {
+                let version = |key: &str| {
+                    data.get(key)
+                        .and_then(Value::as_str)
+                        .map(|x| leak(x.to_string()))
+                };
+                table.insert(
+                    leak(name.clone()),
+                    Availability {
+                        added: version("added"),
+                        removed: version("removed"),
+                    },
+                );
+            }
+        }
+    }
+
+    fn add_prefab(&mut self, prefab: &str, hash: i32, display: &str) {
+        if prefab.is_empty() || self.prefab_hashes.contains_key(prefab) {
+            return;
+        }
+        self.prefab_hashes.insert(leak(prefab.to_string()), hash);
+        self.display_names
+            .entry(hash)
+            .or_insert_with(|| leak(display.to_string()));
+    }
+}
+
+/// Make `pack` the active data pack. Only the first pack installed takes effect; returns `false`
+/// if one was already active.
+pub fn install(pack: DataPack) -> bool {
+    ACTIVE.set(pack).is_ok()
+}
+
+/// The data pack loaded at startup, if any.
+pub fn active() -> Option<&'static DataPack> {
+    ACTIVE.get()
+}
+
+fn leak(text: String) -> &'static str {
+    Box::leak(text.into_boxed_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_files_are_merged_into_one_table() {
+        let mut pack = DataPack::default();
+        pack.add_enums(&serde_json::json!({
+            "scriptEnums": { "LogicType": { "values": {
+                "Flux": { "value": 999, "description": "Modded flux", "deprecated": false }
+            } } },
+            "basicEnums": { "LogicType": { "values": {
+                "Flux": { "value": 1, "description": "Shadowed", "deprecated": true }
+            } } }
+        }));
+        pack.add_stationpedia_lines(
+            "\"StructureFluxPump\" -12345 0xffffcfc7 \"Flux Pump\"\nnot a prefab line\n",
+        );
+        pack.add_stationpedia_pages(&serde_json::json!({ "pages": [
+            { "PrefabName": "ItemFluxCell", "PrefabHash": 4242, "Title": "Flux Cell" },
+            { "Title": "Guide page without a prefab" }
+        ] }));
+
+        let flux = pack.enum_entry_case_insensitive("logictype.flux").unwrap();
+        assert_eq!(
+            (
+                flux.qualified,
+                flux.value,
+                flux.description,
+                flux.deprecated
+            ),
+            ("LogicType.Flux", 999, "Modded flux", false)
+        );
+        assert_eq!(pack.prefab_hash("StructureFluxPump"), Some(-12345));
+        assert_eq!(pack.display_name(4242), Some("Flux Cell"));
+        assert_eq!(pack.summary(), "1 enum members, 2 prefabs");
+
+        pack.add_versions(&serde_json::json!({
+            "instructions": { "pow": { "added": "0.2.6000" } },
+            "enums": { "LogicType.Flux": { "removed": "0.2.5500" } }
+        }));
+        assert_eq!(
+            pack.instruction_availability("pow").and_then(|x| x.added),
+            Some("0.2.6000")
+        );
+        assert_eq!(
+            pack.enum_availability("LogicType.Flux")
+                .and_then(|x| x.removed),
+            Some("0.2.5500")
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Rendering of `--diagnose` results for people and for CI.
+//!
+//! `text` is the original human-readable listing, `gcc` prints `file:line:col: level: message`
+//! lines that editors and CI log parsers understand, `json` is a plain machine-readable dump and
+//! `sarif` is a SARIF 2.1.0 log for code scanning tools. Lines and columns are 1-based in every
+//! format except `text`, which keeps the 0-based positions it always printed.
+
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+
+use crate::cli::OutputFormat;
+
+/// Diagnostics of one checked file.
+pub struct FileDiagnostics {
+    pub path: PathBuf,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+pub fn render(format: OutputFormat, files: &[FileDiagnostics]) -> String {
+    match format {
+        OutputFormat::Text => text(files),
+        OutputFormat::Gcc => gcc(files),
+        OutputFormat::Json => serde_json::to_string_pretty(&self::json(files)).unwrap(),
+        OutputFormat::Sarif => serde_json::to_string_pretty(&sarif(files)).unwrap(),
+    }
+}
+
+fn text(files: &[FileDiagnostics]) -> String {
+    let mut ret = String::new();
+    for file in files {
+        ret += &format!("Diagnostics for {}:\n", file.path.display());
+        if file.diagnostics.is_empty() {
+            ret += "  (no diagnostics)\n";
+        }
+        for d in &file.diagnostics {
+            let sev = match d.severity {
+                Some(DiagnosticSeverity::ERROR) => "ERROR",
+                Some(DiagnosticSeverity::WARNING) => "WARN",
+                Some(DiagnosticSeverity::INFORMATION) => "INFO",
+                Some(DiagnosticSeverity::HINT) => "HINT",
+                _ => "UNKNOWN",
+            };
+            ret += &format!(
+                "  {}:{}:{} - {}\n",
+                sev, d.range.start.line, d.range.start.character, d.message
+            );
+        }
+        ret += "\n";
+    }
+    ret
+}
+
+fn gcc(files: &[FileDiagnostics]) -> String {
+    let mut ret = String::new();
+    for file in files {
+        for d in &file.diagnostics {
+            ret += &format!(
+                "{}:{}:{}: {}: {}\n",
+                file.path.display(),
+                d.range.start.line + 1,
+                d.range.start.character + 1,
+                level(d),
+                d.message
+            );
+        }
+    }
+    ret
+}
+
+fn json(files: &[FileDiagnostics]) -> Value {
+    let files: Vec<Value> = files
+        .iter()
+        .map(|file| {
+            let diagnostics: Vec<Value> = file
+                .diagnostics
+                .iter()
+                .map(|d| {
+                    json!({
+                        "line": d.range.start.line + 1,
+                        "column": d.range.start.character + 1,
+                        "endLine": d.range.end.line + 1,
+                        "endColumn": d.range.end.character + 1,
+                        "severity": level(d),
+                        "code": code(d),
+                        "message": d.message,
+                    })
+                })
+                .collect();
+            json!({
+                "file": file.path.display().to_string(),
+                "diagnostics": diagnostics,
+            })
+        })
+        .collect();
+    Value::Array(files)
+}
+
+fn sarif(files: &[FileDiagnostics]) -> Value {
+    let mut rules: Vec<String> = Vec::new();
+    let mut results = Vec::new();
+    for file in files {
+        let uri = file.path.display().to_string().replace('\\', "/");
+        for d in &file.diagnostics {
+            let rule = code(d).unwrap_or_else(|| "ic10".to_string());
+            if !rules.contains(&rule) {
+                rules.push(rule.clone());
+            }
+            results.push(json!({
+                "ruleId": rule,
+                "level": level(d),
+                "message": { "text": d.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": uri },
+                        "region": {
+                            "startLine": d.range.start.line + 1,
+                            "startColumn": d.range.start.character + 1,
+                            "endLine": d.range.end.line + 1,
+                            "endColumn": d.range.end.character + 1,
+                        },
+                    },
+                }],
+            }));
+        }
+    }
+    let rules: Vec<Value> = rules.into_iter().map(|x| json!({ "id": x })).collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "ic10lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// SARIF/GCC level of a diagnostic: `error`, `warning` or `note`.
+fn level(diagnostic: &Diagnostic) -> &'static str {
+    match diagnostic.severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        _ => "note",
+    }
+}
+
+fn code(diagnostic: &Diagnostic) -> Option<String> {
+    match diagnostic.code.as_ref()? {
+        NumberOrString::Number(x) => Some(x.to_string()),
+        NumberOrString::String(x) => Some(x.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::{Position, Range};
+
+    fn files() -> Vec<FileDiagnostics> {
+        vec![FileDiagnostics {
+            path: PathBuf::from("scripts/furnace.ic10"),
+            diagnostics: vec![Diagnostic {
+                range: Range::new(Position::new(2, 4), Position::new(2, 9)),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String("absolute_jump".to_string())),
+                message: "Unknown identifier".to_string(),
+                ..Default::default()
+            }],
+        }]
+    }
+
+    #[test]
+    fn gcc_and_sarif_use_one_based_positions() {
+        assert_eq!(
+            render(OutputFormat::Gcc, &files()),
+            "scripts/furnace.ic10:3:5: error: Unknown identifier\n"
+        );
+        let sarif = sarif(&files());
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "absolute_jump");
+        assert_eq!(result["level"], "error");
+        let region = &result["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(
+            (region["startLine"].as_u64(), region["endColumn"].as_u64()),
+            (Some(3), Some(10))
+        );
+        assert_eq!(
+            sarif["runs"][0]["tool"]["driver"]["rules"][0]["id"],
+            "absolute_jump"
+        );
+    }
+}
@@ -0,0 +1,1113 @@
+//! Diagnostics engine shared by the language server and `--diagnose`.
+//!
+//! Both run [`check_script`] with the active [`Configuration`], so a script checked in CI with
+//! `--config` gets exactly the diagnostics the editor shows for the same settings. A script using
+//! `@include` or macros is checked as its expansion. The checks cover syntax and type errors,
+//! duplicate definitions, line, column and byte limits, the absolute jump lint, the register usage
+//! analysis and, with a target game version, the availability of instructions and logic types on
+//! that version.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use phf::phf_set;
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString,
+    Position as LspPosition, Range as LspRange, Url,
+};
+use tree_sitter::{Parser, Query, QueryCursor, Tree};
+
+use crate::config::Configuration;
+use crate::game_versions::{self, GameVersion};
+use crate::hash_utils::{extract_hash_argument, get_device_hash, is_hash_function_call};
+use crate::instructions::{self, DataType};
+use crate::preprocessor;
+use crate::register_analysis::{self, RegisterAnalyzer};
+use crate::types::{AliasValue, DefinitionData, Position, Range, TypeData};
+
+/// Diagnostic code for absolute jump instructions (should use relative jumps)
+pub const LINT_ABSOLUTE_JUMP: &str = "absolute-jump";
+
+/// Diagnostic code for instructions and logic types missing from the target game version
+pub const LINT_GAME_VERSION: &str = "game-version";
+
+// Data types an enum keyword can stand for, used to type check keyword operands
+
+/// Parameters that only accept LogicType (e.g., Temperature, Pressure)
+const LOGIC_ONLY: [DataType; 1] = [DataType::LogicType];
+
+/// Parameters that only accept SlotLogicType (e.g., Occupant, OccupantHash)
+const SLOT_ONLY: [DataType; 1] = [DataType::SlotLogicType];
+
+/// Parameters that only accept BatchMode (e.g., Average, Sum, Maximum)
+const BATCH_ONLY: [DataType; 1] = [DataType::BatchMode];
+
+/// Parameters that only accept ReagentMode
+const REAGENT_ONLY: [DataType; 1] = [DataType::ReagentMode];
+
+/// Parameters that only accept Name
+const NAME_ONLY: [DataType; 1] = [DataType::Name];
+const LOGIC_SLOT: [DataType; 2] = [DataType::LogicType, DataType::SlotLogicType];
+const LOGIC_BATCH: [DataType; 2] = [DataType::LogicType, DataType::BatchMode];
+const LOGIC_REAGENT: [DataType; 2] = [DataType::LogicType, DataType::ReagentMode];
+const SLOT_BATCH: [DataType; 2] = [DataType::SlotLogicType, DataType::BatchMode];
+const SLOT_REAGENT: [DataType; 2] = [DataType::SlotLogicType, DataType::ReagentMode];
+const BATCH_REAGENT: [DataType; 2] = [DataType::BatchMode, DataType::ReagentMode];
+const LOGIC_SLOT_BATCH: [DataType; 3] = [
+    DataType::LogicType,
+    DataType::SlotLogicType,
+    DataType::BatchMode,
+];
+const LOGIC_SLOT_REAGENT: [DataType; 3] = [
+    DataType::LogicType,
+    DataType::SlotLogicType,
+    DataType::ReagentMode,
+];
+const LOGIC_BATCH_REAGENT: [DataType; 3] = [
+    DataType::LogicType,
+    DataType::BatchMode,
+    DataType::ReagentMode,
+];
+const SLOT_BATCH_REAGENT: [DataType; 3] = [
+    DataType::SlotLogicType,
+    DataType::BatchMode,
+    DataType::ReagentMode,
+];
+const LOGIC_SLOT_BATCH_REAGENT: [DataType; 4] = [
+    DataType::LogicType,
+    DataType::SlotLogicType,
+    DataType::BatchMode,
+    DataType::ReagentMode,
+];
+
+#[derive(Clone, Copy)]
+struct KeywordFlags(u8);
+
+impl KeywordFlags {
+    fn from_bools(logic: bool, slot: bool, batch: bool, reagent: bool) -> Self {
+        KeywordFlags(
+            (logic as u8) | ((slot as u8) << 1) | ((batch as u8) << 2) | ((reagent as u8) << 3),
+        )
+    }
+
+    fn any(self) -> bool {
+        self.0 != 0
+    }
+
+    fn to_union(self) -> instructions::Union<'static> {
+        union_from_mask(self.0)
+    }
+}
+
+fn classify_exact_keyword(ident: &str) -> KeywordFlags {
+    KeywordFlags::from_bools(
+        instructions::is_logic_type(ident),
+        instructions::is_slot_logic_type(ident),
+        instructions::BATCH_MODES.contains(ident),
+        instructions::REAGENT_MODES.contains(ident),
+    )
+}
+
+fn classify_ci_keyword(ident: &str) -> KeywordFlags {
+    KeywordFlags::from_bools(
+        instructions::is_logic_type_case_insensitive(ident),
+        instructions::is_slot_logic_type_case_insensitive(ident),
+        instructions::BATCH_MODES
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(ident)),
+        instructions::REAGENT_MODES
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(ident)),
+    )
+}
+
+fn union_from_mask(mask: u8) -> instructions::Union<'static> {
+    match mask {
+        0 => instructions::Union(&[]),
+        0b0001 => instructions::Union(&LOGIC_ONLY),
+        0b0010 => instructions::Union(&SLOT_ONLY),
+        0b0100 => instructions::Union(&BATCH_ONLY),
+        0b1000 => instructions::Union(&REAGENT_ONLY),
+        0b0011 => instructions::Union(&LOGIC_SLOT),
+        0b0101 => instructions::Union(&LOGIC_BATCH),
+        0b1001 => instructions::Union(&LOGIC_REAGENT),
+        0b0110 => instructions::Union(&SLOT_BATCH),
+        0b1010 => instructions::Union(&SLOT_REAGENT),
+        0b1100 => instructions::Union(&BATCH_REAGENT),
+        0b0111 => instructions::Union(&LOGIC_SLOT_BATCH),
+        0b1011 => instructions::Union(&LOGIC_SLOT_REAGENT),
+        0b1101 => instructions::Union(&LOGIC_BATCH_REAGENT),
+        0b1110 => instructions::Union(&SLOT_BATCH_REAGENT),
+        0b1111 => instructions::Union(&LOGIC_SLOT_BATCH_REAGENT),
+        _ => instructions::Union(&[]),
+    }
+}
+
+fn diagnostic_identity(diag: &Diagnostic) -> (u32, u32, u32, u32, String) {
+    (
+        diag.range.start.line,
+        diag.range.start.character,
+        diag.range.end.line,
+        diag.range.end.character,
+        diag.message.clone(),
+    )
+}
+
+/// Check a script the way the editor does: one using `@include` or macros is checked as its
+/// expansion, with the diagnostics mapped back onto it. Includes resolve against `dir`.
+pub fn check_script(
+    url: &Url,
+    content: &str,
+    dir: Option<&Path>,
+    config: &Configuration,
+) -> Vec<Diagnostic> {
+    if !preprocessor::has_directives(content) {
+        return check_text(url, content, config);
+    }
+    let expansion = preprocessor::expand(content, dir, |x| fs::read_to_string(x));
+    let mut diagnostics: Vec<Diagnostic> = check_text(url, &expansion.text, config)
+        .into_iter()
+        .map(|x| expansion.map_diagnostic(x, content))
+        .collect();
+    for error in expansion.errors {
+        let length = content.lines().nth(error.site as usize).map_or(0, str::len);
+        diagnostics.push(Diagnostic::new(
+            LspRange::new(
+                LspPosition::new(error.site, 0),
+                LspPosition::new(error.site, length as u32),
+            ),
+            Some(DiagnosticSeverity::ERROR),
+            None,
+            None,
+            error.message,
+            None,
+            None,
+        ));
+    }
+    diagnostics
+}
+
+/// Parse `content` and check it; `url` is used for related information.
+pub fn check_text(url: &Url, content: &str, config: &Configuration) -> Vec<Diagnostic> {
+    let mut parser = Parser::new();
+    parser
+        .set_language(tree_sitter_ic10::language())
+        .expect("Could not set language");
+    let tree = parser.parse(content, None).unwrap();
+    check(url, &tree, content, config)
+}
+
+/// All diagnostics of a parsed script.
+pub fn check(url: &Url, tree: &Tree, content: &str, config: &Configuration) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut type_data = TypeData::default();
+    collect_definitions(url, tree, content, &mut type_data, &mut diagnostics);
+
+    // Register usage, shared by the type check and the register diagnostics
+    let mut register_analyzer = RegisterAnalyzer::new();
+    register_analyzer.analyze_register_usage(tree, content, &type_data.aliases);
+
+    // Syntax errors
+    {
+        let mut cursor = QueryCursor::new();
+        let query = Query::new(tree_sitter_ic10::language(), "(ERROR)@error").unwrap();
+        let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
+        for (capture, _) in captures {
+            diagnostics.push(Diagnostic::new(
+                Range::from(capture.captures[0].node.range()).into(),
+                Some(DiagnosticSeverity::ERROR),
+                None,
+                None,
+                "Syntax error".to_string(),
+                None,
+                None,
+            ));
+        }
+    }
+
+    // Find invalid instructions
+    {
+        let mut cursor = QueryCursor::new();
+        let query = Query::new(
+            tree_sitter_ic10::language(),
+            "(instruction (invalid_instruction)@error)",
+        )
+        .unwrap();
+        let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
+        for (capture, _) in captures {
+            let node = capture.captures[0].node;
+            let instruction_text = node.utf8_text(content.as_bytes()).unwrap();
+            if !instructions::INSTRUCTIONS.contains_key(instruction_text) {
+                diagnostics.push(Diagnostic::new(
+                    Range::from(node.range()).into(),
+                    Some(DiagnosticSeverity::ERROR),
+                    None,
+                    None,
+                    "Invalid instruction".to_string(),
+                    None,
+                    None,
+                ));
+            }
+        }
+    }
+
+    // Type check
+    check_types(
+        tree,
+        content,
+        &type_data,
+        config,
+        &mut diagnostics,
+        &register_analyzer,
+    );
+
+    // Overlength checks
+    {
+        let mut cursor = QueryCursor::new();
+
+        let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
+        for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+            let node = capture.captures[0].node;
+            if node.end_position().column > config.max_columns {
+                diagnostics.push(Diagnostic {
+                    range: LspRange::new(
+                        LspPosition::new(node.end_position().row as u32, config.max_columns as u32),
+                        Position::from(node.end_position()).into(),
+                    ),
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    message: format!("Instruction past column {}", config.max_columns),
+                    ..Default::default()
+                });
+            }
+        }
+
+        if config.warn_overcolumn_comment {
+            let query = Query::new(tree_sitter_ic10::language(), "(comment)@x").unwrap();
+            for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+                let node = capture.captures[0].node;
+                if node.end_position().column > config.max_columns {
+                    diagnostics.push(Diagnostic {
+                        range: LspRange::new(
+                            LspPosition::new(
+                                node.end_position().row as u32,
+                                config.max_columns as u32,
+                            ),
+                            Position::from(node.end_position()).into(),
+                        ),
+                        severity: Some(DiagnosticSeverity::WARNING),
+                        message: format!("Comment past column {}", config.max_columns),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        cursor.set_point_range(
+            tree_sitter::Point::new(config.max_lines, 0)
+                ..tree_sitter::Point::new(usize::MAX, usize::MAX),
+        );
+        let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
+
+        for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+            let node = capture.captures[0].node;
+            diagnostics.push(Diagnostic {
+                range: Range::from(node.range()).into(),
+                severity: Some(DiagnosticSeverity::ERROR),
+                message: format!("Instruction past line {}", config.max_lines),
+                ..Default::default()
+            });
+        }
+
+        if config.warn_overline_comment {
+            let query = Query::new(tree_sitter_ic10::language(), "(comment)@x").unwrap();
+            for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+                let node = capture.captures[0].node;
+                diagnostics.push(Diagnostic {
+                    range: Range::from(node.range()).into(),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    message: format!("Comment past line {}", config.max_lines),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    // Byte size check
+    {
+        let mut byte_count = 0;
+        let mut start_pos: Option<LspPosition> = None;
+        let mut current_line = 0;
+        let mut current_col = 0;
+
+        for char in content.chars() {
+            let char_len = if char == '\n' { 2 } else { 1 };
+
+            if byte_count <= config.max_bytes && byte_count + char_len > config.max_bytes {
+                if start_pos.is_none() {
+                    start_pos = Some(LspPosition::new(current_line, current_col));
+                }
+            }
+            byte_count += char_len;
+
+            if char == '\n' {
+                current_line += 1;
+                current_col = 0;
+            } else {
+                current_col += 1;
+            }
+        }
+
+        if byte_count > config.max_bytes {
+            let end_line = content.lines().count().saturating_sub(1) as u32;
+            let end_col = content.lines().last().map_or(0, |l| l.len()) as u32;
+
+            diagnostics.push(Diagnostic {
+                range: LspRange::new(
+                    start_pos.unwrap_or_else(|| LspPosition::new(end_line, 0)),
+                    LspPosition::new(end_line, end_col),
+                ),
+                severity: Some(DiagnosticSeverity::ERROR),
+                message: format!(
+                    "Script size ({} bytes) exceeds the maximum limit of {} bytes.",
+                    byte_count, config.max_bytes
+                ),
+                ..Default::default()
+            });
+        }
+    }
+
+    // Absolute jump to number lint
+    {
+        const BRANCH_INSTRUCTIONS: phf::Set<&'static str> = phf_set!(
+            "bdns", "bdnsal", "bdse", "bdseal", "bap", "bapz", "bapzal", "beq", "beqal", "beqz",
+            "beqzal", "bge", "bgeal", "bgez", "bgezal", "bgt", "bgtal", "bgtz", "bgtzal", "ble",
+            "bleal", "blez", "blezal", "blt", "bltal", "bltz", "bltzal", "bna", "bnaz", "bnazal",
+            "bne", "bneal", "bnez", "bnezal", "j", "jal"
+        );
+        let mut cursor = QueryCursor::new();
+        let query = Query::new(
+            tree_sitter_ic10::language(),
+            "(instruction operand: (operand (number))) @x",
+        )
+        .unwrap();
+        let mut tree_cursor = tree.walk();
+        let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
+        for (capture, _) in captures {
+            let capture = capture.captures[0].node;
+            let Some(operation_node) = capture.child_by_field_name("operation") else {
+                continue;
+            };
+            let operation = operation_node.utf8_text(content.as_bytes()).unwrap();
+            if !BRANCH_INSTRUCTIONS.contains(operation) {
+                continue;
+            }
+
+            tree_cursor.reset(capture);
+            let Some(last_operand) = capture
+                .children_by_field_name("operand", &mut tree_cursor)
+                .into_iter()
+                .last()
+            else {
+                continue;
+            };
+            if let Some(last_operand) = last_operand.child(0) {
+                if last_operand.kind() == "number" {
+                    diagnostics.push(Diagnostic::new(
+                        Range::from(capture.range()).into(),
+                        Some(DiagnosticSeverity::WARNING),
+                        Some(NumberOrString::String(LINT_ABSOLUTE_JUMP.to_string())),
+                        None,
+                        "Absolute jump to line number".to_string(),
+                        None,
+                        None,
+                    ));
+                }
+            }
+        }
+    }
+
+    if let Some(version) = &config.game_version {
+        check_game_version(tree, content, version, &mut diagnostics);
+    }
+
+    // Register usage analysis
+    {
+        let register_diagnostics = register_analyzer.generate_diagnostics();
+        let mut seen = HashSet::new();
+        for existing in diagnostics.iter() {
+            seen.insert(diagnostic_identity(existing));
+        }
+        for diag in register_diagnostics {
+            if seen.insert(diagnostic_identity(&diag)) {
+                diagnostics.push(diag);
+            }
+        }
+    }
+
+    // Global deduplication to avoid duplicate squiggles across all producers
+    {
+        use std::collections::HashSet;
+        let mut seen: HashSet<(u32, u32, u32, u32, String)> = HashSet::new();
+        diagnostics.retain(|d| seen.insert(diagnostic_identity(d)));
+    }
+
+    diagnostics
+}
+
+/// Collect the defines, aliases and labels of a script into `type_data`, reporting duplicates.
+pub fn collect_definitions(
+    url: &Url,
+    tree: &Tree,
+    content: &str,
+    type_data: &mut TypeData,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    type_data.defines.clear();
+    type_data.aliases.clear();
+    type_data.labels.clear();
+
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(
+        tree_sitter_ic10::language(),
+        "(instruction (operation \"define\"))@define
+                         (instruction (operation \"alias\"))@alias
+                         (instruction (operation \"label\"))@alias
+                         (label (identifier)@label)",
+    )
+    .unwrap();
+
+    let define_idx = query.capture_index_for_name("define").unwrap();
+    let alias_idx = query.capture_index_for_name("alias").unwrap();
+    let label_idx = query.capture_index_for_name("label").unwrap();
+
+    let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
+
+    for (capture, _) in captures {
+        let capture_idx = capture.captures[0].index;
+        if capture_idx == define_idx || capture_idx == alias_idx {
+            if let Some(name_node) = capture.captures[0].node.child_by_field_name("operand") {
+                // Prefer the inner identifier text to avoid whitespace/operand wrapper differences
+                let name = if let Some(inner) = name_node.child(0) {
+                    inner.utf8_text(content.as_bytes()).unwrap()
+                } else {
+                    name_node.utf8_text(content.as_bytes()).unwrap()
+                }
+                .trim();
+                let previous_range = {
+                    if let Some(previous) = type_data.defines.get(name) {
+                        Some(previous.range.clone())
+                    } else if let Some(previous) = type_data.aliases.get(name) {
+                        Some(previous.range.clone())
+                    } else {
+                        None
+                    }
+                };
+                if let Some(previous_range) = previous_range {
+                    diagnostics.push(Diagnostic::new(
+                        Range::from(name_node.range()).into(),
+                        Some(DiagnosticSeverity::ERROR),
+                        None,
+                        None,
+                        "Duplicate definition".to_string(),
+                        Some(vec![DiagnosticRelatedInformation {
+                            location: Location::new(url.clone(), previous_range.into()),
+                            message: "Previously defined here".to_string(),
+                        }]),
+                        None,
+                    ));
+                    continue;
+                } else {
+                    let mut cursor = capture.captures[0].node.walk();
+                    let value_node = capture.captures[0]
+                        .node
+                        .children_by_field_name("operand", &mut cursor)
+                        .last();
+
+                    if let Some(value_node) = value_node {
+                        let value = value_node.utf8_text(content.as_bytes()).unwrap();
+                        if capture.captures[0].index == define_idx {
+                            // Allow defines to be numeric or function-call / preproc strings / identifiers
+                            // (e.g. HASH(...) or STR(...)) so user can define hash or string constants.
+                            let child_kind = value_node.child(0).map(|x| x.kind()).unwrap_or("");
+                            if child_kind != "number"
+                                && child_kind != "function_call"
+                                && child_kind != "hash_preproc"
+                                && child_kind != "preproc_string"
+                                && child_kind != "identifier"
+                            {
+                                continue;
+                            }
+                            type_data.defines.insert(
+                                name.to_owned(),
+                                DefinitionData::new(
+                                    name_node.range().into(),
+                                    value.to_string().into(),
+                                ),
+                            );
+                        } else if capture.captures[0].index == alias_idx {
+                            if value_node
+                                .child(0)
+                                .map(|x| x.kind())
+                                .map_or(false, |x| x != "register" && x != "device_spec")
+                            {
+                                continue;
+                            }
+                            type_data.aliases.insert(
+                                name.to_owned(),
+                                DefinitionData::new(
+                                    name_node.range().into(),
+                                    value.to_owned().into(),
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+        } else if capture_idx == label_idx {
+            let name_node = capture.captures[0].node;
+            let name = name_node.utf8_text(content.as_bytes()).unwrap();
+            if let Some(previous) = type_data.get_range(name) {
+                diagnostics.push(Diagnostic::new(
+                    Range::from(name_node.range()).into(),
+                    Some(DiagnosticSeverity::ERROR),
+                    None,
+                    None,
+                    "Duplicate definition".to_string(),
+                    Some(vec![DiagnosticRelatedInformation {
+                        location: Location::new(url.clone(), previous.into()),
+                        message: "Previously defined here".to_string(),
+                    }]),
+                    None,
+                ));
+                continue;
+            }
+            type_data.labels.insert(
+                name.to_owned(),
+                DefinitionData {
+                    range: name_node.range().into(),
+                    value: name_node.start_position().row as u8,
+                },
+            );
+        }
+    }
+}
+
+/// Warn about instructions, logic types and enum members missing from `version`.
+fn check_game_version(
+    tree: &Tree,
+    content: &str,
+    version: &GameVersion,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut warn = |node: tree_sitter::Node, name: &str, reason: String| {
+        diagnostics.push(Diagnostic::new(
+            Range::from(node.range()).into(),
+            Some(DiagnosticSeverity::WARNING),
+            Some(NumberOrString::String(LINT_GAME_VERSION.to_string())),
+            None,
+            format!(
+                "'{}' is not available on game version {}: {}",
+                name, version, reason
+            ),
+            None,
+            None,
+        ));
+    };
+
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(instruction)@a").unwrap();
+    let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
+    for (capture, _) in captures {
+        let capture = capture.captures[0].node;
+        let Some(operation_node) = capture.child_by_field_name("operation") else {
+            continue;
+        };
+        let operation = operation_node.utf8_text(content.as_bytes()).unwrap();
+        if let Some(reason) = game_versions::instruction(operation).missing_reason(version) {
+            warn(operation_node, operation, reason);
+        }
+
+        let mut tree_cursor = capture.walk();
+        for operand in capture.children_by_field_name("operand", &mut tree_cursor) {
+            let Some(value) = operand.named_child(0) else {
+                continue;
+            };
+            let text = value.utf8_text(content.as_bytes()).unwrap();
+            let availability = match value.kind() {
+                // Logic types newer than the grammar parse as identifiers
+                "logictype" => game_versions::logic_type(text),
+                "identifier" if instructions::is_logic_type(text) => {
+                    game_versions::logic_type(text)
+                }
+                "identifier" if text.contains('.') => {
+                    let Some((canonical, ..)) = instructions::enum_info_case_insensitive(text)
+                    else {
+                        continue;
+                    };
+                    game_versions::enum_member(canonical)
+                }
+                _ => continue,
+            };
+            if let Some(reason) = availability.missing_reason(version) {
+                warn(operand, text, reason);
+            }
+        }
+    }
+}
+
+fn check_types(
+    tree: &Tree,
+    content: &str,
+    type_data: &TypeData,
+    config: &Configuration,
+    diagnostics: &mut Vec<Diagnostic>,
+    register_analyzer: &RegisterAnalyzer,
+) {
+    let mut type_data = type_data.clone();
+
+    // Read config before the loop to avoid await across non-Send types
+    let suppress_hash_diagnostics = config.suppress_hash_diagnostics;
+
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(instruction)@a").unwrap();
+
+    let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
+
+    for (capture, _) in captures {
+        let capture = capture.captures[0].node;
+
+        if let Some(operation_node) = capture.child_by_field_name("operation") {
+            let operation = operation_node.utf8_text(content.as_bytes()).unwrap();
+            let Some(signature) = instructions::INSTRUCTIONS.get(operation) else {
+                diagnostics.push(Diagnostic::new(
+                    Range::from(operation_node.range()).into(),
+                    Some(DiagnosticSeverity::ERROR),
+                    None,
+                    None,
+                    format!("Invalid instruction"),
+                    None,
+                    None,
+                ));
+                continue;
+            };
+
+            let mut argument_count = 0;
+            let mut tree_cursor = capture.walk();
+            let operands = capture.children_by_field_name("operand", &mut tree_cursor);
+            let mut parameters = signature.0.iter();
+
+            let mut first_superfluous_arg = None;
+            let mut pending_define_name: Option<(String, Range)> = None;
+
+            for operand in operands {
+                argument_count = argument_count + 1;
+                let Some(parameter) = parameters.next() else {
+                    if first_superfluous_arg.is_none() {
+                        first_superfluous_arg = Some(operand);
+                    }
+                    continue;
+                };
+
+                let operand_kind = operand.named_child(0).unwrap().kind();
+                let expects_name = parameter.match_type(DataType::Name);
+                // Keep track of an underlying register name if this operand ultimately refers to a register
+                // (either directly or via alias). We'll use this to permit DeviceId registers where Device is expected.
+                let mut underlying_register: Option<String> = None;
+                let typ = match operand_kind {
+                    "register" => {
+                        // Direct register
+                        if let Some(reg_text) = operand
+                            .named_child(0)
+                            .map(|n| n.utf8_text(content.as_bytes()).unwrap_or(""))
+                        {
+                            underlying_register = Some(reg_text.to_string());
+                        }
+                        instructions::Union(&[DataType::Register])
+                    }
+                    "device_spec" => instructions::Union(&[DataType::Device]),
+                    "number" => instructions::Union(&[DataType::Number]),
+                    "logictype" => {
+                        let ident = operand
+                            .named_child(0)
+                            .unwrap()
+                            .utf8_text(content.as_bytes())
+                            .unwrap();
+                        let flags = classify_exact_keyword(ident);
+                        if flags.any() {
+                            flags.to_union()
+                        } else {
+                            instructions::Union(&[])
+                        }
+                    }
+                    "identifier" => {
+                        let ident_node = operand.named_child(0).unwrap();
+                        let ident = ident_node.utf8_text(content.as_bytes()).unwrap();
+
+                        // First operand of a DEFINE is always the define name; remember it and never treat as unknown
+                        if operation.eq_ignore_ascii_case("define") && argument_count == 1 {
+                            pending_define_name =
+                                Some((ident.to_string(), Range::from(ident_node.range()).into()));
+                            instructions::Union(&NAME_ONLY)
+                        } else
+                        // Accept fully-qualified enum names like Family.Member as numeric identifiers (case-insensitive)
+                        if expects_name {
+                            instructions::Union(&NAME_ONLY)
+                        } else if ident.contains('.') {
+                            if let Some((canonical, _val, _desc, _dep)) =
+                                instructions::enum_info_case_insensitive(ident)
+                            {
+                                if canonical != ident {
+                                    diagnostics.push(Diagnostic::new(
+                                        Range::from(operand.range()).into(),
+                                        Some(DiagnosticSeverity::WARNING),
+                                        None,
+                                        None,
+                                        format!(
+                                            "Enum '{}' differs in case from canonical '{}'.",
+                                            ident, canonical
+                                        ),
+                                        None,
+                                        None,
+                                    ));
+                                }
+                                instructions::Union(&[DataType::Number])
+                            } else if type_data.defines.contains_key(ident)
+                                || type_data.labels.contains_key(ident)
+                            {
+                                // Fully-qualified define/label; treat as numeric identifier
+                                instructions::Union(&[DataType::Number])
+                            } else if let Some((canonical, _)) = type_data
+                                .defines
+                                .keys()
+                                .find(|k| k.eq_ignore_ascii_case(ident))
+                                .map(|k| (k.clone(), ()))
+                            {
+                                if canonical != ident {
+                                    diagnostics.push(Diagnostic::new(
+                                        Range::from(operand.range()).into(),
+                                        Some(DiagnosticSeverity::WARNING),
+                                        None,
+                                        None,
+                                        format!(
+                                            "Define '{}' differs in case from canonical '{}'.",
+                                            ident, canonical
+                                        ),
+                                        None,
+                                        None,
+                                    ));
+                                }
+                                instructions::Union(&[DataType::Number])
+                            } else if let Some(type_data_val) = type_data.aliases.get(ident) {
+                                match type_data_val.value {
+                                    AliasValue::Device(_) => {
+                                        instructions::Union(&[DataType::Device])
+                                    }
+                                    AliasValue::Register(ref reg_name) => {
+                                        underlying_register = Some(reg_name.clone());
+                                        instructions::Union(&[DataType::Register])
+                                    }
+                                }
+                            } else {
+                                // fall through to case-insensitive logic checks below
+                                instructions::Union(&[])
+                            }
+                        }
+                        // Prefer user-defined identifiers (defines/labels/aliases) over reserved keywords
+                        else if type_data.defines.contains_key(ident)
+                            || type_data.labels.contains_key(ident)
+                        {
+                            // User-defined identifier (define/label) always resolves; value may be HASH(...) or number
+                            instructions::Union(&[DataType::Number])
+                        } else if let Some((canonical, _)) = type_data
+                            .defines
+                            .keys()
+                            .find(|k| k.eq_ignore_ascii_case(ident))
+                            .map(|k| (k.clone(), ()))
+                        {
+                            if canonical != ident {
+                                diagnostics.push(Diagnostic::new(
+                                    Range::from(operand.range()).into(),
+                                    Some(DiagnosticSeverity::WARNING),
+                                    None,
+                                    None,
+                                    format!(
+                                        "Define '{}' differs in case from canonical '{}'.",
+                                        ident, canonical
+                                    ),
+                                    None,
+                                    None,
+                                ));
+                            }
+                            instructions::Union(&[DataType::Number])
+                        } else if let Some(type_data_val) = type_data.aliases.get(ident) {
+                            match type_data_val.value {
+                                AliasValue::Device(_) => instructions::Union(&[DataType::Device]),
+                                AliasValue::Register(ref reg_name) => {
+                                    // Alias points at a register; remember for DeviceId substitution
+                                    underlying_register = Some(reg_name.clone());
+                                    instructions::Union(&[DataType::Register])
+                                }
+                            }
+                        } else {
+                            let exact_flags = classify_exact_keyword(ident);
+                            if exact_flags.any() {
+                                exact_flags.to_union()
+                            } else {
+                                let ci_flags = classify_ci_keyword(ident);
+                                if ci_flags.any() {
+                                    diagnostics.push(Diagnostic::new(
+                                        Range::from(operand.range()).into(),
+                                        Some(DiagnosticSeverity::WARNING),
+                                        None,
+                                        None,
+                                        format!("Identifier '{}' matches a known logic/parameter type by name but differs by case. Consider using proper case or renaming your identifier.", ident),
+                                        None,
+                                        None,
+                                    ));
+                                    ci_flags.to_union()
+                                } else {
+                                    diagnostics.push(Diagnostic::new(
+                                        Range::from(operand.range()).into(),
+                                        Some(DiagnosticSeverity::ERROR),
+                                        None,
+                                        None,
+                                        format!("Unknown identifier"),
+                                        None,
+                                        None,
+                                    ));
+                                    continue;
+                                }
+                            }
+                        }
+                    }
+                    "function_call" | "hash_preproc" => {
+                        // Treat HASH("...") and similar constant-producing functions as numbers
+                        let call_text = operand.utf8_text(content.as_bytes()).unwrap();
+                        if is_hash_function_call(call_text) {
+                            // Optional: if known device name, we can warn on case differences or unknown names
+                            if let Some(name) = extract_hash_argument(call_text) {
+                                if let Some(_) = get_device_hash(name.as_str()) {
+                                    // Known device name; optionally could inlay the numeric value
+                                } else {
+                                    // Unknown device string; still treat as number but nudge (unless suppressed)
+                                    if !suppress_hash_diagnostics {
+                                        diagnostics.push(Diagnostic::new(
+                                                Range::from(operand.range()).into(),
+                                                Some(DiagnosticSeverity::INFORMATION),
+                                                None,
+                                                None,
+                                                format!("Unrecognized device name '{}' in HASH(...). Will be treated as number.", name),
+                                                None,
+                                                None,
+                                            ));
+                                    }
+                                }
+                            }
+                            instructions::Union(&[DataType::Number])
+                        } else {
+                            // Unknown function: conservatively treat as number to avoid spurious errors
+                            instructions::Union(&[DataType::Number])
+                        }
+                    }
+                    _ => {
+                        continue;
+                    }
+                };
+                // Special case: register (direct or via alias) holding DeviceId or Unknown can satisfy a Device parameter
+                // Special case: register holding LogicType or Unknown can satisfy a LogicType parameter
+                let mut effective_typ = typ;
+                if parameter.match_type(DataType::Device) {
+                    if let Some(reg_name) = underlying_register.as_ref() {
+                        let kind = register_analyzer.get_register_kind(reg_name);
+                        if kind == register_analysis::ValueKind::DeviceId
+                            || kind == register_analysis::ValueKind::Unknown
+                        {
+                            effective_typ = instructions::Union(&[DataType::Device]);
+                        }
+                    }
+                } else if parameter.match_type(DataType::LogicType)
+                    || parameter.match_type(DataType::SlotLogicType)
+                {
+                    if let Some(reg_name) = underlying_register.as_ref() {
+                        let kind = register_analyzer.get_register_kind(reg_name);
+                        // LogicTypes are numeric constants, so Number/LogicType/Unknown can all satisfy LogicType parameters
+                        if kind == register_analysis::ValueKind::LogicType
+                            || kind == register_analysis::ValueKind::Number
+                            || kind == register_analysis::ValueKind::Unknown
+                        {
+                            // Register holds a numeric/LogicType value, so it can be used where LogicType is expected
+                            if parameter.match_type(DataType::LogicType) {
+                                effective_typ = instructions::Union(&[DataType::LogicType]);
+                            } else {
+                                effective_typ = instructions::Union(&[DataType::SlotLogicType]);
+                            }
+                        }
+                    }
+                }
+                // Allow define name second operand to be register when signature expects Number|Register already (adjusted in INSTRUCTIONS)
+                if !parameter.match_union(&effective_typ) {
+                    diagnostics.push(Diagnostic::new(
+                        Range::from(operand.range()).into(),
+                        Some(DiagnosticSeverity::ERROR),
+                        None,
+                        None,
+                        format!(
+                            "Type mismatch. Found {}, expected {}",
+                            effective_typ, parameter
+                        ),
+                        None,
+                        None,
+                    ));
+                }
+
+                // After processing the second operand of DEFINE, store it in the working define map
+                if operation.eq_ignore_ascii_case("define") && argument_count == 2 {
+                    if let Some((define_name, define_range)) = pending_define_name.clone() {
+                        let value_text = operand
+                            .utf8_text(content.as_bytes())
+                            .unwrap()
+                            .trim()
+                            .to_string();
+                        type_data.defines.insert(
+                            define_name,
+                            DefinitionData::new(define_range, value_text.into()),
+                        );
+                    }
+                }
+            }
+            if argument_count > signature.0.len() {
+                let plural_str = if argument_count - signature.0.len() > 1 {
+                    "s"
+                } else {
+                    ""
+                };
+
+                diagnostics.push(Diagnostic::new(
+                    tower_lsp::lsp_types::Range::new(
+                        Position::from(first_superfluous_arg.unwrap().start_position()).into(),
+                        Position::from(capture.end_position()).into(),
+                    ),
+                    Some(DiagnosticSeverity::ERROR),
+                    None,
+                    None,
+                    format!(
+                        "Superfluous argument{}. '{}' only requires {} arguments.",
+                        plural_str,
+                        operation,
+                        signature.0.len()
+                    ),
+                    None,
+                    None,
+                ));
+                continue;
+            }
+            if argument_count != signature.0.len() {
+                diagnostics.push(Diagnostic::new(
+                    Range::from(capture.range()).into(),
+                    Some(DiagnosticSeverity::ERROR),
+                    None,
+                    None,
+                    "Invalid number of arguments".to_string(),
+                    None,
+                    None,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn configured_limits_and_lints_apply_to_text() {
+        let source = "define Limit 5\ndefine Limit 6\nmove r0 Limit\nj 0\n";
+        let mut config = Configuration::default();
+        config.update(&json!({ "max_lines": 3, "warnings": { "overline_comment": false } }));
+        let url = Url::parse("file:///limits.ic10").unwrap();
+
+        let messages: Vec<_> = check_text(&url, source, &config)
+            .into_iter()
+            .map(|x| (x.range.start.line, x.message))
+            .collect();
+        for expected in [
+            (1, "Duplicate definition"),
+            (3, "Instruction past line 3"),
+            (3, "Absolute jump to line number"),
+        ] {
+            assert!(
+                messages.iter().any(|x| (x.0, x.1.as_str()) == expected),
+                "missing {:?} in {:?}",
+                expected,
+                messages
+            );
+        }
+    }
+
+    #[test]
+    fn target_game_version_flags_newer_instructions() {
+        let source = "pow r0 2 8\nl r1 d0 Apex\nmove r2 LogicType.Apex\nsbs -1 0 Occupied 1\n";
+        let url = Url::parse("file:///versions.ic10").unwrap();
+        let version_warnings = |config: &Configuration| {
+            check_text(&url, source, config)
+                .into_iter()
+                .filter(|x| x.code == Some(NumberOrString::String(LINT_GAME_VERSION.to_string())))
+                .map(|x| (x.range.start.line, x.message))
+                .collect::<Vec<_>>()
+        };
+
+        let mut config = Configuration::default();
+        assert!(version_warnings(&config).is_empty());
+
+        // Older than every version in data/GameVersions.json
+        config.update(&json!({ "gameVersion": "0.1" }));
+        let version = GameVersion::parse("0.1").unwrap();
+        let warning = |line: u32, name: &str, availability: game_versions::Availability| {
+            let reason = availability.missing_reason(&version).unwrap();
+            let message = format!(
+                "'{}' is not available on game version 0.1: {}",
+                name, reason
+            );
+            (line, message)
+        };
+        assert_eq!(
+            version_warnings(&config),
+            vec![
+                warning(0, "pow", game_versions::instruction("pow")),
+                warning(1, "Apex", game_versions::logic_type("Apex")),
+                warning(
+                    2,
+                    "LogicType.Apex",
+                    game_versions::enum_member("LogicType.Apex")
+                ),
+                warning(3, "sbs", game_versions::instruction("sbs")),
+            ]
+        );
+
+        config.update(&json!({ "gameVersion": "" }));
+        assert!(version_warnings(&config).is_empty());
+    }
+
+    #[test]
+    fn included_files_are_checked_with_the_script() {
+        let dir = std::env::temp_dir().join(format!("ic10-check-script-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("pid.ic10"), "pid:\nadd r0 r0 1\nj ra\n").unwrap();
+        let source = "# @include \"pid.ic10\"\nstart:\njal pid\nj start\n";
+        let url = Url::parse("file:///main.ic10").unwrap();
+        let config = Configuration::default();
+
+        let unknown = |diagnostics: Vec<Diagnostic>| {
+            diagnostics
+                .iter()
+                .any(|x| x.message == "Unknown identifier")
+        };
+        assert!(!unknown(check_script(&url, source, Some(&dir), &config)));
+        // Without the include the label is unknown
+        assert!(unknown(check_text(&url, source, &config)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
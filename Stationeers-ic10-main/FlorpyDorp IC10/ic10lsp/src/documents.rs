@@ -0,0 +1,134 @@
+//! Storage for open documents.
+//!
+//! Each document sits behind its own lock, so a long diagnostics run on one file never blocks
+//! hovers or completions on another, and the store-wide lock is only held to look a document
+//! up. Every edit bumps the document's revision; a diagnostics run started for an older
+//! revision is aborted, or at least never published.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::Url;
+
+use crate::FileData;
+
+pub struct Document {
+    pub data: RwLock<FileData>,
+    revision: AtomicU64,
+    pending_diagnostics: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Document {
+    fn new(data: FileData) -> Self {
+        Document {
+            data: RwLock::new(data),
+            revision: AtomicU64::new(0),
+            pending_diagnostics: Mutex::new(None),
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    /// Record an edit, returning the new revision.
+    pub fn bump_revision(&self) -> u64 {
+        self.revision.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Make `task` the pending diagnostics run, aborting the one it replaces.
+    pub fn replace_pending_diagnostics(&self, task: JoinHandle<()>) {
+        let mut pending = self.pending_diagnostics.lock().unwrap();
+        if let Some(previous) = pending.replace(task) {
+            previous.abort();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DocumentStore {
+    documents: RwLock<HashMap<Url, Arc<Document>>>,
+}
+
+impl DocumentStore {
+    pub async fn get(&self, uri: &Url) -> Option<Arc<Document>> {
+        self.documents.read().await.get(uri).cloned()
+    }
+
+    pub async fn contains(&self, uri: &Url) -> bool {
+        self.documents.read().await.contains_key(uri)
+    }
+
+    pub async fn uris(&self) -> Vec<Url> {
+        self.documents.read().await.keys().cloned().collect()
+    }
+
+    /// The document at `uri`, created from `create` if it is not open yet. The flag tells
+    /// whether it was created.
+    pub async fn get_or_insert_with(
+        &self,
+        uri: &Url,
+        create: impl FnOnce() -> FileData,
+    ) -> (Arc<Document>, bool) {
+        if let Some(document) = self.get(uri).await {
+            return (document, false);
+        }
+        let mut documents = self.documents.write().await;
+        match documents.get(uri) {
+            Some(document) => (document.clone(), false),
+            None => {
+                let document = Arc::new(Document::new(create()));
+                documents.insert(uri.clone(), document.clone());
+                (document, true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn empty_file() -> FileData {
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        FileData {
+            document_data: crate::DocumentData {
+                url: Url::parse("file:///base.ic10").unwrap(),
+                content: String::new(),
+                synthetic_newline: false,
+                tree: None,
+                parser,
+            },
+            type_data: crate::TypeData::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replacing_pending_diagnostics_aborts_the_previous_run() {
+        let document = Document::new(empty_file());
+        let first = tokio::spawn(tokio::time::sleep(Duration::from_secs(60)));
+        let abort_handle = first.abort_handle();
+        document.replace_pending_diagnostics(first);
+        document.replace_pending_diagnostics(tokio::spawn(async {}));
+        tokio::task::yield_now().await;
+        assert!(abort_handle.is_finished());
+    }
+
+    #[tokio::test]
+    async fn store_keeps_one_document_per_uri() {
+        let store = DocumentStore::default();
+        let uri = Url::parse("file:///base.ic10").unwrap();
+        let (first, created) = store.get_or_insert_with(&uri, empty_file).await;
+        let (second, created_again) = store.get_or_insert_with(&uri, empty_file).await;
+        assert!(created && !created_again);
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let revision = first.bump_revision();
+        assert_eq!(second.revision(), revision);
+    }
+}
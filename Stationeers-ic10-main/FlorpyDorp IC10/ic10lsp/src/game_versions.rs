@@ -0,0 +1,178 @@
+//! # Game Version Profiles
+//!
+//! Dedicated servers do not always run the current release, and a script using an instruction
+//! or logic type the server's build does not know fails only once it is loaded in game. The
+//! version tables record the game version each of them was added in or removed from, so the
+//! diagnostics can warn about it when a target version is configured.
+//!
+//! The shipped tables live in `data/GameVersions.json` and are built into the server. Anything
+//! missing from them is taken to exist on every version. Members of the `LogicType` enum share the
+//! availability of the logic type of the same name. A data pack's `GameVersions.json` has the
+//! same layout and adds entries or overrides shipped ones, so a wrong version is corrected
+//! without rebuilding the server:
+//!
+//! ```json
+//! {
+//!   "instructions": { "pow": { "added": "0.2.5228" } },
+//!   "logicTypes": { "Stress": { "added": "0.2.4475", "removed": "0.2.6000" } },
+//!   "enums": { "RocketMode.Chart": { "added": "0.2.4475" } }
+//! }
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::data_pack::{self, DataPack};
+
+/// A game version such as `0.2.5228.24247`. Versions compare component by component, and a
+/// shorter version is older than any longer one it is a prefix of, so `0.2.5228` stands for
+/// the first build of that version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameVersion(Vec<u32>);
+
+impl GameVersion {
+    pub fn parse(text: &str) -> Option<Self> {
+        let parts = text
+            .trim()
+            .trim_start_matches(['v', 'V'])
+            .split('.')
+            .map(|x| x.parse().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        Some(GameVersion(parts))
+    }
+}
+
+impl PartialOrd for GameVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for GameVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl fmt::Display for GameVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u32::to_string).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+/// Versions an instruction, logic type or enum member was added in and removed from.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Availability {
+    pub added: Option<&'static str>,
+    pub removed: Option<&'static str>,
+}
+
+impl Availability {
+    /// `None` if present on `version`, otherwise why not, e.g. `added in 0.2.5228`.
+    pub fn missing_reason(&self, version: &GameVersion) -> Option<String> {
+        let parse = |x: Option<&str>| x.and_then(GameVersion::parse);
+        if let Some(added) = parse(self.added) {
+            if *version < added {
+                return Some(format!("added in {}", added));
+            }
+        }
+        if let Some(removed) = parse(self.removed) {
+            if *version >= removed {
+                return Some(format!("removed in {}", removed));
+            }
+        }
+        None
+    }
+}
+
+/// The tables shipped in `data/GameVersions.json`.
+fn shipped() -> &'static DataPack {
+    static SHIPPED: OnceLock<DataPack> = OnceLock::new();
+    SHIPPED.get_or_init(|| {
+        let json = serde_json::from_str(include_str!("../../../../data/GameVersions.json"))
+            .expect("data/GameVersions.json is valid JSON");
+        DataPack::from_versions(&json)
+    })
+}
+
+pub fn instruction(name: &str) -> Availability {
+    data_pack::active()
+        .and_then(|x| x.instruction_availability(name))
+        .or_else(|| shipped().instruction_availability(name))
+        .unwrap_or_default()
+}
+
+pub fn logic_type(name: &str) -> Availability {
+    data_pack::active()
+        .and_then(|x| x.logic_type_availability(name))
+        .or_else(|| shipped().logic_type_availability(name))
+        .unwrap_or_default()
+}
+
+/// Availability of an enum member by qualified name, e.g. `LogicType.Apex`.
+pub fn enum_member(qualified: &str) -> Availability {
+    if let Some(availability) = data_pack::active()
+        .and_then(|x| x.enum_availability(qualified))
+        .or_else(|| shipped().enum_availability(qualified))
+    {
+        return availability;
+    }
+    match qualified.split_once('.') {
+        Some(("LogicType", member)) => logic_type(member),
+        _ => Availability::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn availability_is_checked_against_the_target_version() {
+        let version = |x| GameVersion::parse(x).unwrap();
+        assert!(version("0.2.4809.23571") > version("0.2.4809"));
+        assert!(version("0.2.10000") > version("0.2.5228.24247"));
+        assert_eq!(version("v0.2.5228").to_string(), "0.2.5228");
+        assert_eq!(GameVersion::parse("latest"), None);
+
+        let pack = DataPack::from_versions(&serde_json::json!({
+            "instructions": { "pow": { "added": "0.2.5228" } }
+        }));
+        let pow = pack.instruction_availability("pow").unwrap();
+        let old = version("0.2.4000.20000");
+        assert_eq!(
+            pow.missing_reason(&old).as_deref(),
+            Some("added in 0.2.5228")
+        );
+        assert_eq!(pow.missing_reason(&version("0.2.5228")), None);
+        assert_eq!(instruction("add").missing_reason(&old), None);
+
+        let retired = Availability {
+            added: None,
+            removed: Some("0.2.4809"),
+        };
+        assert_eq!(
+            retired.missing_reason(&version("0.2.4809.1")).as_deref(),
+            Some("removed in 0.2.4809")
+        );
+        assert_eq!(retired.missing_reason(&old), None);
+    }
+
+    #[test]
+    fn shipped_versions_parse() {
+        let json: serde_json::Value =
+            serde_json::from_str(include_str!("../../../../data/GameVersions.json")).unwrap();
+        for section in ["instructions", "logicTypes", "enums"] {
+            for (name, entry) in json[section].as_object().unwrap() {
+                for key in ["added", "removed"] {
+                    if let Some(text) = entry.get(key) {
+                        let text = text.as_str().unwrap();
+                        assert!(GameVersion::parse(text).is_some(), "{name}: {text}");
+                    }
+                }
+            }
+        }
+    }
+}
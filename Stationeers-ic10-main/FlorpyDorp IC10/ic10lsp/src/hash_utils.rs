@@ -1,3 +1,4 @@
+use crate::data_pack;
 use crate::device_hashes::{DEVICE_NAME_TO_HASH, HASH_TO_DISPLAY_NAME};
 use crc32fast::Hasher;
 
@@ -49,12 +50,29 @@ pub fn is_hash_function_call(input: &str) -> bool {
 
 /// Looks up device name in device registry and returns the corresponding hash
 pub fn get_device_hash(device_name: &str) -> Option<i32> {
-    DEVICE_NAME_TO_HASH.get(device_name).copied()
+    data_pack::active()
+        .and_then(|x| x.prefab_hash(device_name))
+        .or_else(|| DEVICE_NAME_TO_HASH.get(device_name).copied())
 }
 
 /// Gets device name for a given hash value from the registry
 pub fn get_device_name_for_hash(hash_value: i32) -> Option<&'static str> {
-    HASH_TO_DISPLAY_NAME.get(&hash_value).copied()
+    data_pack::active()
+        .and_then(|x| x.display_name(hash_value))
+        .or_else(|| HASH_TO_DISPLAY_NAME.get(&hash_value).copied())
+}
+
+/// Every known device prefab name with its hash, data pack entries first.
+pub fn all_devices() -> Vec<(&'static str, i32)> {
+    let pack = data_pack::active();
+    let mut ret: Vec<(&'static str, i32)> = pack.into_iter().flat_map(|x| x.prefabs()).collect();
+    ret.extend(
+        DEVICE_NAME_TO_HASH
+            .entries()
+            .filter(|(name, _)| pack.is_none_or(|x| x.prefab_hash(name).is_none()))
+            .map(|(name, hash)| (*name, *hash)),
+    );
+    ret
 }
 
 #[cfg(test)]
@@ -0,0 +1,149 @@
+//! Inlay hints showing what an operand resolves to.
+//!
+//! Label references show the line they jump to, defines and qualified enums (`LogicType.Power`)
+//! their numeric value, and relative branches the absolute line they land on.
+
+use tower_lsp::lsp_types::{InlayHint, InlayHintLabel, Range as LspRange};
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::instructions::enum_info_case_insensitive;
+use crate::jump_labels::jump_kind;
+use crate::references::is_declaration_name;
+use crate::{Position, TypeData};
+
+/// Value hints for the instructions on the lines of `range`.
+pub fn value_hints(
+    tree: &Tree,
+    content: &str,
+    type_data: &TypeData,
+    range: LspRange,
+) -> Vec<InlayHint> {
+    let mut ret = Vec::new();
+
+    let mut cursor = QueryCursor::new();
+    cursor.set_point_range(
+        tree_sitter::Point::new(range.start.line as usize, 0)
+            ..tree_sitter::Point::new(range.end.line as usize + 1, 0),
+    );
+    let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
+    for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+        let instruction = capture.captures[0].node;
+        let line = instruction.start_position().row as i64;
+        let relative_jump = instruction
+            .child_by_field_name("operation")
+            .and_then(|x| x.utf8_text(content.as_bytes()).ok())
+            .and_then(jump_kind);
+
+        let mut tree_cursor = instruction.walk();
+        let operands: Vec<_> = instruction
+            .children_by_field_name("operand", &mut tree_cursor)
+            .collect();
+        for (idx, operand) in operands.iter().enumerate() {
+            let Some(value) = operand.child(0) else {
+                continue;
+            };
+            let text = value.utf8_text(content.as_bytes()).unwrap_or("");
+            let is_target = relative_jump.is_some() && idx == operands.len() - 1;
+
+            let label = match value.kind() {
+                "identifier" if is_declaration_name(instruction, *operand, content) => None,
+                "identifier" => {
+                    if let Some(label) = type_data.labels.get(text) {
+                        Some(if is_target {
+                            format!("→ line {}", label.value)
+                        } else {
+                            format!("= {}", label.value)
+                        })
+                    } else if let Some(define) = type_data.defines.get(text) {
+                        Some(match define.value.resolved_numeric() {
+                            Some(number) => format!("= {}", number),
+                            None => format!("= {}", define.value),
+                        })
+                    } else if text.contains('.') {
+                        enum_info_case_insensitive(text).map(|x| format!("= {}", x.1))
+                    } else {
+                        None
+                    }
+                }
+                "number" if is_target && relative_jump == Some(true) => text
+                    .parse::<i64>()
+                    .ok()
+                    .map(|offset| format!("→ line {}", line + offset)),
+                _ => None,
+            };
+            if let Some(label) = label {
+                ret.push(hint(value, label));
+            }
+        }
+    }
+
+    ret
+}
+
+fn hint(node: Node, label: String) -> InlayHint {
+    InlayHint {
+        position: Position::from(node.end_position()).into(),
+        label: InlayHintLabel::String(label),
+        kind: None,
+        text_edits: None,
+        tooltip: None,
+        padding_left: Some(true),
+        padding_right: None,
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DefinitionData, Range};
+    use tower_lsp::lsp_types::Position as LspPosition;
+    use tree_sitter::Parser;
+
+    #[test]
+    fn hints_resolve_labels_defines_enums_and_relative_jumps() {
+        let source = "define Furnace HASH(\"StructureFurnace\")\ndefine Limit 5\nstart:\nmove r0 LogicType.Power\nbrgt r0 Limit -1\nsb Furnace On r0\nj start\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+
+        let at = |line: u32| {
+            Range(LspRange::new(
+                LspPosition::new(line, 0),
+                LspPosition::new(line, 1),
+            ))
+        };
+        let mut type_data = TypeData::default();
+        type_data.defines.insert(
+            "Furnace".to_string(),
+            DefinitionData::new(at(0), "HASH(\"StructureFurnace\")".to_string().into()),
+        );
+        type_data.defines.insert(
+            "Limit".to_string(),
+            DefinitionData::new(at(1), "5".to_string().into()),
+        );
+        type_data
+            .labels
+            .insert("start".to_string(), DefinitionData::new(at(2), 2));
+
+        let range = LspRange::new(LspPosition::new(0, 0), LspPosition::new(7, 0));
+        let labels: Vec<_> = value_hints(&tree, source, &type_data, range)
+            .into_iter()
+            .map(|x| match x.label {
+                InlayHintLabel::String(label) => (x.position.line, label),
+                _ => unreachable!(),
+            })
+            .collect();
+        let furnace = crate::hash_utils::compute_crc32("StructureFurnace");
+        assert_eq!(
+            labels,
+            vec![
+                (3, "= 1".to_string()),
+                (4, "= 5".to_string()),
+                (4, "→ line 3".to_string()),
+                (5, format!("= {}", furnace)),
+                (6, "→ line 2".to_string()),
+            ]
+        );
+    }
+}
@@ -2,6 +2,8 @@ use std::fmt::Display;
 
 use phf::{phf_map, phf_set};
 
+use crate::data_pack;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DataType {
     Number,
@@ -929,8 +931,12 @@ pub use _stationpedia_generated::*;
 include!(concat!(env!("OUT_DIR"), "/enums_generated.rs"));
 
 /// Returns (value, description, deprecated) for a fully qualified enum name such as
-/// "LogicType.Power" or "PrinterInstruction.Print" if present.
+/// "LogicType.Power" or "PrinterInstruction.Print" if present. An active data pack takes
+/// precedence over the built-in table.
 pub fn enum_info(qname: &str) -> Option<(i32, &'static str, bool)> {
+    if let Some(entry) = data_pack::active().and_then(|x| x.enum_entry(qname)) {
+        return Some((entry.value, entry.description, entry.deprecated));
+    }
     let value = ENUM_VALUE_BY_NAME.get(qname).cloned()?;
     let desc = ENUM_DESC_BY_NAME.get(qname).cloned().unwrap_or("");
     let deprecated = ENUM_DEPRECATED.contains(qname);
@@ -941,6 +947,18 @@ pub fn enum_info(qname: &str) -> Option<(i32, &'static str, bool)> {
 /// If the provided `qname` differs in case from the canonical enum name, the
 /// returned tuple's first element will be the canonical name.
 pub fn enum_info_case_insensitive(qname: &str) -> Option<(&'static str, i32, &'static str, bool)> {
+    let pack = data_pack::active();
+    let from_pack = |entry: data_pack::EnumEntry| {
+        (
+            entry.qualified,
+            entry.value,
+            entry.description,
+            entry.deprecated,
+        )
+    };
+    if let Some(entry) = pack.and_then(|x| x.enum_entry(qname)) {
+        return Some(from_pack(entry));
+    }
     // Exact-case fast path: locate the canonical static key matching qname
     if let Some(val) = ENUM_VALUE_BY_NAME.get(qname) {
         for (qualified, v) in ENUM_VALUE_BY_NAME.entries() {
@@ -951,6 +969,9 @@ pub fn enum_info_case_insensitive(qname: &str) -> Option<(&'static str, i32, &'s
             }
         }
     }
+    if let Some(entry) = pack.and_then(|x| x.enum_entry_case_insensitive(qname)) {
+        return Some(from_pack(entry));
+    }
     let lower = qname.to_ascii_lowercase();
     for (qualified, value) in ENUM_VALUE_BY_NAME.entries() {
         if qualified.to_ascii_lowercase() == lower {
@@ -964,12 +985,26 @@ pub fn enum_info_case_insensitive(qname: &str) -> Option<(&'static str, i32, &'s
 
 /// Convenience: logic type simple name to numeric value if present.
 pub fn logic_type_value(name: &str) -> Option<i32> {
+    if let Some(entry) =
+        data_pack::active().and_then(|x| x.enum_entry(&format!("LogicType.{}", name)))
+    {
+        return Some(entry.value);
+    }
     LOGIC_TYPE_NAME_TO_VALUE.get(name).cloned()
 }
 
 /// Convenience: logic type numeric value to simple name if present (derived by scanning name->value map).
 #[allow(dead_code)]
 pub fn logic_type_name(value: i32) -> Option<&'static str> {
+    if let Some(pack) = data_pack::active() {
+        for entry in pack.enum_entries() {
+            if entry.value == value {
+                if let Some(name) = entry.qualified.strip_prefix("LogicType.") {
+                    return Some(name);
+                }
+            }
+        }
+    }
     for (name, v) in LOGIC_TYPE_NAME_TO_VALUE.entries() {
         if *v == value {
             return Some(*name);
@@ -978,9 +1013,45 @@ pub fn logic_type_name(value: i32) -> Option<&'static str> {
     None
 }
 
+/// Whether `name` is a logic type, built in or added by the active data pack.
+pub fn is_logic_type(name: &str) -> bool {
+    LOGIC_TYPES.contains(name)
+        || data_pack::active()
+            .is_some_and(|x| x.enum_entry(&format!("LogicType.{}", name)).is_some())
+}
+
+/// Whether `name` is a slot logic type, built in or added by the active data pack.
+pub fn is_slot_logic_type(name: &str) -> bool {
+    SLOT_LOGIC_TYPES.contains(name)
+        || data_pack::active()
+            .is_some_and(|x| x.enum_entry(&format!("LogicSlotType.{}", name)).is_some())
+}
+
+/// Whether `name` is a logic type in any letter case, built in or added by the active data pack.
+pub fn is_logic_type_case_insensitive(name: &str) -> bool {
+    LOGIC_TYPES.iter().any(|x| x.eq_ignore_ascii_case(name))
+        || data_pack::active().is_some_and(|x| {
+            x.enum_entry_case_insensitive(&format!("LogicType.{}", name))
+                .is_some()
+        })
+}
+
+/// Whether `name` is a slot logic type in any letter case, built in or added by the active data
+/// pack.
+pub fn is_slot_logic_type_case_insensitive(name: &str) -> bool {
+    SLOT_LOGIC_TYPES
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(name))
+        || data_pack::active().is_some_and(|x| {
+            x.enum_entry_case_insensitive(&format!("LogicSlotType.{}", name))
+                .is_some()
+        })
+}
+
 /// Return iterator of (family, member_simple, qualified, value, description, deprecated)
 /// derived from Enums.json for completion generation. Families are the text before the first '.'
-/// in the qualified name (e.g. TraderInstruction, PrinterInstruction, LogicType).
+/// in the qualified name (e.g. TraderInstruction, PrinterInstruction, LogicType). Members of an
+/// active data pack replace built-in members of the same name.
 pub fn all_enum_entries() -> impl Iterator<
     Item = (
         &'static str,
@@ -991,12 +1062,35 @@ pub fn all_enum_entries() -> impl Iterator<
         bool,
     ),
 > {
-    ENUM_VALUE_BY_NAME.entries().map(|(qualified, value)| {
-        let deprecated = ENUM_DEPRECATED.contains(qualified);
-        let desc = ENUM_DESC_BY_NAME.get(qualified).copied().unwrap_or("");
-        let (family, member) = qualified.split_once('.').unwrap_or((qualified, ""));
-        (family, member, *qualified, *value, desc, deprecated)
-    })
+    let pack = data_pack::active();
+    let split = |qualified: &'static str| qualified.split_once('.').unwrap_or((qualified, ""));
+    let mut entries: Vec<_> = pack
+        .into_iter()
+        .flat_map(|x| x.enum_entries())
+        .map(|entry| {
+            let (family, member) = split(entry.qualified);
+            (
+                family,
+                member,
+                entry.qualified,
+                entry.value,
+                entry.description,
+                entry.deprecated,
+            )
+        })
+        .collect();
+    entries.extend(
+        ENUM_VALUE_BY_NAME
+            .entries()
+            .filter(|(qualified, _)| pack.is_none_or(|x| x.enum_entry(qualified).is_none()))
+            .map(|(qualified, value)| {
+                let deprecated = ENUM_DEPRECATED.contains(qualified);
+                let desc = ENUM_DESC_BY_NAME.get(qualified).copied().unwrap_or("");
+                let (family, member) = split(qualified);
+                (family, member, *qualified, *value, desc, deprecated)
+            }),
+    );
+    entries.into_iter()
 }
 
 #[cfg(test)]
@@ -0,0 +1,407 @@
+//! Rewriting of line-number jumps into label jumps.
+//!
+//! A branch whose target is a number (`j 12`, `beqz r0 4`, `brnez r0 -3`) breaks as soon as a
+//! line is inserted above its target. The conversion resolves every such target to the line it
+//! reaches, puts a label on that line (reusing a label already there), and points the branch at
+//! it. Relative branches become their absolute form (`brnez` → `bnez`, `jr` → `j`).
+//!
+//! Inserting a label shifts the lines below it, so numeric jumps that are left alone are
+//! renumbered to keep reaching the same instruction. Refactorings that add, remove or move lines
+//! use the same renumbering through [`renumber_jumps`].
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use tower_lsp::lsp_types::{Position, Range as LspRange, TextEdit};
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::instructions::INSTRUCTIONS;
+use crate::register_analysis::RegisterAnalyzer;
+use crate::{AliasValue, DefineValue, DefinitionData, NodeEx, Range};
+
+/// A branch or jump whose target operand is a number.
+#[derive(Debug, Clone)]
+pub struct NumericJump {
+    /// Line of the branch itself.
+    pub line: u32,
+    /// Line the branch reaches.
+    pub target: u32,
+    pub relative: bool,
+    operation: String,
+    operation_range: Range,
+    operand_range: Range,
+}
+
+/// Whether `operation` jumps relative to its own line, `None` if it is not a jump at all.
+pub fn jump_kind(operation: &str) -> Option<bool> {
+    if !INSTRUCTIONS.contains_key(operation) {
+        return None;
+    }
+    match operation {
+        "jr" => Some(true),
+        "j" | "jal" => Some(false),
+        _ if operation.starts_with("br") => Some(true),
+        _ if operation.starts_with('b') => Some(false),
+        _ => None,
+    }
+}
+
+/// Every jump in the document whose numeric target lands on an existing line.
+pub fn find_numeric_jumps(tree: &Tree, content: &str) -> Vec<NumericJump> {
+    let line_count = content.lines().count() as i64;
+    let mut ret = Vec::new();
+
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
+    for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+        let node = capture.captures[0].node;
+        let Some(operation_node) = node.child_by_field_name("operation") else {
+            continue;
+        };
+        let operation = operation_node.utf8_text(content.as_bytes()).unwrap();
+        let Some(relative) = jump_kind(operation) else {
+            continue;
+        };
+        let mut tree_cursor = node.walk();
+        let Some(operand) = node
+            .children_by_field_name("operand", &mut tree_cursor)
+            .last()
+            .and_then(|x| x.child(0))
+        else {
+            continue;
+        };
+        if operand.kind() != "number" {
+            continue;
+        }
+        let Ok(value) = operand
+            .utf8_text(content.as_bytes())
+            .unwrap()
+            .parse::<i64>()
+        else {
+            continue;
+        };
+
+        let line = node.start_position().row as i64;
+        let target = if relative { line + value } else { value };
+        if !(0..line_count).contains(&target) {
+            continue;
+        }
+        ret.push(NumericJump {
+            line: line as u32,
+            target: target as u32,
+            relative,
+            operation: operation.to_string(),
+            operation_range: operation_node.range().into(),
+            operand_range: operand.range().into(),
+        });
+    }
+    ret
+}
+
+/// Whether a jump reaches its target through a define (`define Loop 2` ... `j Loop`). Such a
+/// target cannot follow moved lines without changing the define for all its other uses.
+pub fn has_define_targets(
+    tree: &Tree,
+    content: &str,
+    defines: &HashMap<String, DefinitionData<DefineValue>>,
+) -> bool {
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(operand (identifier)@x)").unwrap();
+    cursor
+        .captures(&query, tree.root_node(), content.as_bytes())
+        .any(|(capture, _)| {
+            let node = capture.captures[0].node;
+            node.kind() == "identifier"
+                && jump_target_kind(node, content).is_some()
+                && node
+                    .utf8_text(content.as_bytes())
+                    .is_ok_and(|x| defines.contains_key(x))
+        })
+}
+
+/// `Some(relative)` if `node` lies in the target operand of a jump, the last operand of `j`,
+/// `jr`, `jal` and the branches.
+pub fn jump_target_kind(node: Node, content: &str) -> Option<bool> {
+    let operand = node.find_parent("operand")?;
+    let instruction = operand.parent()?;
+    let relative = instruction
+        .child_by_field_name("operation")
+        .and_then(|x| x.utf8_text(content.as_bytes()).ok())
+        .and_then(jump_kind)?;
+    let mut tree_cursor = instruction.walk();
+    let last = instruction
+        .children_by_field_name("operand", &mut tree_cursor)
+        .last()?;
+    (last.id() == operand.id()).then_some(relative)
+}
+
+/// Edits keeping `jumps` on the instructions they reach once lines move. `new_line` maps a line
+/// of the document to the line it ends up on.
+pub fn renumber_jumps(jumps: &[NumericJump], new_line: impl Fn(u32) -> u32) -> Vec<TextEdit> {
+    jumps
+        .iter()
+        .filter_map(|jump| {
+            let (old, new) = if jump.relative {
+                (
+                    jump.target as i64 - jump.line as i64,
+                    new_line(jump.target) as i64 - new_line(jump.line) as i64,
+                )
+            } else {
+                (jump.target as i64, new_line(jump.target) as i64)
+            };
+            (old != new).then(|| TextEdit::new(jump.operand_range.clone().into(), new.to_string()))
+        })
+        .collect()
+}
+
+/// Line reached by the jump whose target operand is at `position`.
+///
+/// Besides numeric targets this follows a register target (`jr r0`, `j addr`) when the register
+/// is assigned exactly once in the program, by a `move` of a constant.
+pub fn jump_target_at(
+    tree: &Tree,
+    content: &str,
+    aliases: &HashMap<String, DefinitionData<AliasValue>>,
+    position: Position,
+) -> Option<u32> {
+    let point = tree_sitter::Point::new(position.line as usize, position.character as usize);
+    let node = tree
+        .root_node()
+        .named_descendant_for_point_range(point, point)?;
+    let relative = jump_target_kind(node, content)?;
+    let operand = node.find_parent("operand")?;
+    let instruction = operand.parent()?;
+
+    let target = operand.child(0)?;
+    let text = target.utf8_text(content.as_bytes()).ok()?;
+    let value = match target.kind() {
+        "number" => text.parse::<i64>().ok()?,
+        "register" | "identifier" => register_constant(tree, content, aliases, text)?,
+        _ => return None,
+    };
+    let line = instruction.start_position().row as i64;
+    let target = if relative { line + value } else { value };
+    (0..content.lines().count() as i64)
+        .contains(&target)
+        .then_some(target as u32)
+}
+
+/// Constant held by a register (or register alias) that only a single `move` ever assigns.
+fn register_constant(
+    tree: &Tree,
+    content: &str,
+    aliases: &HashMap<String, DefinitionData<AliasValue>>,
+    name: &str,
+) -> Option<i64> {
+    let mut analyzer = RegisterAnalyzer::new();
+    analyzer.analyze_register_usage(tree, content, aliases);
+    // The analyzer may record an assignment more than once
+    let mut assignments: Vec<_> = analyzer
+        .get_register_info(name)?
+        .assignments
+        .iter()
+        .map(|x| (x.0.start.line, x.0.start.character))
+        .collect();
+    assignments.sort();
+    assignments.dedup();
+    let [(line, character)] = assignments[..] else {
+        return None;
+    };
+    let start = tree_sitter::Point::new(line as usize, character as usize);
+    let instruction = tree
+        .root_node()
+        .named_descendant_for_point_range(start, start)?
+        .find_parent("instruction")?;
+    let operation = instruction.child_by_field_name("operation")?;
+    if operation.utf8_text(content.as_bytes()).ok()? != "move" {
+        return None;
+    }
+    let mut tree_cursor = instruction.walk();
+    let value = instruction
+        .children_by_field_name("operand", &mut tree_cursor)
+        .last()?
+        .child(0)?;
+    if value.kind() != "number" {
+        return None;
+    }
+    value.utf8_text(content.as_bytes()).ok()?.parse().ok()
+}
+
+/// Edits converting the numeric jumps accepted by `convert` into label jumps. Fails when a jump
+/// reaches its line through a define, which the inserted labels would shift.
+pub fn convert_to_labels(
+    tree: &Tree,
+    content: &str,
+    defines: &HashMap<String, DefinitionData<DefineValue>>,
+    convert: impl Fn(&NumericJump) -> bool,
+) -> Result<Vec<TextEdit>, String> {
+    if has_define_targets(tree, content, defines) {
+        return Err(
+            "A jump reaches its line through a define; inserting labels would move it".to_string(),
+        );
+    }
+    let jumps = find_numeric_jumps(tree, content);
+
+    // Labels already defined, and the lines that hold one
+    let mut names = HashSet::new();
+    let mut label_lines = BTreeMap::new();
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(label (identifier)@x)").unwrap();
+    for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+        let node = capture.captures[0].node;
+        let name = node.utf8_text(content.as_bytes()).unwrap().to_string();
+        label_lines
+            .entry(node.start_position().row as u32)
+            .or_insert_with(|| name.clone());
+        names.insert(name);
+    }
+
+    // Label for every converted target, and the lines that need a new one
+    let mut targets = BTreeMap::new();
+    let mut inserted = Vec::new();
+    for jump in jumps.iter().filter(|x| convert(x)) {
+        if targets.contains_key(&jump.target) {
+            continue;
+        }
+        let name = match label_lines.get(&jump.target) {
+            Some(name) => name.clone(),
+            None => {
+                let name = (0..)
+                    .map(|n| match n {
+                        0 => format!("line{}", jump.target),
+                        n => format!("line{}_{}", jump.target, n),
+                    })
+                    .find(|x| !names.contains(x))
+                    .unwrap();
+                names.insert(name.clone());
+                inserted.push(jump.target);
+                name
+            }
+        };
+        targets.insert(jump.target, name);
+    }
+
+    // Line an original line ends up on once the new labels are in
+    let shifted = |line: u32| line + inserted.iter().filter(|x| **x <= line).count() as u32;
+
+    let mut edits: Vec<TextEdit> = inserted
+        .iter()
+        .map(|line| {
+            TextEdit::new(
+                LspRange::new(Position::new(*line, 0), Position::new(*line, 0)),
+                format!("{}:\n", targets[line]),
+            )
+        })
+        .collect();
+
+    let (converted, kept): (Vec<_>, Vec<_>) = jumps.into_iter().partition(|x| convert(x));
+    for jump in &converted {
+        if jump.relative {
+            let absolute = match jump.operation.as_str() {
+                "jr" => "j".to_string(),
+                x => format!("b{}", &x[2..]),
+            };
+            edits.push(TextEdit::new(jump.operation_range.clone().into(), absolute));
+        }
+        edits.push(TextEdit::new(
+            jump.operand_range.clone().into(),
+            targets[&jump.target].clone(),
+        ));
+    }
+    edits.extend(renumber_jumps(&kept, shifted));
+
+    Ok(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    fn apply(source: &str, edits: &[TextEdit]) -> String {
+        let mut lines: Vec<String> = source.lines().map(str::to_string).collect();
+        let mut edits = edits.to_vec();
+        // Apply bottom-up so earlier positions stay valid; inserts go last on their line
+        edits.sort_by_key(|x| {
+            (
+                std::cmp::Reverse(x.range.start.line),
+                x.range.start == x.range.end,
+                std::cmp::Reverse(x.range.start.character),
+            )
+        });
+        for edit in edits {
+            let line = edit.range.start.line as usize;
+            let (start, end) = (
+                edit.range.start.character as usize,
+                edit.range.end.character as usize,
+            );
+            let mut text = lines[line].clone();
+            text.replace_range(start..end, &edit.new_text);
+            let split: Vec<String> = text.split('\n').map(str::to_string).collect();
+            lines.splice(line..=line, split);
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn convert(source: &str, only_line: Option<u32>) -> String {
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let edits = convert_to_labels(&tree, source, &HashMap::new(), |x| {
+            only_line.is_none_or(|l| x.line == l)
+        })
+        .unwrap();
+        apply(source, &edits)
+    }
+
+    #[test]
+    fn jump_targets_resolve_numbers_and_constant_registers() {
+        let source = "move r0 1\nyield\nbrgt r1 0 -1\nj 1\njr r0\nj r1\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let target = |line, character| {
+            jump_target_at(
+                &tree,
+                source,
+                &HashMap::new(),
+                Position::new(line, character),
+            )
+        };
+        assert_eq!(target(2, 11), Some(1));
+        assert_eq!(target(3, 2), Some(1));
+        assert_eq!(target(4, 3), Some(5));
+        // r1 is never assigned a constant, and `brgt`'s first operands are not targets
+        assert_eq!(target(5, 2), None);
+        assert_eq!(target(2, 5), None);
+    }
+
+    #[test]
+    fn converts_absolute_and_relative_jumps() {
+        let source = "move r0 0\nadd r0 r0 1\nbrlt r0 10 -1\nstart:\nyield\nj 3\n";
+        assert_eq!(
+            convert(source, None),
+            "move r0 0\nline1:\nadd r0 r0 1\nblt r0 10 line1\nstart:\nyield\nj start\n"
+        );
+    }
+
+    #[test]
+    fn single_conversion_renumbers_other_jumps() {
+        let source = "yield\nl r0 d0 On\nbeqz r0 0\nbrnez r0 -2\nj 1\n";
+        assert_eq!(
+            convert(source, Some(4)),
+            "yield\nline1:\nl r0 d0 On\nbeqz r0 0\nbrnez r0 -2\nj line1\n"
+        );
+    }
+    #[test]
+    fn define_targets_refuse_conversion() {
+        let source = "define Top 1\nyield\nj Top\nj 1\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let defines = HashMap::from([(
+            "Top".to_string(),
+            DefinitionData::new(Range(LspRange::default()), "1".to_string().into()),
+        )]);
+        assert!(convert_to_labels(&tree, source, &defines, |_| true).is_err());
+    }
+}
@@ -1,10 +1,29 @@
 //! # IC10 Instruction Library
 //!
 //! This module provides the core instruction definitions and metadata for the IC10
-//! language. It serves as the foundation for language server features like code
-//! completion, validation, and hover documentation.
+//! language, and the analysis built on them. It serves as the foundation for language
+//! server features like code completion, validation, and hover documentation, and for
+//! the `--diagnose` command line checks.
 //!
 //! ## Modules
 //! - `instructions` - All IC10 instruction definitions, signatures, and documentation
+//! - `data_pack` - Game data loaded at runtime and merged over the built-in tables
+//! - `game_versions` - Game versions instructions and logic types were added in or removed from
+//! - `device_hashes` - Device hash mappings and resolution (HASH() function support)
+//! - `hash_utils` - Utility functions for hash computation and parsing
+//! - `types` - Ranges, definitions and type data collected from a script
+//! - `config` - Settings the diagnostics are computed with
+//! - `preprocessor` - Expansion of `@include` and macro directives with a source map
+//! - `register_analysis` - Register usage tracking and value kind inference
+//! - `diagnostics` - Diagnostics engine shared by the language server and `--diagnose`
 
+pub mod config;
+pub mod data_pack;
+pub mod device_hashes;
+pub mod diagnostics;
+pub mod game_versions;
+pub mod hash_utils;
 pub mod instructions;
+pub mod preprocessor;
+pub mod register_analysis;
+pub mod types;
@@ -22,18 +22,23 @@
 //! - Completion providers (instructions, parameters, enums)
 //! - Hover providers (documentation, examples, history)
 
-use ic10lsp::instructions::{self, DataType}; // access library module with instruction metadata
+use ic10lsp::config::Configuration;
+use ic10lsp::diagnostics::{self, LINT_ABSOLUTE_JUMP};
+use ic10lsp::instructions; // access library module with instruction metadata
+use ic10lsp::types::{
+    AliasValue, DefineValue, DefinitionData, HasType, NodeEx, Position, Range, TypeData,
+};
+use ic10lsp::{hash_utils, preprocessor, register_analysis};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet},
-    fmt::Display,
+    collections::HashMap,
     net::Ipv4Addr,
     sync::Arc,
 };
 use tower_lsp::lsp_types::SemanticTokenType;
-use tower_lsp::lsp_types::{Position as LspPosition, Range as LspRange};
+use tower_lsp::lsp_types::Range as LspRange;
 use tower_lsp::{LanguageServer, LspService, Server};
 use tree_sitter::{Node, Parser, Query, QueryCursor, Tree};
 
@@ -42,27 +47,78 @@ use tree_sitter::{Node, Parser, Query, QueryCursor, Tree};
 // ============================================================================
 // These modules provide specialized functionality for the language server
 
-/// Additional language features like register analysis and code actions
+/// Code actions for refactoring and fixing instructions
 mod additional_features;
 
+/// Call hierarchy over labels reached through jal and branch-and-link instructions
+mod call_hierarchy;
+
 /// Command-line interface handling
 mod cli;
 
-/// Device hash mappings and resolution (HASH() function support)
-mod device_hashes;
+/// Code lenses with label reference counts, line numbers and size budgets
+mod code_lens;
+
+/// Text, GCC, JSON and SARIF rendering of `--diagnose` results
+mod diagnostic_report;
+
+/// Per-document storage with revisions for cancelling stale diagnostics
+mod documents;
+
+/// Inlay hints for label lines, define and enum values and relative jump targets
+mod inlay_hints;
+
+/// Conversion of line-number jumps into label jumps
+mod jump_labels;
+
+/// Minification of scripts into a build that fits the in-game size limits
+mod minifier;
+
+/// Peephole rewrites of redundant instruction sequences
+mod optimizer;
 
-/// Utility functions for hash computation and parsing
-mod hash_utils;
+/// Index of identifier references used by lenses and editing features
+mod references;
+
+/// Result ids and reports for pull-model diagnostics
+mod pull_diagnostics;
+
+/// Liveness-based reassignment of registers to use as few as possible
+mod register_allocator;
+
+/// Expansion of `--diagnose` directories and globs into script paths
+mod script_files;
+
+/// Conversion between `.ic10` files and the game's `instruction.xml` script library
+mod script_library;
+
+/// Result ids and delta computation for semantic token requests
+mod semantic_tokens;
+
+/// Incremental text synchronisation and tree-sitter edit computation
+mod text_sync;
 
 /// Enhanced tooltip/hover documentation with examples
 mod tooltip_documentation;
 
+/// Symbol index over every IC10 file in the workspace folders
+mod workspace_index;
+
+/// Listing, export and injection of the chip programs in a world save
+mod world_save;
+
 // ============================================================================
 // Constants
 // ============================================================================
 
-/// Diagnostic code for absolute jump instructions (should use relative jumps)
-const LINT_ABSOLUTE_JUMP: &str = "absolute-jump";
+/// Diagnostic code for numeric batch modes that have a named replacement
+const LINT_NUMBER_BATCH_MODE: &str = "number-batch-mode";
+
+/// Pause after an edit before diagnostics run, so a burst of keystrokes triggers one run.
+const DIAGNOSTICS_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// How often `--diagnose --watch` looks for changed scripts.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
 
 /// Semantic token types supported by the LSP for syntax highlighting.
 /// These map to VSCode's semantic token system for rich colorization.
@@ -76,65 +132,25 @@ const SEMANTIC_SYMBOL_LEGEND: &[SemanticTokenType] = &[
     SemanticTokenType::NUMBER,
     SemanticTokenType::COMMENT,
     SemanticTokenType::MACRO,
+    DEVICE_ALIAS_TOKEN,
+    REGISTER_ALIAS_TOKEN,
 ];
 
-// ============================================================================
-// Data Type Unions for Parameter Validation
-// ============================================================================
-// These constants define which data types are acceptable for various
-// instruction parameters. Used for completion suggestions and type checking.
-
-/// Parameters that only accept LogicType (e.g., Temperature, Pressure)
-const LOGIC_ONLY: [DataType; 1] = [DataType::LogicType];
-
-/// Parameters that only accept SlotLogicType (e.g., Occupant, OccupantHash)
-const SLOT_ONLY: [DataType; 1] = [DataType::SlotLogicType];
-
-/// Parameters that only accept BatchMode (e.g., Average, Sum, Maximum)
-const BATCH_ONLY: [DataType; 1] = [DataType::BatchMode];
-
-/// Parameters that only accept ReagentMode
-const REAGENT_ONLY: [DataType; 1] = [DataType::ReagentMode];
-
-/// Parameters that only accept Name
-const NAME_ONLY: [DataType; 1] = [DataType::Name];
-const LOGIC_SLOT: [DataType; 2] = [DataType::LogicType, DataType::SlotLogicType];
-const LOGIC_BATCH: [DataType; 2] = [DataType::LogicType, DataType::BatchMode];
-const LOGIC_REAGENT: [DataType; 2] = [DataType::LogicType, DataType::ReagentMode];
-const SLOT_BATCH: [DataType; 2] = [DataType::SlotLogicType, DataType::BatchMode];
-const SLOT_REAGENT: [DataType; 2] = [DataType::SlotLogicType, DataType::ReagentMode];
-const BATCH_REAGENT: [DataType; 2] = [DataType::BatchMode, DataType::ReagentMode];
-const LOGIC_SLOT_BATCH: [DataType; 3] = [
-    DataType::LogicType,
-    DataType::SlotLogicType,
-    DataType::BatchMode,
-];
-const LOGIC_SLOT_REAGENT: [DataType; 3] = [
-    DataType::LogicType,
-    DataType::SlotLogicType,
-    DataType::ReagentMode,
-];
-const LOGIC_BATCH_REAGENT: [DataType; 3] = [
-    DataType::LogicType,
-    DataType::BatchMode,
-    DataType::ReagentMode,
-];
-const SLOT_BATCH_REAGENT: [DataType; 3] = [
-    DataType::SlotLogicType,
-    DataType::BatchMode,
-    DataType::ReagentMode,
-];
-const LOGIC_SLOT_BATCH_REAGENT: [DataType; 4] = [
-    DataType::LogicType,
-    DataType::SlotLogicType,
-    DataType::BatchMode,
-    DataType::ReagentMode,
+/// Token type for aliases of device pins (`alias sensor d0`).
+const DEVICE_ALIAS_TOKEN: SemanticTokenType = SemanticTokenType::new("deviceAlias");
+
+/// Token type for aliases of registers (`alias counter r0`).
+const REGISTER_ALIAS_TOKEN: SemanticTokenType = SemanticTokenType::new("registerAlias");
+
+/// Semantic token modifiers; a token's bitset has bit N set for entry N.
+const SEMANTIC_MODIFIER_LEGEND: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::READONLY,
+    SemanticTokenModifier::DEPRECATED,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
 ];
 
 use phf::phf_set;
-use crate::hash_utils::{
-    compute_crc32, extract_hash_argument, get_device_hash, is_hash_function_call,
-};
 use serde_json::Value;
 use tokio::{
     net::{TcpListener, TcpStream},
@@ -144,185 +160,31 @@ use tower_lsp::{async_trait, jsonrpc::Result, lsp_types::*, Client};
 struct DocumentData {
     url: Url,
     content: String,
+    /// Whether `content` ends with a newline the client's buffer does not have
+    synthetic_newline: bool,
     tree: Option<Tree>,
     parser: Parser,
 }
 
-#[derive(Debug, Clone)]
-struct DefinitionData<T> {
-    range: Range,
-    value: T,
-}
-
-impl<T> DefinitionData<T> {
-    fn new(range: Range, value: T) -> Self {
-        DefinitionData { range, value }
-    }
-}
-
-#[derive(Debug, Clone)]
-enum AliasValue {
-    Register(String),
-    Device(String),
-}
-
-impl Display for AliasValue {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = match self {
-            AliasValue::Register(s) => s,
-            AliasValue::Device(s) => s,
-        };
-        s.fmt(f)
-    }
-}
-
-impl From<String> for AliasValue {
-    fn from(value: String) -> Self {
-        use AliasValue::*;
-        if value.starts_with("d") {
-            Device(value)
-        } else {
-            Register(value)
-        }
-    }
-}
-
-trait HasType {
-    fn get_type(&self) -> instructions::DataType;
-}
-
-impl HasType for AliasValue {
-    fn get_type(&self) -> instructions::DataType {
-        match *self {
-            AliasValue::Register(_) => instructions::DataType::Register,
-            AliasValue::Device(_) => instructions::DataType::Device,
-        }
-    }
-}
-
-impl HasType for DefinitionData<f64> {
-    fn get_type(&self) -> instructions::DataType {
-        instructions::DataType::Number
-    }
-}
-
-impl HasType for DefinitionData<u8> {
-    fn get_type(&self) -> instructions::DataType {
-        instructions::DataType::Number
-    }
-}
-
-#[derive(Debug, Clone)]
-struct DefineValue {
-    original: String,
-    resolved_numeric: Option<i32>,
-}
-
-impl DefineValue {
-    fn resolve_numeric(text: &str) -> Option<i32> {
-        if let Ok(value) = text.trim().parse::<i32>() {
-            return Some(value);
-        }
-        if is_hash_function_call(text) {
-            if let Some(arg) = extract_hash_argument(text) {
-                return Some(compute_crc32(&arg));
-            }
-        }
-        None
-    }
-
-    fn resolved_numeric(&self) -> Option<i32> {
-        self.resolved_numeric
-    }
-}
-
-impl From<String> for DefineValue {
-    fn from(value: String) -> Self {
-        let resolved_numeric = Self::resolve_numeric(&value);
-        Self {
-            original: value,
-            resolved_numeric,
-        }
-    }
-}
-
-impl std::fmt::Display for DefineValue {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.original.fmt(f)
-    }
-}
-
-impl HasType for DefineValue {
-    fn get_type(&self) -> instructions::DataType {
-        instructions::DataType::Number
-    }
-}
-
-impl<T> HasType for DefinitionData<T>
-where
-    T: HasType,
-{
-    fn get_type(&self) -> instructions::DataType {
-        self.value.get_type()
-    }
-}
-
-#[derive(Default, Debug, Clone)]
-struct TypeData {
-    defines: HashMap<String, DefinitionData<DefineValue>>,
-    aliases: HashMap<String, DefinitionData<AliasValue>>,
-    labels: HashMap<String, DefinitionData<u8>>,
-}
-
-impl TypeData {
-    fn get_range(&self, name: &str) -> Option<Range> {
-        if let Some(definition_data) = self.defines.get(name) {
-            return Some(definition_data.range.clone());
-        }
-                    if let Some(definition_data) = self.aliases.get(name) {
-            return Some(definition_data.range.clone());
-        }
-        if let Some(definition_data) = self.labels.get(name) {
-            return Some(definition_data.range.clone());
-        }
-        None
-    }
-}
-
 struct FileData {
     document_data: DocumentData,
     type_data: TypeData,
 }
 
-#[derive(Clone, Debug)]
-struct Configuration {
-    max_lines: usize,
-    max_columns: usize,
-    max_bytes: usize,
-    warn_overline_comment: bool,
-    warn_overcolumn_comment: bool,
-    suppress_hash_diagnostics: bool,
-}
-
-impl Default for Configuration {
-    fn default() -> Self {
-        Self {
-            max_lines: 128,
-            max_columns: 90,
-            max_bytes: 4096,
-            warn_overline_comment: true,
-            warn_overcolumn_comment: true,
-            suppress_hash_diagnostics: false,
-        }
-    }
-}
-
+#[derive(Clone)]
 struct Backend {
     client: Client,
-    files: Arc<RwLock<HashMap<Url, FileData>>>,
+    files: Arc<documents::DocumentStore>,
     config: Arc<RwLock<Configuration>>,
     // Runtime flag to allow diagnostics suppression without restart
     diagnostics_enabled: Arc<RwLock<bool>>,
+    workspace: Arc<RwLock<workspace_index::WorkspaceIndex>>,
+    // Encoding negotiated for position columns in initialize
+    position_encoding: Arc<RwLock<PositionEncodingKind>>,
+    semantic_token_cache: Arc<RwLock<semantic_tokens::TokenCache>>,
+    // Client pulls diagnostics itself, so they are not published on change
+    pull_diagnostics: Arc<RwLock<bool>>,
+    workspace_diagnostics: Arc<RwLock<pull_diagnostics::WorkspaceCache>>,
 }
 
 #[async_trait]
@@ -335,45 +197,50 @@ impl LanguageServer for Backend {
         if let Some(init_options) = params.initialization_options {
             self.client.log_message(MessageType::INFO, format!("Init options: {}", serde_json::to_string_pretty(&init_options).unwrap_or_else(|_| "serialize failed".to_string()))).await;
             
-            let mut config = self.config.write().await;
-            
-            if let Some(warnings) = init_options.get("warnings").and_then(Value::as_object) {
-                config.warn_overline_comment = warnings
-                    .get("overline_comment")
-                    .and_then(Value::as_bool)
-                    .unwrap_or(config.warn_overline_comment);
-                config.warn_overcolumn_comment = warnings
-                    .get("overcolumn_comment")
-                    .and_then(Value::as_bool)
-                    .unwrap_or(config.warn_overcolumn_comment);
+            if let Some(dir) = init_options
+                .get("dataPack")
+                .and_then(Value::as_str)
+                .filter(|x| !x.is_empty())
+            {
+                self.load_data_pack(Path::new(dir)).await;
             }
-            
-            config.max_lines = init_options
-                .get("max_lines")
-                .and_then(Value::as_u64)
-                .map(|x| x as usize)
-                .unwrap_or(config.max_lines);
-            
-            config.max_columns = init_options
-                .get("max_columns")
-                .and_then(Value::as_u64)
-                .map(|x| x as usize)
-                .unwrap_or(config.max_columns);
-            
-            config.max_bytes = init_options
-                .get("max_bytes")
-                .and_then(Value::as_u64)
-                .map(|x| x as usize)
-                .unwrap_or(config.max_bytes);
-            
-            config.suppress_hash_diagnostics = init_options
-                .get("suppressHashDiagnostics")
-                .and_then(Value::as_bool)
-                .unwrap_or(config.suppress_hash_diagnostics);
-            
+
+            let mut config = self.config.write().await;
+            config.update(&init_options);
+
             self.client.log_message(MessageType::INFO, format!("Initial config - suppress_hash_diagnostics: {}", config.suppress_hash_diagnostics)).await;
         }
         
+        // Remember the workspace folders; they are scanned for scripts once initialized
+        {
+            let mut workspace = self.workspace.write().await;
+            #[allow(deprecated)]
+            let folders = params.workspace_folders.clone().unwrap_or_else(|| {
+                params
+                    .root_uri
+                    .clone()
+                    .map(|uri| {
+                        vec![WorkspaceFolder {
+                            uri,
+                            name: String::new(),
+                        }]
+                    })
+                    .unwrap_or_default()
+            });
+            for folder in folders {
+                if let Ok(path) = folder.uri.to_file_path() {
+                    workspace.add_folder(path);
+                }
+            }
+        }
+
+        *self.pull_diagnostics.write().await = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|x| x.diagnostic.as_ref())
+            .is_some();
+
         let mut utf8_supported = false;
         if let Some(encodings) = params
             .capabilities
@@ -389,6 +256,9 @@ impl LanguageServer for Backend {
             // The warning is suppressed to avoid confusion since the vscode-languageclient
             // handles encoding negotiation automatically.
         }
+        if utf8_supported {
+            *self.position_encoding.write().await = PositionEncodingKind::UTF8;
+        }
         // Log current counts of static maps/sets so we can verify the running binary contains
         // the latest logic types. This message appears once on server init in the Output panel.
         self.client
@@ -405,12 +275,16 @@ impl LanguageServer for Backend {
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 execute_command_provider: Some(ExecuteCommandOptions {
                     commands: vec![
                         "setDiagnostics".to_string(),
                         "ic10.setHashDiagnostics".to_string(),
+                        "ic10.convertJumpsToLabels".to_string(),
+                        "ic10.allocateRegisters".to_string(),
+                        "ic10.minify".to_string(),
+                        "ic10.preprocess".to_string(),
                     ],
                     work_done_progress_options: WorkDoneProgressOptions {
                         work_done_progress: None,
@@ -427,6 +301,22 @@ impl LanguageServer for Backend {
                 }),
                 position_encoding: utf8_supported.then_some(PositionEncodingKind::UTF8),
                 document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                diagnostic_provider: Some(DiagnosticServerCapabilities::Options(
+                    DiagnosticOptions {
+                        identifier: Some("ic10".to_string()),
+                        inter_file_dependencies: false,
+                        workspace_diagnostics: true,
+                        work_done_progress_options: WorkDoneProgressOptions::default(),
+                    },
+                )),
+                linked_editing_range_provider: Some(
+                    LinkedEditingRangeServerCapabilities::Simple(true),
+                ),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
                 completion_provider: Some(CompletionOptions {
                     resolve_provider: Some(false),
                     trigger_characters: Some(vec![" ".to_string()]),
@@ -438,12 +328,12 @@ impl LanguageServer for Backend {
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensOptions(
                         SemanticTokensOptions {
-                            range: Some(false),
-                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                            range: Some(true),
+                            full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                             legend: {
                                 SemanticTokensLegend {
                                     token_types: SEMANTIC_SYMBOL_LEGEND.into(),
-                                    token_modifiers: vec![],
+                                    token_modifiers: SEMANTIC_MODIFIER_LEGEND.into(),
                                 }
                             },
                             ..Default::default()
@@ -451,7 +341,10 @@ impl LanguageServer for Backend {
                     ),
                 ),
                 workspace: Some(WorkspaceServerCapabilities {
-                    workspace_folders: None,
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
                     file_operations: None,
                 }),
                 ..Default::default()
@@ -463,7 +356,31 @@ impl LanguageServer for Backend {
         })
     }
 
-    async fn initialized(&self, _params: InitializedParams) {}
+    async fn initialized(&self, _params: InitializedParams) {
+        // Ask the client to tell us about scripts created, edited or deleted on disk
+        let watchers = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.ic10".to_string()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: "ic10-watched-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(watchers).ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(
+                    MessageType::INFO,
+                    format!("File watching not available: {}", e),
+                )
+                .await;
+        }
+
+        let folders = self.workspace.read().await.folders().to_vec();
+        self.index_workspace_folders(folders).await;
+    }
 
     async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
         match params.command.as_str() {
@@ -482,11 +399,11 @@ impl LanguageServer for Backend {
                         *flag = enabled;
                     }
                     // re-run or clear diagnostics for all open documents
-                    let uris = {
-                        let files = self.files.read().await;
-                        files.keys().cloned().collect::<Vec<_>>()
-                    };
-                    for uri in uris {
+                    if *self.pull_diagnostics.read().await {
+                        self.refresh_diagnostics().await;
+                        return Ok(None);
+                    }
+                    for uri in self.files.uris().await {
                         if enabled {
                             self.run_diagnostics(&uri).await;
                         } else {
@@ -502,13 +419,7 @@ impl LanguageServer for Backend {
                     self.config.write().await.suppress_hash_diagnostics = suppress;
 
                     // Re-run diagnostics for all open files
-                    let uris = {
-                        let files = self.files.read().await;
-                        files.keys().cloned().collect::<Vec<_>>()
-                    };
-                    for uri in uris {
-                        self.run_diagnostics(&uri).await;
-                    }
+                    self.refresh_diagnostics().await;
                 }
             }
             "ic10.suppressAllRegisterDiagnostics" => {
@@ -516,12 +427,12 @@ impl LanguageServer for Backend {
                 if let Some(uri_value) = params.arguments.get(0) {
                     if let Some(uri_str) = uri_value.as_str() {
                         if let Ok(uri) = Url::parse(uri_str) {
-                            let files = self.files.read().await;
-                            if let Some(file_data) = files.get(&uri) {
+                            if let Some(file) = self.files.get(&uri).await {
+                                let file_data = file.data.read().await;
                                 let content = &file_data.document_data.content;
                                 
                                 // Re-run register analysis to get current diagnostics
-                                let mut register_analyzer = additional_features::RegisterAnalyzer::new();
+                                let mut register_analyzer = register_analysis::RegisterAnalyzer::new();
                                 if let Some(ref tree) = file_data.document_data.tree {
                                     register_analyzer.analyze_register_usage(
                                         tree,
@@ -609,6 +520,187 @@ impl LanguageServer for Backend {
                     }
                 }
             }
+            "ic10.convertJumpsToLabels" => {
+                let Some(uri) = params
+                    .arguments
+                    .first()
+                    .and_then(Value::as_str)
+                    .and_then(|x| Url::parse(x).ok())
+                else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "Expected a document URI",
+                    ));
+                };
+                let edits = {
+                    let Some(file) = self.files.get(&uri).await else {
+                        return Err(tower_lsp::jsonrpc::Error::invalid_request());
+                    };
+                    let file_data = file.data.read().await;
+                    let Some(tree) = file_data.document_data.tree.as_ref() else {
+                        return Ok(None);
+                    };
+                    jump_labels::convert_to_labels(
+                        tree,
+                        &file_data.document_data.content,
+                        &file_data.type_data.defines,
+                        |_| true,
+                    )
+                };
+                let edits = match edits {
+                    Ok(edits) => edits,
+                    Err(message) => {
+                        self.client
+                            .show_message(MessageType::WARNING, message)
+                            .await;
+                        return Ok(None);
+                    }
+                };
+                let count = edits.len();
+                if count > 0 {
+                    let _ = self
+                        .client
+                        .apply_edit(WorkspaceEdit::new(HashMap::from([(uri, edits)])))
+                        .await;
+                }
+                return Ok(Some(Value::from(count)));
+            }
+            "ic10.allocateRegisters" => {
+                let Some(uri) = params
+                    .arguments
+                    .first()
+                    .and_then(Value::as_str)
+                    .and_then(|x| Url::parse(x).ok())
+                else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "Expected a document URI",
+                    ));
+                };
+                let allocation = {
+                    let Some(file) = self.files.get(&uri).await else {
+                        return Err(tower_lsp::jsonrpc::Error::invalid_request());
+                    };
+                    let file_data = file.data.read().await;
+                    let Some(tree) = file_data.document_data.tree.as_ref() else {
+                        return Ok(None);
+                    };
+                    register_allocator::allocate(
+                        tree,
+                        &file_data.document_data.content,
+                        &file_data.type_data.aliases,
+                    )
+                };
+                let allocation = match allocation {
+                    Ok(allocation) => allocation,
+                    Err(message) => {
+                        self.client
+                            .show_message(MessageType::WARNING, message)
+                            .await;
+                        return Ok(None);
+                    }
+                };
+                let message = if allocation.edits.is_empty() {
+                    format!("Registers already minimal ({} in use)", allocation.before)
+                } else {
+                    let _ = self
+                        .client
+                        .apply_edit(WorkspaceEdit::new(HashMap::from([(uri, allocation.edits)])))
+                        .await;
+                    let moves: Vec<String> = allocation
+                        .mapping
+                        .iter()
+                        .filter(|(from, to)| from != to)
+                        .map(|(from, to)| format!("r{from} -> r{to}"))
+                        .collect();
+                    format!(
+                        "Registers reallocated: {} -> {} in use ({})",
+                        allocation.before,
+                        allocation.after,
+                        moves.join(", ")
+                    )
+                };
+                self.client.show_message(MessageType::INFO, message).await;
+                return Ok(Some(Value::from(allocation.after)));
+            }
+            "ic10.minify" => {
+                let Some(uri) = params
+                    .arguments
+                    .first()
+                    .and_then(Value::as_str)
+                    .and_then(|x| Url::parse(x).ok())
+                else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "Expected a document URI",
+                    ));
+                };
+                let minified = {
+                    let Some(file) = self.files.get(&uri).await else {
+                        return Err(tower_lsp::jsonrpc::Error::invalid_request());
+                    };
+                    let file_data = file.data.read().await;
+                    let Some(tree) = file_data.document_data.tree.as_ref() else {
+                        return Ok(None);
+                    };
+                    minifier::minify(tree, &file_data.document_data.content)
+                };
+                let minified = match minified {
+                    Ok(minified) => minified,
+                    Err(message) => {
+                        self.client
+                            .show_message(MessageType::WARNING, message)
+                            .await;
+                        return Ok(None);
+                    }
+                };
+                let report = {
+                    let config = self.config.read().await;
+                    minified.report(config.max_lines, config.max_bytes)
+                };
+                for warning in &minified.warnings {
+                    self.client
+                        .show_message(MessageType::WARNING, warning)
+                        .await;
+                }
+                return Ok(Some(serde_json::json!({
+                    "text": minified.text,
+                    "report": report,
+                    "warnings": minified.warnings,
+                })));
+            }
+            "ic10.preprocess" => {
+                let Some(uri) = params
+                    .arguments
+                    .first()
+                    .and_then(Value::as_str)
+                    .and_then(|x| Url::parse(x).ok())
+                else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "Expected a document URI",
+                    ));
+                };
+                let content = {
+                    let Some(file) = self.files.get(&uri).await else {
+                        return Err(tower_lsp::jsonrpc::Error::invalid_request());
+                    };
+                    let file_data = file.data.read().await;
+                    file_data.document_data.content.clone()
+                };
+                let path = uri.to_file_path().ok();
+                let dir = path.as_deref().and_then(Path::parent);
+                let expansion = preprocessor::expand(&content, dir, |x| fs::read_to_string(x));
+                for error in &expansion.errors {
+                    self.client
+                        .show_message(
+                            MessageType::WARNING,
+                            format!("Line {}: {}", error.site + 1, error.message),
+                        )
+                        .await;
+                }
+                let root = path.map_or(uri.to_string(), |x| x.display().to_string());
+                return Ok(Some(serde_json::json!({
+                    "text": expansion.text,
+                    "sourceMap": expansion.source_map(&root),
+                })));
+            }
             _ => {}
         }
         Ok(None)
@@ -619,18 +711,20 @@ impl LanguageServer for Backend {
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        self.update_content(params.text_document.uri.clone(), params.text_document.text)
+        let change = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: params.text_document.text,
+        };
+        self.update_content(params.text_document.uri.clone(), vec![change])
             .await;
         self.run_diagnostics(&params.text_document.uri).await;
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        for change in params.content_changes {
-            // Should only ever be one, because we are getting full updates
-            self.update_content(params.text_document.uri.clone(), change.text)
-                .await;
-        }
-        self.run_diagnostics(&params.text_document.uri).await;
+        self.update_content(params.text_document.uri.clone(), params.content_changes)
+            .await;
+        self.schedule_diagnostics(params.text_document.uri).await;
     }
 
     async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
@@ -642,61 +736,22 @@ impl LanguageServer for Backend {
             self.client.log_message(MessageType::INFO, "=== Configuration received ===").await;
             self.client.log_message(MessageType::INFO, format!("Config JSON: {}", serde_json::to_string_pretty(&value).unwrap_or_else(|_| "Failed to serialize".to_string()))).await;
 
-            if let Some(warnings) = value.get("warnings").and_then(Value::as_object) {
-                config.warn_overline_comment = warnings
-                    .get("overline_comment")
-                    .and_then(Value::as_bool)
-                    .unwrap_or(config.warn_overline_comment);
-
-                config.warn_overcolumn_comment = warnings
-                    .get("overcolumn_comment")
-                    .and_then(Value::as_bool)
-                    .unwrap_or(config.warn_overcolumn_comment);
-            }
-
-            config.max_lines = value
-                .get("max_lines")
-                .and_then(Value::as_u64)
-                .map(|x| x as usize)
-                .unwrap_or(config.max_lines);
-
-            config.max_columns = value
-                .get("max_columns")
-                .and_then(Value::as_u64)
-                .map(|x| x as usize)
-                .unwrap_or(config.max_columns);
-
-            config.max_bytes = value
-                .get("max_bytes")
-                .and_then(Value::as_u64)
-                .map(|x| x as usize)
-                .unwrap_or(config.max_bytes);
-
-            config.suppress_hash_diagnostics = value
-                .get("suppressHashDiagnostics")
-                .and_then(Value::as_bool)
-                .unwrap_or(config.suppress_hash_diagnostics);
+            config.update(&value);
 
             self.client.log_message(MessageType::INFO, format!("suppress_hash_diagnostics set to: {}", config.suppress_hash_diagnostics)).await;
         }
 
-        let uris = {
-            let files = self.files.read().await;
-            files.keys().map(Clone::clone).collect::<Vec<_>>()
-        };
-        for uri in uris {
-            self.run_diagnostics(&uri).await;
-        }
+        self.refresh_diagnostics().await;
     }
 
     async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
         let mut ret = Vec::new();
 
-        let files = self.files.read().await;
         let uri = params.text_document.uri;
-        let Some(file_data) = files.get(&uri) else {
+        let Some(file) = self.files.get(&uri).await else {
             return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
+        let file_data = file.data.read().await;
 
         let document = &file_data.document_data;
 
@@ -722,7 +777,7 @@ impl LanguageServer for Backend {
 
             // Direct numeric device hash lookup
             if let Ok(number) = text.parse::<i32>() {
-                if let Some(item_name) = crate::device_hashes::HASH_TO_DISPLAY_NAME.get(&number) {
+                if let Some(item_name) = crate::hash_utils::get_device_name_for_hash(number) {
                     let Some(line_node) = node.find_parent("line") else {
                         continue;
                     };
@@ -761,9 +816,7 @@ impl LanguageServer for Backend {
             if let Some(device_name) = crate::hash_utils::extract_hash_argument(call_text) {
                 if let Some(hash_val) = crate::hash_utils::get_device_hash(&device_name) {
                     // Look up the display name for this hash
-                    let display_text = crate::device_hashes::HASH_TO_DISPLAY_NAME
-                        .get(&hash_val)
-                        .copied()
+                    let display_text = crate::hash_utils::get_device_name_for_hash(hash_val)
                         .unwrap_or("Unknown Device");
                     
                     let Some(line_node) = call_node.find_parent("line") else {
@@ -847,6 +900,13 @@ impl LanguageServer for Backend {
             }
         }
 
+        ret.extend(inlay_hints::value_hints(
+            tree,
+            &document.content,
+            &file_data.type_data,
+            params.range,
+        ));
+
         Ok(Some(ret))
     }
 
@@ -854,208 +914,37 @@ impl LanguageServer for Backend {
         &self,
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
-        let mut ret = Vec::new();
-        let files = self.files.read().await;
         let uri = params.text_document.uri;
-        let Some(file_data) = files.get(&uri) else {
-            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        let Some(tokens) = self.semantic_tokens(&uri, None).await? else {
+            return Ok(None);
         };
-        let document = &file_data.document_data;
+        let tokens = self.semantic_token_cache.write().await.full(&uri, tokens);
+        Ok(Some(SemanticTokensResult::Tokens(tokens)))
+    }
 
-        let Some(ref tree) = document.tree else {
-            return Err(tower_lsp::jsonrpc::Error::internal_error());
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        let uri = params.text_document.uri;
+        let Some(tokens) = self.semantic_tokens(&uri, None).await? else {
+            return Ok(None);
         };
+        let mut cache = self.semantic_token_cache.write().await;
+        Ok(Some(cache.delta(&uri, &params.previous_result_id, tokens)))
+    }
 
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(
-            tree_sitter_ic10::language(),
-            "(comment) @comment
-             (instruction (operation)@keyword)
-             (instruction (invalid_instruction)@invalid_keyword)
-             (logictype)@string
-             (device)@preproc
-             (register)@macro
-             (number)@float
-             (identifier)@variable",
-        )
-        .unwrap();
-
-        let mut previous_line = 0u32;
-        let mut previous_col = 0u32;
-
-        let comment_idx = query.capture_index_for_name("comment").unwrap();
-        let keyword_idx = query.capture_index_for_name("keyword").unwrap();
-        let invalid_keyword_idx = query.capture_index_for_name("invalid_keyword").unwrap();
-        let string_idx = query.capture_index_for_name("string").unwrap();
-        let preproc_idx = query.capture_index_for_name("preproc").unwrap();
-        let macro_idx = query.capture_index_for_name("macro").unwrap();
-        let float_idx = query.capture_index_for_name("float").unwrap();
-        let variable_idx = query.capture_index_for_name("variable").unwrap();
-
-        for (capture, _) in cursor.captures(&query, tree.root_node(), document.content.as_bytes()) {
-            let node = capture.captures[0].node;
-            let idx = capture.captures[0].index;
-            let start = node.range().start_point;
-
-            let delta_line = start.row as u32 - previous_line;
-            let delta_start = if delta_line == 0 {
-                start.column as u32 - previous_col
-            } else {
-                start.column as u32
-            };
-
-            let tokentype = {
-                if idx == comment_idx {
-                    SemanticTokenType::COMMENT
-                } else if idx == keyword_idx {
-                    SemanticTokenType::KEYWORD
-                } else if idx == invalid_keyword_idx {
-                    let instruction_text = node.utf8_text(document.content.as_bytes()).unwrap();
-                    if instructions::INSTRUCTIONS.contains_key(instruction_text) {
-                        SemanticTokenType::KEYWORD
-                    } else {
-                        continue;
-                    }
-                } else if idx == string_idx {
-                    SemanticTokenType::STRING
-                } else if idx == preproc_idx {
-                    SemanticTokenType::FUNCTION
-                } else if idx == macro_idx {
-                    SemanticTokenType::MACRO
-                } else if idx == float_idx {
-                    SemanticTokenType::NUMBER
-                } else if idx == variable_idx {
-                    // Classify identifiers: labels -> TYPE (purple), enums -> ENUM, otherwise VARIABLE
-                    let ident_text = node.utf8_text(document.content.as_bytes()).unwrap_or("");
-                    // Reconstruct fully-qualified enum token if this identifier is part of a dotted operand
-                    let mut qualified_operand: Option<String> = None;
-                    if let Some(parent) = node.parent() {
-                        if parent.kind() == "operand" {
-                            if let Ok(full) = parent.utf8_text(document.content.as_bytes()) {
-                                // Trim trailing comment or whitespace artifacts
-                                let full_trim = full.split('#').next().unwrap_or(full).trim();
-                                if full_trim.contains('.') {
-                                    qualified_operand = Some(full_trim.to_string());
-                                }
-                            }
-                        }
-                    }
-                    // Determine if this identifier is a branch/jump label reference even if forward‑declared.
-                    let mut branch_label_reference = false;
-                    if !file_data.type_data.labels.contains_key(ident_text) {
-                        // Only attempt contextual detection if not already a known label definition.
-                        if let Some(operand_parent) = node.parent() {
-                            if operand_parent.kind() == "operand" {
-                                if let Some(instr_parent) = operand_parent.parent() {
-                                    if instr_parent.kind() == "instruction" {
-                                        if let Some(op_node) =
-                                            instr_parent.child_by_field_name("operation")
-                                        {
-                                            if let Ok(op_text) =
-                                                op_node.utf8_text(document.content.as_bytes())
-                                            {
-                                                // Classify branch/jump mnemonics for positional label operands.
-                                                // Two groups: (a,b,label) form and (a,label) form; plus single‑operand j/jal.
-                                                static THREE_OPERAND_BRANCHES: phf::Set<
-                                                    &'static str,
-                                                > = phf_set!(
-                                                    "beq", "bne", "blt", "bgt", "ble", "bge",
-                                                    "breq", "brne", "brlt", "brgt", "brle", "brge",
-                                                    "beqal", "bneal", "bltal", "bgtal", "bleal",
-                                                    "bgeal"
-                                                );
-                                                static TWO_OPERAND_BRANCHES: phf::Set<
-                                                    &'static str,
-                                                > = phf_set!(
-                                                    "beqz", "bnez", "bltz", "bgtz", "blez", "bgez",
-                                                    "breqz", "brnez", "brltz", "brgtz", "brlez",
-                                                    "brgez", "beqzal", "bnezal", "bltzal",
-                                                    "bgtzal", "blezal", "bgezal"
-                                                );
-                                                static SINGLE_OPERAND_JUMPS: phf::Set<
-                                                    &'static str,
-                                                > = phf_set!("j", "jal");
-
-                                                // Count operand index for this identifier within the instruction.
-                                                let mut w = instr_parent.walk();
-                                                let operands: Vec<_> = instr_parent
-                                                    .children_by_field_name("operand", &mut w)
-                                                    .collect();
-                                                let operand_index = operands
-                                                    .iter()
-                                                    .position(|o| o.id() == operand_parent.id());
-                                                if let Some(idx_op) = operand_index {
-                                                    let op_lower = op_text.to_ascii_lowercase();
-                                                    if THREE_OPERAND_BRANCHES
-                                                        .contains(op_lower.as_str())
-                                                    {
-                                                        // label is last (third) operand
-                                                        if idx_op == 2 {
-                                                            branch_label_reference = true;
-                                                        }
-                                                    } else if TWO_OPERAND_BRANCHES
-                                                        .contains(op_lower.as_str())
-                                                    {
-                                                        // label is second operand
-                                                        if idx_op == 1 {
-                                                            branch_label_reference = true;
-                                                        }
-                                                    } else if SINGLE_OPERAND_JUMPS
-                                                        .contains(op_lower.as_str())
-                                                    {
-                                                        // label is sole operand
-                                                        if idx_op == 0 {
-                                                            branch_label_reference = true;
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    if file_data.type_data.labels.contains_key(ident_text) || branch_label_reference
-                    {
-                        SemanticTokenType::TYPE
-                    } else if ident_text.contains('.')
-                        && ic10lsp::instructions::enum_info_case_insensitive(ident_text).is_some()
-                    {
-                        SemanticTokenType::ENUM
-                    } else if let Some(full) = qualified_operand.as_ref() {
-                        // If the full operand is an enum qualified name (e.g., TraderInstruction.WriteTraderData)
-                        // color both identifiers as ENUM tokens
-                        if ic10lsp::instructions::enum_info_case_insensitive(full).is_some() {
-                            SemanticTokenType::ENUM
-                        } else {
-                            SemanticTokenType::VARIABLE
-                        }
-                    } else {
-                        SemanticTokenType::VARIABLE
-                    }
-                } else {
-                    continue;
-                }
-            };
-
-            ret.push(SemanticToken {
-                delta_line,
-                delta_start,
-                length: node.range().end_point.column as u32 - start.column as u32,
-                token_type: SEMANTIC_SYMBOL_LEGEND
-                    .iter()
-                    .position(|x| *x == tokentype)
-                    .unwrap() as u32,
-                token_modifiers_bitset: 0,
-            });
-
-            previous_line = start.row as u32;
-            previous_col = start.column as u32;
-        }
-        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        let uri = params.text_document.uri;
+        let Some(tokens) = self.semantic_tokens(&uri, Some(params.range)).await? else {
+            return Ok(None);
+        };
+        Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
             result_id: None,
-            data: ret,
+            data: tokens,
         })))
     }
 
@@ -1063,13 +952,12 @@ impl LanguageServer for Backend {
         &self,
         params: DocumentSymbolParams,
     ) -> Result<Option<DocumentSymbolResponse>> {
-        let mut ret = Vec::new();
-        let files = self.files.read().await;
         let uri = params.text_document.uri;
 
-        let Some(file_data) = files.get(&uri) else {
+        let Some(file) = self.files.get(&uri).await else {
             return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
+        let file_data = file.data.read().await;
 
         let document = &file_data.document_data;
 
@@ -1077,62 +965,183 @@ impl LanguageServer for Backend {
             return Err(tower_lsp::jsonrpc::Error::internal_error());
         };
 
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(
-            tree_sitter_ic10::language(),
-            "(instruction (operation \"define\") . (operand)@name)@define
-            (instruction (operation \"alias\") . (operand)@name)@alias
-            (instruction (operation \"label\") . (operand)@name)@alias
-            (label (identifier)@name)@label",
-        )
-        .unwrap();
-        let define_idx = query.capture_index_for_name("define").unwrap();
-        let alias_idx = query.capture_index_for_name("alias").unwrap();
-        let label_idx = query.capture_index_for_name("label").unwrap();
-        let name_idx = query.capture_index_for_name("name").unwrap();
-
-        let matches = cursor.matches(&query, tree.root_node(), document.content.as_bytes());
-
-        for matched in matches {
-            let main_match = {
-                let mut ret = None;
-                for cap in matched.captures {
-                    if cap.index == define_idx || cap.index == alias_idx || cap.index == label_idx {
-                        ret = Some(cap);
-                    }
+        Ok(Some(DocumentSymbolResponse::Flat(collect_document_symbols(
+            &uri,
+            tree,
+            &document.content,
+        ))))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let uri = params.text_document.uri;
+        let Some(file) = self.files.get(&uri).await else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let file_data = file.data.read().await;
+        let document = &file_data.document_data;
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
+        let config = self.config.read().await;
+        let references = references::ReferenceIndex::build(tree, &document.content);
+        Ok(Some(code_lens::code_lenses(
+            &document.content,
+            &file_data.type_data.labels,
+            &references,
+            &config,
+        )))
+    }
+
+    async fn linked_editing_range(
+        &self,
+        params: LinkedEditingRangeParams,
+    ) -> Result<Option<LinkedEditingRanges>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let Some(file) = self.files.get(&uri).await else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let file_data = file.data.read().await;
+        let document = &file_data.document_data;
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
+        let Some(node) = self.node_at_position(position.into(), tree) else {
+            return Ok(None);
+        };
+        if node.kind() != "identifier" {
+            return Ok(None);
+        }
+        let name = node.utf8_text(document.content.as_bytes()).unwrap();
+        let type_data = &file_data.type_data;
+        let Some(declaration) = type_data
+            .labels
+            .get(name)
+            .map(|x| &x.range)
+            .or_else(|| type_data.aliases.get(name).map(|x| &x.range))
+        else {
+            return Ok(None);
+        };
+
+        let references = references::ReferenceIndex::build(tree, &document.content);
+        Ok(references
+            .linked_ranges(name, declaration, position)
+            .map(|ranges| LinkedEditingRanges {
+                ranges: ranges.into_iter().map(Into::into).collect(),
+                word_pattern: Some("[a-zA-Z_.][a-zA-Z0-9_.]*".to_string()),
+            }))
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<CallHierarchyItem>>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let Some(file) = self.files.get(&uri).await else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let file_data = file.data.read().await;
+        let document = &file_data.document_data;
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
+        let Some(node) = self.node_at_position(position.into(), tree) else {
+            return Ok(None);
+        };
+        if node.kind() != "identifier" {
+            return Ok(None);
+        }
+        let name = node.utf8_text(document.content.as_bytes()).unwrap();
+        let graph =
+            call_hierarchy::CallGraph::build(tree, &document.content, &file_data.type_data.labels);
+        Ok(graph.prepare(&uri, name).map(|item| vec![item]))
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        let uri = &params.item.uri;
+        let Some(file) = self.files.get(uri).await else {
+            return Ok(None);
+        };
+        let file_data = file.data.read().await;
+        let document = &file_data.document_data;
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
+        let graph =
+            call_hierarchy::CallGraph::build(tree, &document.content, &file_data.type_data.labels);
+        Ok(Some(graph.incoming(uri, &params.item)))
+    }
+
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        let uri = &params.item.uri;
+        let Some(file) = self.files.get(uri).await else {
+            return Ok(None);
+        };
+        let file_data = file.data.read().await;
+        let document = &file_data.document_data;
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
+        let graph =
+            call_hierarchy::CallGraph::build(tree, &document.content, &file_data.type_data.labels);
+        Ok(Some(graph.outgoing(uri, &params.item)))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        let workspace = self.workspace.read().await;
+        Ok(Some(workspace.query(&params.query)))
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let mut added = Vec::new();
+        {
+            let mut workspace = self.workspace.write().await;
+            for folder in params.event.removed {
+                if let Ok(path) = folder.uri.to_file_path() {
+                    workspace.remove_folder(&path);
                 }
-                match ret {
-                    Some(ret) => ret,
-                    None => continue,
+            }
+            for folder in params.event.added {
+                if let Ok(path) = folder.uri.to_file_path() {
+                    workspace.add_folder(path.clone());
+                    added.push(path);
                 }
-            };
-
-            let kind = if main_match.index == define_idx {
-                SymbolKind::NUMBER
-            } else if main_match.index == alias_idx {
-                SymbolKind::VARIABLE
-            } else if main_match.index == label_idx {
-                SymbolKind::FUNCTION
-            } else {
-                SymbolKind::FILE
-            };
+            }
+        }
+        self.index_workspace_folders(added).await;
+    }
 
-            let Some(name_node) = matched.nodes_for_capture_index(name_idx).next() else {
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        for change in params.changes {
+            // Open documents are indexed from the editor buffer, not from disk
+            if self.files.contains(&change.uri).await {
+                continue;
+            }
+            let Ok(path) = change.uri.to_file_path() else {
                 continue;
             };
-
-            let name = name_node.utf8_text(document.content.as_bytes()).unwrap();
-            #[allow(deprecated)]
-            ret.push(SymbolInformation {
-                name: name.to_string(),
-                kind,
-                tags: None,
-                deprecated: Some(matched.pattern_index == 2),
-                location: Location::new(uri.clone(), Range::from(name_node.range()).into()),
-                container_name: None,
-            });
+            if !workspace_index::is_ic10_file(&path) {
+                continue;
+            }
+            if change.typ == FileChangeType::DELETED {
+                self.workspace.write().await.remove(&change.uri);
+            } else if let Ok(content) = tokio::fs::read_to_string(&path).await {
+                let symbols = workspace_index::index_text(&change.uri, &content);
+                self.workspace.write().await.insert(change.uri, symbols);
+            }
         }
-        Ok(Some(DocumentSymbolResponse::Flat(ret)))
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
@@ -1306,10 +1315,10 @@ impl LanguageServer for Backend {
             ))
         };
 
-        let files = self.files.read().await;
-        let Some(file_data) = files.get(&uri) else {
+        let Some(file) = self.files.get(&uri).await else {
             return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
+        let file_data = file.data.read().await;
 
         let document = &file_data.document_data;
 
@@ -1417,12 +1426,10 @@ impl LanguageServer for Backend {
                     let start_entries = ret.len();
 
                     // Use comprehensive device registry with fuzzy search
-                    for hash_name in crate::device_hashes::DEVICE_NAME_TO_HASH.keys() {
+                    for (hash_name, hash_value) in crate::hash_utils::all_devices() {
                         // Fuzzy search: match if search text appears anywhere in device name or display name
                         let search_lower = string_text.to_lowercase();
-                        let hash_value = crate::device_hashes::DEVICE_NAME_TO_HASH[hash_name];
-                        let display_name = crate::device_hashes::HASH_TO_DISPLAY_NAME
-                            .get(&hash_value)
+                        let display_name = crate::hash_utils::get_device_name_for_hash(hash_value)
                             .unwrap_or(hash_name);
 
                         let matches = hash_name.to_lowercase().contains(&search_lower)
@@ -1515,10 +1522,10 @@ impl LanguageServer for Backend {
         let uri = params.text_document_position_params.text_document.uri;
         let position = Position::from(params.text_document_position_params.position);
 
-        let files = self.files.read().await;
-        let Some(file_data) = files.get(&uri) else {
+        let Some(file) = self.files.get(&uri).await else {
             return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
+        let file_data = file.data.read().await;
 
         let document = &file_data.document_data;
 
@@ -1619,10 +1626,10 @@ impl LanguageServer for Backend {
     ) -> Result<Option<Vec<CodeActionOrCommand>>> {
         let mut ret = Vec::new();
 
-        let files = self.files.read().await;
-        let Some(file_data) = files.get(&params.text_document.uri) else {
+        let Some(file) = self.files.get(&params.text_document.uri).await else {
             return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
+        let file_data = file.data.read().await;
 
         let document = &file_data.document_data;
         let uri = &document.url;
@@ -1662,56 +1669,46 @@ impl LanguageServer for Backend {
                     }));
                 }
                 LINT_ABSOLUTE_JUMP => {
-                    const REPLACEMENTS: phf::Map<&'static str, &'static str> = phf::phf_map! {
-                        "bdns" => "brdns",
-                        "bdse" => "brdse",
-                        "bap" => "brap",
-                        "bapz" => "brapz",
-                        "beq" => "breq",
-                        "beqz" => "breqz",
-                        "bge" => "brge",
-                        "bgez" => "brgez",
-                        "bgt" => "brgt",
-                        "bgtz" => "brgtz",
-                        "ble" => "brle",
-                        "blez" => "brlez",
-                        "blt" => "brlt",
-                        "bltz" => "brltz",
-                        "bna" => "brna",
-                        "bnaz" => "brnaz",
-                        "bne" => "brne",
-                        "bnez" => "brnez",
-                        "j" => "jr",
-                    };
-
-                    if let Some(node) =
-                        line_node.query("(instruction (operation)@x)", document.content.as_bytes())
-                    {
-                        let text = node.utf8_text(document.content.as_bytes()).unwrap();
-
-                        if let Some(replacement) = REPLACEMENTS.get(text) {
-                            let edit = TextEdit::new(
-                                Range::from(node.range()).into(),
-                                replacement.to_string(),
-                            );
-
+                    let line = line_node.start_position().row as u32;
+                    let defines = &file_data.type_data.defines;
+                    let content = &document.content;
+                    let single = match jump_labels::convert_to_labels(tree, content, defines, |x| {
+                        x.line == line
+                    }) {
+                        Ok(edits) => edits,
+                        Err(reason) => {
                             ret.push(CodeActionOrCommand::CodeAction(CodeAction {
-                                title: format!("Replace with {replacement}"),
+                                title: "Jump to a label instead of a line number".to_string(),
                                 kind: Some(CodeActionKind::QUICKFIX),
                                 diagnostics: Some(vec![diagnostic]),
-                                edit: Some(WorkspaceEdit::new(HashMap::from([(
-                                    uri.clone(),
-                                    vec![edit],
-                                )]))),
-                                command: None,
-                                is_preferred: Some(true),
-                                disabled: None,
-                                data: None,
+                                disabled: Some(CodeActionDisabled { reason }),
+                                ..Default::default()
                             }));
+                            continue 'diagnostics;
                         }
-
-                        break;
+                    };
+                    if single.is_empty() {
+                        continue 'diagnostics;
                     }
+                    let all = jump_labels::convert_to_labels(tree, content, defines, |_| true)
+                        .unwrap_or_default();
+
+                    ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title: "Jump to a label instead of a line number".to_string(),
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), single)]))),
+                        is_preferred: Some(true),
+                        ..Default::default()
+                    }));
+                    ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title: "Convert all line-number jumps to labels".to_string(),
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic]),
+                        edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), all)]))),
+                        is_preferred: Some(false),
+                        ..Default::default()
+                    }));
                 }
                 "register_assigned_not_read" | "register_read_before_assign" => {
                     // Extract register name from diagnostic data
@@ -1793,9 +1790,13 @@ impl LanguageServer for Backend {
         }
 
         // Add instruction-based code actions for enhanced interactivity
-        if let Some(instruction_actions) =
-            additional_features::get_instruction_code_actions(&node, &document.content)
-        {
+        if let Some(instruction_actions) = additional_features::get_instruction_code_actions(
+            uri,
+            tree,
+            &document.content,
+            params.range,
+            &file_data.type_data,
+        ) {
             ret.extend(instruction_actions);
         }
 
@@ -1806,17 +1807,33 @@ impl LanguageServer for Backend {
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
-        let files = self.files.read().await;
-        let Some(file_data) = files.get(&params.text_document_position_params.text_document.uri)
+        let Some(file) = self
+            .files
+            .get(&params.text_document_position_params.text_document.uri)
+            .await
         else {
             return Err(tower_lsp::jsonrpc::Error::internal_error());
         };
+        let file_data = file.data.read().await;
         let document = &file_data.document_data;
         let mut type_data = file_data.type_data.clone();
 
         let position = params.text_document_position_params.position;
 
         if let Some(tree) = document.tree.as_ref() {
+            // Numeric, relative and constant-register jump targets go to the line they reach
+            if let Some(line) = jump_labels::jump_target_at(
+                tree,
+                &document.content,
+                &type_data.aliases,
+                position,
+            ) {
+                let start = tower_lsp::lsp_types::Position::new(line, 0);
+                return Ok(Some(GotoDefinitionResponse::Scalar(Location::new(
+                    document.url.clone(),
+                    tower_lsp::lsp_types::Range::new(start, start),
+                ))));
+            }
             if let Some(node) = self.node_at_position(position.into(), tree) {
                 if node.kind() == "identifier" {
                     let name = node.utf8_text(document.content.as_bytes()).unwrap();
@@ -1827,17 +1844,34 @@ impl LanguageServer for Backend {
                         ))));
                     }
                 }
+                // A device pin goes to the aliases naming it
+                if node.kind() == "device" {
+                    let pin = node.utf8_text(document.content.as_bytes()).unwrap();
+                    let mut locations: Vec<_> = type_data
+                        .aliases
+                        .values()
+                        .filter(|x| matches!(&x.value, AliasValue::Device(d) if d == pin))
+                        .map(|x| Location::new(document.url.clone(), x.range.0))
+                        .collect();
+                    locations.sort_by_key(|x| x.range.start);
+                    if !locations.is_empty() {
+                        return Ok(Some(GotoDefinitionResponse::Array(locations)));
+                    }
+                }
             }
         }
         Ok(None)
     }
 
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
-        let files = self.files.read().await;
-        let Some(file_data) = files.get(&params.text_document_position_params.text_document.uri)
+        let Some(file) = self
+            .files
+            .get(&params.text_document_position_params.text_document.uri)
+            .await
         else {
             return Err(tower_lsp::jsonrpc::Error::internal_error());
         };
+        let file_data = file.data.read().await;
         let document = &file_data.document_data;
         let mut type_data = file_data.type_data.clone();
 
@@ -1990,7 +2024,7 @@ impl LanguageServer for Backend {
                     // Check if this is a register alias and provide value tracking info
                     if let AliasValue::Register(_) = &definition_data.value {
                         // Perform register analysis to get current value information
-                        let mut register_analyzer = additional_features::RegisterAnalyzer::new();
+                        let mut register_analyzer = register_analysis::RegisterAnalyzer::new();
                         if let Some(tree) = document.tree.as_ref() {
                             register_analyzer.analyze_register_usage(
                                 tree,
@@ -2088,7 +2122,7 @@ impl LanguageServer for Backend {
                     let instruction_node = node.find_parent("instruction").unwrap_or(node);
 
                     // Create register analyzer to get operation history
-                    let mut register_analyzer = additional_features::RegisterAnalyzer::new();
+                    let mut register_analyzer = register_analysis::RegisterAnalyzer::new();
                     if let Some(tree) = document.tree.as_ref() {
                         register_analyzer.analyze_register_usage(
                             tree,
@@ -2178,7 +2212,7 @@ impl LanguageServer for Backend {
             }
             "register" => {
                 // Handle direct register hover (e.g., hovering over "r0", "r1", etc.)
-                let mut register_analyzer = additional_features::RegisterAnalyzer::new();
+                let mut register_analyzer = register_analysis::RegisterAnalyzer::new();
                 if let Some(tree) = document.tree.as_ref() {
                     register_analyzer.analyze_register_usage(
                         tree,
@@ -2278,42 +2312,146 @@ impl Backend {
         node
     }
 
-    async fn update_content(&self, uri: Url, mut text: String) {
-        let mut files = self.files.write().await;
+    /// Semantic tokens for an open document, `None` if it has not been parsed.
+    async fn semantic_tokens(
+        &self,
+        uri: &Url,
+        range: Option<LspRange>,
+    ) -> Result<Option<Vec<SemanticToken>>> {
+        let Some(file) = self.files.get(uri).await else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let file_data = file.data.read().await;
+        let Some(tree) = file_data.document_data.tree.as_ref() else {
+            return Ok(None);
+        };
+        Ok(Some(collect_semantic_tokens(&file_data, tree, range)))
+    }
 
-        if !text.ends_with("\n") {
-            text.push('\n');
+    /// Scan `folders` for scripts on a blocking thread and add them to the workspace index.
+    async fn index_workspace_folders(&self, folders: Vec<std::path::PathBuf>) {
+        if folders.is_empty() {
+            return;
+        }
+        let scanned =
+            tokio::task::spawn_blocking(move || workspace_index::scan_folders(&folders))
+                .await
+                .unwrap_or_default();
+        let open = self.files.uris().await;
+        let mut workspace = self.workspace.write().await;
+        for (uri, symbols) in scanned {
+            // Documents opened while scanning already hold fresher symbols
+            if open.contains(&uri) && workspace.contains(&uri) {
+                continue;
+            }
+            workspace.insert(uri, symbols);
         }
-        match files.entry(uri) {
-            std::collections::hash_map::Entry::Vacant(entry) => {
+    }
+
+    async fn update_content(&self, uri: Url, changes: Vec<TextDocumentContentChangeEvent>) {
+        let utf8 = *self.position_encoding.read().await == PositionEncodingKind::UTF8;
+        let (file, created) = self
+            .files
+            .get_or_insert_with(&uri, || {
                 let mut parser = Parser::new();
                 parser
                     .set_language(tree_sitter_ic10::language())
                     .expect("Could not set language");
-                let key = entry.key().clone();
-                entry.insert(FileData {
+                FileData {
                     document_data: DocumentData {
-                        url: key,
-                        tree: parser.parse(&text, None),
-                        content: text,
+                        url: uri.clone(),
+                        content: String::new(),
+                        synthetic_newline: false,
+                        tree: None,
                         parser,
                     },
                     type_data: TypeData::default(),
-                });
+                }
+            })
+            .await;
+        file.bump_revision();
+        let mut file_data = file.data.write().await;
+        let document = &mut file_data.document_data;
+
+        // Ranges refer to the client's buffer, which lacks our trailing newline
+        let mut text = document.content.clone();
+        if document.synthetic_newline {
+            text.pop();
+        }
+        let mut text = text_sync::apply_changes(text, changes, utf8);
+        document.synthetic_newline = !text.ends_with('\n');
+        if document.synthetic_newline {
+            text.push('\n');
+        }
+
+        if created {
+            document.tree = document.parser.parse(&text, None);
+        } else {
+            if let Some(tree) = document.tree.as_mut() {
+                if let Some(edit) = text_sync::input_edit(&document.content, &text) {
+                    tree.edit(&edit);
+                }
+            }
+            document.tree = document.parser.parse(&text, document.tree.as_ref());
+        }
+        document.content = text;
+
+        // Keep the workspace symbol index in step with the editor buffer
+        if let Some(tree) = document.tree.as_ref() {
+            let symbols = collect_document_symbols(&uri, tree, &document.content);
+            self.workspace.write().await.insert(uri.clone(), symbols);
+        }
+    }
+
+    /// Load and activate the data pack in `dir`, unless `--data-pack` already activated one.
+    async fn load_data_pack(&self, dir: &Path) {
+        let message = match ic10lsp::data_pack::DataPack::load(dir) {
+            Ok(pack) => {
+                let summary = pack.summary();
+                if ic10lsp::data_pack::install(pack) {
+                    format!("Loaded data pack {}: {}", dir.display(), summary)
+                } else {
+                    format!("Ignoring data pack {}: one is already loaded", dir.display())
+                }
             }
-            std::collections::hash_map::Entry::Occupied(mut entry) => {
-                let mut entry = entry.get_mut();
-                entry.document_data.tree = entry.document_data.parser.parse(&text, None); // TODO
-                entry.document_data.content = text;
+            Err(message) => {
+                self.client
+                    .show_message(MessageType::WARNING, format!("Data pack not loaded: {}", message))
+                    .await;
+                return;
             }
+        };
+        self.client.log_message(MessageType::INFO, message).await;
+    }
+
+    /// Diagnostics for a script using `@include` or macros, computed on the expanded program and
+    /// mapped back onto the script. `None` if the script uses no directives.
+    async fn preprocessed_diagnostics(&self, uri: &Url) -> Option<Vec<Diagnostic>> {
+        let content = {
+            let file = self.files.get(uri).await?;
+            let file_data = file.data.read().await;
+            file_data.document_data.content.clone()
+        };
+        if !preprocessor::has_directives(&content) {
+            return None;
         }
+        // Definitions still drive hovers and completions in the script itself
+        self.update_definitions(uri, &mut Vec::new()).await;
+
+        let dir = uri
+            .to_file_path()
+            .ok()
+            .and_then(|x| x.parent().map(Path::to_path_buf));
+        let config = self.config.read().await.clone();
+        Some(diagnostics::check_script(uri, &content, dir.as_deref(), &config))
     }
 
     async fn update_definitions(&self, uri: &Url, diagnostics: &mut Vec<Diagnostic>) {
-        let mut files = self.files.write().await;
-        let Some(file_data) = files.get_mut(uri) else {
+        let Some(file) = self.files.get(uri).await else {
             return;
         };
+        let mut file_data = file.data.write().await;
+        let file_data = &mut *file_data;
         let document = &file_data.document_data;
         let type_data = &mut file_data.type_data;
 
@@ -2321,1338 +2459,490 @@ impl Backend {
             type_data.defines.clear();
             type_data.aliases.clear();
             type_data.labels.clear();
+            diagnostics::collect_definitions(
+                &document.url,
+                tree,
+                &document.content,
+                type_data,
+                diagnostics,
+            );
+        }
+    }
 
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(
-                tree_sitter_ic10::language(),
-                "(instruction (operation \"define\"))@define
-                         (instruction (operation \"alias\"))@alias
-                         (instruction (operation \"label\"))@alias
-                         (label (identifier)@label)",
-            )
-            .unwrap();
-
-            let define_idx = query.capture_index_for_name("define").unwrap();
-            let alias_idx = query.capture_index_for_name("alias").unwrap();
-            let label_idx = query.capture_index_for_name("label").unwrap();
-
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
 
-            for (capture, _) in captures {
-                let capture_idx = capture.captures[0].index;
-                if capture_idx == define_idx || capture_idx == alias_idx {
-                    if let Some(name_node) = capture.captures[0].node.child_by_field_name("operand")
-                    {
-                        // Prefer the inner identifier text to avoid whitespace/operand wrapper differences
-                        let name = if let Some(inner) = name_node.child(0) {
-                            inner.utf8_text(document.content.as_bytes()).unwrap()
-                        } else {
-                            name_node.utf8_text(document.content.as_bytes()).unwrap()
-                        }.trim();
-                        let previous_range = {
-                            if let Some(previous) = type_data.defines.get(name) {
-                                Some(previous.range.clone())
-                            } else if let Some(previous) = type_data.aliases.get(name) {
-                                Some(previous.range.clone())
-                            } else {
-                                None
-                            }
-                        };
-                        if let Some(previous_range) = previous_range {
-                            diagnostics.push(Diagnostic::new(
-                                Range::from(name_node.range()).into(),
-                                Some(DiagnosticSeverity::ERROR),
-                                None,
-                                None,
-                                "Duplicate definition".to_string(),
-                                Some(vec![DiagnosticRelatedInformation {
-                                    location: Location::new(
-                                        document.url.clone(),
-                                        previous_range.into(),
-                                    ),
-                                    message: "Previously defined here".to_string(),
-                                }]),
-                                None,
-                            ));
-                            continue;
-                        } else {
-                            let mut cursor = capture.captures[0].node.walk();
-                            let value_node = capture.captures[0]
-                                .node
-                                .children_by_field_name("operand", &mut cursor)
-                                .last();
-
-                            if let Some(value_node) = value_node {
-                                let value =
-                                    value_node.utf8_text(document.content.as_bytes()).unwrap();
-                                if capture.captures[0].index == define_idx {
-                                    // Allow defines to be numeric or function-call / preproc strings / identifiers
-                                    // (e.g. HASH(...) or STR(...)) so user can define hash or string constants.
-                                    let child_kind =
-                                        value_node.child(0).map(|x| x.kind()).unwrap_or("");
-                                    if child_kind != "number"
-                                        && child_kind != "function_call"
-                                        && child_kind != "hash_preproc"
-                                        && child_kind != "preproc_string"
-                                        && child_kind != "identifier"
-                                    {
-                                        continue;
-                                    }
-                                    type_data.defines.insert(
-                                        name.to_owned(),
-                                        DefinitionData::new(
-                                            name_node.range().into(),
-                                            value.to_string().into(),
-                                        ),
-                                    );
-                                } else if capture.captures[0].index == alias_idx {
-                                    if value_node
-                                        .child(0)
-                                        .map(|x| x.kind())
-                                        .map_or(false, |x| x != "register" && x != "device_spec")
-                                    {
-                                        continue;
-                                    }
-                                    type_data.aliases.insert(
-                                        name.to_owned(),
-                                        DefinitionData::new(
-                                            name_node.range().into(),
-                                            value.to_owned().into(),
-                                        ),
-                                    );
-                                }
-                            }
-                        }
-                    }
-                } else if capture_idx == label_idx {
-                    let name_node = capture.captures[0].node;
-                    let name = name_node.utf8_text(document.content.as_bytes()).unwrap();
-                    if let Some(previous) = type_data.get_range(name) {
-                        diagnostics.push(Diagnostic::new(
-                            Range::from(name_node.range()).into(),
-                            Some(DiagnosticSeverity::ERROR),
-                            None,
-                            None,
-                            "Duplicate definition".to_string(),
-                            Some(vec![DiagnosticRelatedInformation {
-                                location: Location::new(document.url.clone(), previous.into()),
-                                message: "Previously defined here".to_string(),
-                            }]),
-                            None,
-                        ));
-                        continue;
-                    }
-                    type_data.labels.insert(
-                        name.to_owned(),
-                        DefinitionData {
-                            range: name_node.range().into(),
-                            value: name_node.start_position().row as u8,
-                        },
-                    );
-                }
-                //println!("{:#?}", capture);
-            }
-            // println!("{:#?}", type_data.defines);
-            // println!("{:#?}", type_data.aliases);
-            // println!("{:#?}", type_data.labels);
+    /// Re-run diagnostics for every open document, or ask a pulling client to fetch them again.
+    async fn refresh_diagnostics(&self) {
+        if *self.pull_diagnostics.read().await {
+            let _ = self
+                .client
+                .send_request::<request::WorkspaceDiagnosticRefresh>(())
+                .await;
+            return;
+        }
+        for uri in self.files.uris().await {
+            self.run_diagnostics(&uri).await;
         }
     }
 
-    async fn check_types(&self, uri: &Url, diagnostics: &mut Vec<Diagnostic>) {
-        let files = self.files.read().await;
-        let Some(file_data) = files.get(uri) else {
+    /// Update definitions now and publish diagnostics once edits pause. A run still pending
+    /// for an earlier revision is aborted, and a run that finishes after a newer edit is dropped.
+    async fn schedule_diagnostics(&self, uri: Url) {
+        // Hovers and completions read the definitions, so they are never deferred
+        self.update_definitions(&uri, &mut Vec::new()).await;
+        if *self.pull_diagnostics.read().await {
             return;
-        };
-        let document = &file_data.document_data;
-        let mut type_data = file_data.type_data.clone();
-
-        let Some(tree) = document.tree.as_ref() else {
+        }
+        let Some(file) = self.files.get(&uri).await else {
             return;
         };
 
-        // Read config before the loop to avoid await across non-Send types
-        let suppress_hash_diagnostics = self.config.read().await.suppress_hash_diagnostics;
-        self.client.log_message(MessageType::INFO, format!("Running diagnostics with suppress_hash_diagnostics: {}", suppress_hash_diagnostics)).await;
-
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(tree_sitter_ic10::language(), "(instruction)@a").unwrap();
-
-        let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
+        let revision = file.revision();
+        let backend = self.clone();
+        let document = file.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(DIAGNOSTICS_DEBOUNCE).await;
+            let diagnostics = backend.compute_diagnostics(&uri).await;
+            if document.revision() == revision {
+                backend
+                    .client
+                    .publish_diagnostics(uri, diagnostics, None)
+                    .await;
+            }
+        });
+        file.replace_pending_diagnostics(task);
+    }
 
-        // Build register analyzer (for device-id awareness & prior value kinds)
-        let mut register_analyzer = additional_features::RegisterAnalyzer::new();
-        register_analyzer.analyze_register_usage(tree, &document.content, &type_data.aliases);
+    async fn run_diagnostics(&self, uri: &Url) {
+        // A pulling client asks for diagnostics itself; only keep the definitions current
+        if *self.pull_diagnostics.read().await {
+            self.update_definitions(uri, &mut Vec::new()).await;
+            return;
+        }
+        let diagnostics = self.compute_diagnostics(uri).await;
+        self.client
+            .publish_diagnostics(uri.to_owned(), diagnostics, None)
+            .await;
+    }
 
-        for (capture, _) in captures {
-            let capture = capture.captures[0].node;
+    async fn document_diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult> {
+        let diagnostics = self.compute_diagnostics(&params.text_document.uri).await;
+        Ok(pull_diagnostics::document_report(
+            diagnostics,
+            params.previous_result_id.as_deref(),
+        ))
+    }
 
-            if let Some(operation_node) = capture.child_by_field_name("operation") {
-                let operation = operation_node
-                    .utf8_text(document.content.as_bytes())
-                    .unwrap();
-                let Some(signature) = instructions::INSTRUCTIONS.get(operation) else {
-                    diagnostics.push(Diagnostic::new(
-                        Range::from(operation_node.range()).into(),
-                        Some(DiagnosticSeverity::ERROR),
-                        None,
-                        None,
-                        format!("Invalid instruction"),
-                        None,
-                        None,
+    /// Diagnostics for the workspace scripts that are not open; open ones are pulled per document.
+    async fn workspace_diagnostic(
+        &self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult> {
+        let previous: HashMap<Url, String> = params
+            .previous_result_ids
+            .into_iter()
+            .map(|x| (x.uri, x.value))
+            .collect();
+        let folders = self.workspace.read().await.folders().to_vec();
+        let enabled = *self.diagnostics_enabled.read().await;
+        let config = self.config.read().await.clone();
+
+        let open = self.files.uris().await;
+        let cache = self.workspace_diagnostics.clone();
+
+        // Walking the folders and reading files blocks, so keep it off the async workers
+        let items = tokio::task::spawn_blocking(move || {
+            let mut cache = cache.blocking_write();
+            let mut items = Vec::new();
+            for folder in folders {
+                for path in workspace_index::find_ic10_files(&folder) {
+                    let Ok(uri) = Url::from_file_path(&path) else {
+                        continue;
+                    };
+                    if open.contains(&uri) {
+                        continue;
+                    }
+                    let diagnostics = if enabled {
+                        let Some(diagnostics) = cache.check(&path, &uri, &config) else {
+                            continue;
+                        };
+                        diagnostics
+                    } else {
+                        Vec::new()
+                    };
+                    let previous = previous.get(&uri).map(String::as_str);
+                    items.push(pull_diagnostics::workspace_report(
+                        uri,
+                        diagnostics,
+                        previous,
                     ));
-                    continue;
-                };
-
-                let mut argument_count = 0;
-                let mut tree_cursor = capture.walk();
-                let operands = capture.children_by_field_name("operand", &mut tree_cursor);
-                let mut parameters = signature.0.iter();
+                }
+            }
+            items
+        })
+        .await
+        .unwrap_or_default();
+        Ok(WorkspaceDiagnosticReportResult::Report(
+            WorkspaceDiagnosticReport { items },
+        ))
+    }
 
-                let mut first_superfluous_arg = None;
-                let mut pending_define_name: Option<(String, Range)> = None;
+    async fn compute_diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        if !*self.diagnostics_enabled.read().await {
+            return Vec::new();
+        }
+        if let Some(diagnostics) = self.preprocessed_diagnostics(uri).await {
+            return diagnostics;
+        }
 
-                for operand in operands {
-                    argument_count = argument_count + 1;
-                    let Some(parameter) = parameters.next() else {
-                        if first_superfluous_arg.is_none() {
-                            first_superfluous_arg = Some(operand);
-                        }
-                        continue;
-                    };
+        // Hovers and completions read the definitions collected here
+        self.update_definitions(uri, &mut Vec::new()).await;
 
-                    let operand_kind = operand.named_child(0).unwrap().kind();
-                    let expects_name = parameter.match_type(DataType::Name);
-                    // Keep track of an underlying register name if this operand ultimately refers to a register
-                    // (either directly or via alias). We'll use this to permit DeviceId registers where Device is expected.
-                    let mut underlying_register: Option<String> = None;
-                    let typ = match operand_kind {
-                        "register" => {
-                            // Direct register
-                            if let Some(reg_text) = operand
-                                .named_child(0)
-                                .map(|n| n.utf8_text(document.content.as_bytes()).unwrap_or(""))
-                            {
-                                underlying_register = Some(reg_text.to_string());
-                            }
-                            instructions::Union(&[DataType::Register])
-                        }
-                        "device_spec" => instructions::Union(&[DataType::Device]),
-                        "number" => instructions::Union(&[DataType::Number]),
-                        "logictype" => {
-                            let ident = operand
-                                .named_child(0)
-                                .unwrap()
-                                .utf8_text(document.content.as_bytes())
-                                .unwrap();
-                            let flags = classify_exact_keyword(ident);
-                            if flags.any() {
-                                flags.to_union()
-                            } else {
-                                instructions::Union(&[])
-                            }
-                        }
-                        "identifier" => {
-                            let ident_node = operand.named_child(0).unwrap();
-                            let ident = ident_node
-                                .utf8_text(document.content.as_bytes())
-                                .unwrap();
-
-                            // First operand of a DEFINE is always the define name; remember it and never treat as unknown
-                            if operation.eq_ignore_ascii_case("define") && argument_count == 1 {
-                                pending_define_name = Some((
-                                    ident.to_string(),
-                                    Range::from(ident_node.range()).into(),
-                                ));
-                                instructions::Union(&NAME_ONLY)
-                            } else
-
-                            // Accept fully-qualified enum names like Family.Member as numeric identifiers (case-insensitive)
-                            if expects_name {
-                                instructions::Union(&NAME_ONLY)
-                            } else if ident.contains('.') {
-                                if let Some((canonical, _val, _desc, _dep)) =
-                                    instructions::enum_info_case_insensitive(ident)
-                                {
-                                    if canonical != ident {
-                                        diagnostics.push(Diagnostic::new(
-                                            Range::from(operand.range()).into(),
-                                            Some(DiagnosticSeverity::WARNING),
-                                            None,
-                                            None,
-                                            format!(
-                                                "Enum '{}' differs in case from canonical '{}'.",
-                                                ident, canonical
-                                            ),
-                                            None,
-                                            None,
-                                        ));
-                                    }
-                                    instructions::Union(&[DataType::Number])
-                                } else if type_data.defines.contains_key(ident)
-                                    || type_data.labels.contains_key(ident)
-                                {
-                                    // Fully-qualified define/label; treat as numeric identifier
-                                    instructions::Union(&[DataType::Number])
-                                } else if let Some((canonical, _)) = type_data
-                                    .defines
-                                    .keys()
-                                    .find(|k| k.eq_ignore_ascii_case(ident))
-                                    .map(|k| (k.clone(), ()))
-                                {
-                                    if canonical != ident {
-                                        diagnostics.push(Diagnostic::new(
-                                            Range::from(operand.range()).into(),
-                                            Some(DiagnosticSeverity::WARNING),
-                                            None,
-                                            None,
-                                            format!(
-                                                "Define '{}' differs in case from canonical '{}'.",
-                                                ident, canonical
-                                            ),
-                                            None,
-                                            None,
-                                        ));
-                                    }
-                                    instructions::Union(&[DataType::Number])
-                                } else if let Some(type_data_val) = type_data.aliases.get(ident) {
-                                    match type_data_val.value {
-                                        AliasValue::Device(_) => {
-                                            instructions::Union(&[DataType::Device])
-                                        }
-                                        AliasValue::Register(ref reg_name) => {
-                                            underlying_register = Some(reg_name.clone());
-                                            instructions::Union(&[DataType::Register])
-                                        }
-                                    }
-                                } else {
-                                    // fall through to case-insensitive logic checks below
-                                    instructions::Union(&[])
-                                }
-                            }
-                            // Prefer user-defined identifiers (defines/labels/aliases) over reserved keywords
-                            else if type_data.defines.contains_key(ident)
-                                || type_data.labels.contains_key(ident)
-                            {
-                                // User-defined identifier (define/label) always resolves; value may be HASH(...) or number
-                                instructions::Union(&[DataType::Number])
-                            } else if let Some((canonical, _)) = type_data
-                                .defines
-                                .keys()
-                                .find(|k| k.eq_ignore_ascii_case(ident))
-                                .map(|k| (k.clone(), ()))
-                            {
-                                if canonical != ident {
-                                    diagnostics.push(Diagnostic::new(
-                                        Range::from(operand.range()).into(),
-                                        Some(DiagnosticSeverity::WARNING),
-                                        None,
-                                        None,
-                                        format!(
-                                            "Define '{}' differs in case from canonical '{}'.",
-                                            ident, canonical
-                                        ),
-                                        None,
-                                        None,
-                                    ));
-                                }
-                                instructions::Union(&[DataType::Number])
-                            } else if let Some(type_data_val) = type_data.aliases.get(ident) {
-                                match type_data_val.value {
-                                    AliasValue::Device(_) => {
-                                        instructions::Union(&[DataType::Device])
-                                    }
-                                    AliasValue::Register(ref reg_name) => {
-                                        // Alias points at a register; remember for DeviceId substitution
-                                        underlying_register = Some(reg_name.clone());
-                                        instructions::Union(&[DataType::Register])
-                                    }
-                                }
-                            } else {
-                                let exact_flags = classify_exact_keyword(ident);
-                                if exact_flags.any() {
-                                    exact_flags.to_union()
-                                } else {
-                                    let ci_flags = classify_ci_keyword(ident);
-                                    if ci_flags.any() {
-                                        diagnostics.push(Diagnostic::new(
-                                        Range::from(operand.range()).into(),
-                                        Some(DiagnosticSeverity::WARNING),
-                                        None,
-                                        None,
-                                        format!("Identifier '{}' matches a known logic/parameter type by name but differs by case. Consider using proper case or renaming your identifier.", ident),
-                                        None,
-                                        None,
-                                    ));
-                                        ci_flags.to_union()
-                                    } else {
-                                        diagnostics.push(Diagnostic::new(
-                                            Range::from(operand.range()).into(),
-                                            Some(DiagnosticSeverity::ERROR),
-                                            None,
-                                            None,
-                                            format!("Unknown identifier"),
-                                            None,
-                                            None,
-                                        ));
-                                        continue;
-                                    }
-                                }
-                            }
-                        }
-                        "function_call" | "hash_preproc" => {
-                            // Treat HASH("...") and similar constant-producing functions as numbers
-                            let call_text =
-                                operand.utf8_text(document.content.as_bytes()).unwrap();
-                            if is_hash_function_call(call_text) {
-                                // Optional: if known device name, we can warn on case differences or unknown names
-                                if let Some(name) = extract_hash_argument(call_text) {
-                                    if let Some(_) = get_device_hash(name.as_str()) {
-                                        // Known device name; optionally could inlay the numeric value
-                                    } else {
-                                        // Unknown device string; still treat as number but nudge (unless suppressed)
-                                        if !suppress_hash_diagnostics {
-                                            diagnostics.push(Diagnostic::new(
-                                                Range::from(operand.range()).into(),
-                                                Some(DiagnosticSeverity::INFORMATION),
-                                                None,
-                                                None,
-                                                format!("Unrecognized device name '{}' in HASH(...). Will be treated as number.", name),
-                                                None,
-                                                None,
-                                            ));
-                                        }
-                                    }
-                                }
-                                instructions::Union(&[DataType::Number])
-                            } else {
-                                // Unknown function: conservatively treat as number to avoid spurious errors
-                                instructions::Union(&[DataType::Number])
-                            }
-                        }
-                        _ => {
-                            continue;
-                        }
-                    };
-                    // Special case: register (direct or via alias) holding DeviceId or Unknown can satisfy a Device parameter
-                    // Special case: register holding LogicType or Unknown can satisfy a LogicType parameter
-                    let mut effective_typ = typ;
-                    if parameter.match_type(DataType::Device) {
-                        if let Some(reg_name) = underlying_register.as_ref() {
-                            let kind = register_analyzer.get_register_kind(reg_name);
-                            if kind == additional_features::ValueKind::DeviceId
-                                || kind == additional_features::ValueKind::Unknown
-                            {
-                                effective_typ = instructions::Union(&[DataType::Device]);
-                            }
-                        }
-                    } else if parameter.match_type(DataType::LogicType) || parameter.match_type(DataType::SlotLogicType) {
-                        if let Some(reg_name) = underlying_register.as_ref() {
-                            let kind = register_analyzer.get_register_kind(reg_name);
-                            // LogicTypes are numeric constants, so Number/LogicType/Unknown can all satisfy LogicType parameters
-                            if kind == additional_features::ValueKind::LogicType
-                                || kind == additional_features::ValueKind::Number
-                                || kind == additional_features::ValueKind::Unknown
-                            {
-                                // Register holds a numeric/LogicType value, so it can be used where LogicType is expected
-                                if parameter.match_type(DataType::LogicType) {
-                                    effective_typ = instructions::Union(&[DataType::LogicType]);
-                                } else {
-                                    effective_typ = instructions::Union(&[DataType::SlotLogicType]);
-                                }
-                            }
-                        }
-                    }
-                    // Allow define name second operand to be register when signature expects Number|Register already (adjusted in INSTRUCTIONS)
-                    if !parameter.match_union(&effective_typ) {
-                        diagnostics.push(Diagnostic::new(
-                            Range::from(operand.range()).into(),
-                            Some(DiagnosticSeverity::ERROR),
-                            None,
-                            None,
-                            format!(
-                                "Type mismatch. Found {}, expected {}",
-                                effective_typ, parameter
-                            ),
-                            None,
-                            None,
-                        ));
-                    }
-
-                    // After processing the second operand of DEFINE, store it in the working define map
-                    if operation.eq_ignore_ascii_case("define") && argument_count == 2 {
-                        if let Some((define_name, define_range)) = pending_define_name.clone() {
-                            let value_text = operand
-                                .utf8_text(document.content.as_bytes())
-                                .unwrap()
-                                .trim()
-                                .to_string();
-                            type_data.defines.insert(
-                                define_name,
-                                DefinitionData::new(define_range, value_text.into()),
-                            );
-                        }
-                    }
-                }
-                if argument_count > signature.0.len() {
-                    let plural_str = if argument_count - signature.0.len() > 1 {
-                        "s"
-                    } else {
-                        ""
-                    };
-
-                    diagnostics.push(Diagnostic::new(
-                        tower_lsp::lsp_types::Range::new(
-                            Position::from(first_superfluous_arg.unwrap().start_position()).into(),
-                            Position::from(capture.end_position()).into(),
-                        ),
-                        Some(DiagnosticSeverity::ERROR),
-                        None,
-                        None,
-                        format!(
-                            "Superfluous argument{}. '{}' only requires {} arguments.",
-                            plural_str,
-                            operation,
-                            signature.0.len()
-                        ),
-                        None,
-                        None,
-                    ));
-                    continue;
-                }
-                if argument_count != signature.0.len() {
-                    diagnostics.push(Diagnostic::new(
-                        Range::from(capture.range()).into(),
-                        Some(DiagnosticSeverity::ERROR),
-                        None,
-                        None,
-                        "Invalid number of arguments".to_string(),
-                        None,
-                        None,
-                    ));
-                }
-            }
-        }
-    }
-
-    async fn run_diagnostics(&self, uri: &Url) {
-        // If diagnostics disabled, clear and bail
-        if !*self.diagnostics_enabled.read().await {
-            self.client
-                .publish_diagnostics(uri.clone(), vec![], None)
-                .await;
-            return;
-        }
-        let mut diagnostics = Vec::new();
-
-        // Collect definitions
-        self.update_definitions(uri, &mut diagnostics).await;
-
-        let config = self.config.read().await;
-        let files = self.files.read().await;
-        let Some(file_data) = files.get(uri) else {
-            return;
+        let config = self.config.read().await.clone();
+        self.client.log_message(MessageType::INFO, format!("Running diagnostics with suppress_hash_diagnostics: {}", config.suppress_hash_diagnostics)).await;
+        let Some(file) = self.files.get(uri).await else {
+            return Vec::new();
         };
-
+        let file_data = file.data.read().await;
         let document = &file_data.document_data;
         let Some(tree) = document.tree.as_ref() else {
-            return;
+            return Vec::new();
         };
+        diagnostics::check(&document.url, tree, &document.content, &config)
+    }
+}
 
-        // Syntax errors
-        {
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(tree_sitter_ic10::language(), "(ERROR)@error").unwrap();
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
-            for (capture, _) in captures {
-                diagnostics.push(Diagnostic::new(
-                    Range::from(capture.captures[0].node.range()).into(),
-                    Some(DiagnosticSeverity::ERROR),
-                    None,
-                    None,
-                    "Syntax error".to_string(),
-                    None,
-                    None,
-                ));
-            }
-        }
+/// Encode the semantic tokens of a document, optionally only those overlapping `range`.
+fn collect_semantic_tokens(
+    file_data: &FileData,
+    tree: &Tree,
+    range: Option<LspRange>,
+) -> Vec<SemanticToken> {
+    let mut ret = Vec::new();
+    let document = &file_data.document_data;
+
+    let mut cursor = QueryCursor::new();
+    if let Some(range) = range {
+        cursor
+            .set_point_range(Position::from(range.start).into()..Position::from(range.end).into());
+    }
+    let query = Query::new(
+        tree_sitter_ic10::language(),
+        "(comment) @comment
+         (instruction (operation)@keyword)
+         (instruction (invalid_instruction)@invalid_keyword)
+         (logictype)@string
+         (device)@preproc
+         (register)@macro
+         (number)@float
+         (identifier)@variable",
+    )
+    .unwrap();
+
+    let mut previous_line = 0u32;
+    let mut previous_col = 0u32;
+
+    let comment_idx = query.capture_index_for_name("comment").unwrap();
+    let keyword_idx = query.capture_index_for_name("keyword").unwrap();
+    let invalid_keyword_idx = query.capture_index_for_name("invalid_keyword").unwrap();
+    let string_idx = query.capture_index_for_name("string").unwrap();
+    let preproc_idx = query.capture_index_for_name("preproc").unwrap();
+    let macro_idx = query.capture_index_for_name("macro").unwrap();
+    let float_idx = query.capture_index_for_name("float").unwrap();
+    let variable_idx = query.capture_index_for_name("variable").unwrap();
+
+    for (capture, _) in cursor.captures(&query, tree.root_node(), document.content.as_bytes()) {
+        let node = capture.captures[0].node;
+        let idx = capture.captures[0].index;
+        let start = node.range().start_point;
+
+        let delta_line = start.row as u32 - previous_line;
+        let delta_start = if delta_line == 0 {
+            start.column as u32 - previous_col
+        } else {
+            start.column as u32
+        };
 
-        // Find invalid instructions
-        {
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(
-                tree_sitter_ic10::language(),
-                "(instruction (invalid_instruction)@error)",
-            )
-            .unwrap();
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
-            for (capture, _) in captures {
-                let node = capture.captures[0].node;
+        let tokentype = {
+            if idx == comment_idx {
+                SemanticTokenType::COMMENT
+            } else if idx == keyword_idx {
+                SemanticTokenType::KEYWORD
+            } else if idx == invalid_keyword_idx {
                 let instruction_text = node.utf8_text(document.content.as_bytes()).unwrap();
-                if !instructions::INSTRUCTIONS.contains_key(instruction_text) {
-                    diagnostics.push(Diagnostic::new(
-                        Range::from(node.range()).into(),
-                        Some(DiagnosticSeverity::ERROR),
-                        None,
-                        None,
-                        "Invalid instruction".to_string(),
-                        None,
-                        None,
-                    ));
+                if instructions::INSTRUCTIONS.contains_key(instruction_text) {
+                    SemanticTokenType::KEYWORD
+                } else {
+                    continue;
                 }
-            }
-        }
-
-        // Type check
-        self.check_types(uri, &mut diagnostics).await;
-
-        // Overlength checks
-        {
-            let mut cursor = QueryCursor::new();
-
-            let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
-            for (capture, _) in
-                cursor.captures(&query, tree.root_node(), document.content.as_bytes())
-            {
-                let node = capture.captures[0].node;
-                if node.end_position().column > config.max_columns {
-                    diagnostics.push(Diagnostic {
-                        range: LspRange::new(
-                            LspPosition::new(
-                                node.end_position().row as u32,
-                                config.max_columns as u32,
-                            ),
-                            Position::from(node.end_position()).into(),
-                        ),
-                        severity: Some(DiagnosticSeverity::ERROR),
-                        message: format!("Instruction past column {}", config.max_columns),
-                        ..Default::default()
-                    });
+            } else if idx == string_idx {
+                SemanticTokenType::STRING
+            } else if idx == preproc_idx {
+                SemanticTokenType::FUNCTION
+            } else if idx == macro_idx {
+                SemanticTokenType::MACRO
+            } else if idx == float_idx {
+                SemanticTokenType::NUMBER
+            } else if idx == variable_idx {
+                // Classify identifiers: labels -> TYPE (purple), enums -> ENUM, otherwise VARIABLE
+                let ident_text = node.utf8_text(document.content.as_bytes()).unwrap_or("");
+                // Reconstruct fully-qualified enum token if this identifier is part of a dotted operand
+                let mut qualified_operand: Option<String> = None;
+                if let Some(parent) = node.parent() {
+                    if parent.kind() == "operand" {
+                        if let Ok(full) = parent.utf8_text(document.content.as_bytes()) {
+                            // Trim trailing comment or whitespace artifacts
+                            let full_trim = full.split('#').next().unwrap_or(full).trim();
+                            if full_trim.contains('.') {
+                                qualified_operand = Some(full_trim.to_string());
+                            }
+                        }
+                    }
                 }
-            }
-
-            if config.warn_overcolumn_comment {
-                let query = Query::new(tree_sitter_ic10::language(), "(comment)@x").unwrap();
-                for (capture, _) in
-                    cursor.captures(&query, tree.root_node(), document.content.as_bytes())
-                {
-                    let node = capture.captures[0].node;
-                    if node.end_position().column > config.max_columns {
-                        diagnostics.push(Diagnostic {
-                            range: LspRange::new(
-                                LspPosition::new(
-                                    node.end_position().row as u32,
-                                    config.max_columns as u32,
-                                ),
-                                Position::from(node.end_position()).into(),
-                            ),
-                            severity: Some(DiagnosticSeverity::WARNING),
-                            message: format!("Comment past column {}", config.max_columns),
-                            ..Default::default()
-                        });
+                // Determine if this identifier is a branch/jump label reference even if forward‑declared.
+                let mut branch_label_reference = false;
+                if !file_data.type_data.labels.contains_key(ident_text) {
+                    // Only attempt contextual detection if not already a known label definition.
+                    if let Some(operand_parent) = node.parent() {
+                        if operand_parent.kind() == "operand" {
+                            if let Some(instr_parent) = operand_parent.parent() {
+                                if instr_parent.kind() == "instruction" {
+                                    if let Some(op_node) =
+                                        instr_parent.child_by_field_name("operation")
+                                    {
+                                        if let Ok(op_text) =
+                                            op_node.utf8_text(document.content.as_bytes())
+                                        {
+                                            // Classify branch/jump mnemonics for positional label operands.
+                                            // Two groups: (a,b,label) form and (a,label) form; plus single‑operand j/jal.
+                                            static THREE_OPERAND_BRANCHES: phf::Set<&'static str> = phf_set!(
+                                                "beq", "bne", "blt", "bgt", "ble", "bge", "breq",
+                                                "brne", "brlt", "brgt", "brle", "brge", "beqal",
+                                                "bneal", "bltal", "bgtal", "bleal", "bgeal"
+                                            );
+                                            static TWO_OPERAND_BRANCHES: phf::Set<&'static str> = phf_set!(
+                                                "beqz", "bnez", "bltz", "bgtz", "blez", "bgez",
+                                                "breqz", "brnez", "brltz", "brgtz", "brlez",
+                                                "brgez", "beqzal", "bnezal", "bltzal", "bgtzal",
+                                                "blezal", "bgezal"
+                                            );
+                                            static SINGLE_OPERAND_JUMPS: phf::Set<&'static str> =
+                                                phf_set!("j", "jal");
+
+                                            // Count operand index for this identifier within the instruction.
+                                            let mut w = instr_parent.walk();
+                                            let operands: Vec<_> = instr_parent
+                                                .children_by_field_name("operand", &mut w)
+                                                .collect();
+                                            let operand_index = operands
+                                                .iter()
+                                                .position(|o| o.id() == operand_parent.id());
+                                            if let Some(idx_op) = operand_index {
+                                                let op_lower = op_text.to_ascii_lowercase();
+                                                if THREE_OPERAND_BRANCHES
+                                                    .contains(op_lower.as_str())
+                                                {
+                                                    // label is last (third) operand
+                                                    if idx_op == 2 {
+                                                        branch_label_reference = true;
+                                                    }
+                                                } else if TWO_OPERAND_BRANCHES
+                                                    .contains(op_lower.as_str())
+                                                {
+                                                    // label is second operand
+                                                    if idx_op == 1 {
+                                                        branch_label_reference = true;
+                                                    }
+                                                } else if SINGLE_OPERAND_JUMPS
+                                                    .contains(op_lower.as_str())
+                                                {
+                                                    // label is sole operand
+                                                    if idx_op == 0 {
+                                                        branch_label_reference = true;
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
                     }
                 }
-            }
-
-            cursor.set_point_range(
-                tree_sitter::Point::new(config.max_lines, 0)
-                    ..tree_sitter::Point::new(usize::MAX, usize::MAX),
-            );
-            let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
-
-            for (capture, _) in
-                cursor.captures(&query, tree.root_node(), document.content.as_bytes())
-            {
-                let node = capture.captures[0].node;
-                diagnostics.push(Diagnostic {
-                    range: Range::from(node.range()).into(),
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    message: format!("Instruction past line {}", config.max_lines),
-                    ..Default::default()
-                });
-            }
-
-            if config.warn_overline_comment {
-                let query = Query::new(tree_sitter_ic10::language(), "(comment)@x").unwrap();
-                for (capture, _) in
-                    cursor.captures(&query, tree.root_node(), document.content.as_bytes())
+                if file_data.type_data.labels.contains_key(ident_text) || branch_label_reference {
+                    SemanticTokenType::TYPE
+                } else if ident_text.contains('.')
+                    && ic10lsp::instructions::enum_info_case_insensitive(ident_text).is_some()
                 {
-                    let node = capture.captures[0].node;
-                    diagnostics.push(Diagnostic {
-                        range: Range::from(node.range()).into(),
-                        severity: Some(DiagnosticSeverity::WARNING),
-                        message: format!("Comment past line {}", config.max_lines),
-                        ..Default::default()
-                    });
+                    SemanticTokenType::ENUM
+                } else if let Some(full) = qualified_operand.as_ref() {
+                    // If the full operand is an enum qualified name (e.g., TraderInstruction.WriteTraderData)
+                    // color both identifiers as ENUM tokens
+                    if ic10lsp::instructions::enum_info_case_insensitive(full).is_some() {
+                        SemanticTokenType::ENUM
+                    } else {
+                        SemanticTokenType::VARIABLE
+                    }
+                } else {
+                    SemanticTokenType::VARIABLE
                 }
+            } else {
+                continue;
             }
-        }
+        };
 
-        // Byte size check
+        // Aliases are coloured by what they stand for
+        let tokentype = match file_data
+            .type_data
+            .aliases
+            .get(node.utf8_text(document.content.as_bytes()).unwrap_or(""))
         {
-            let mut byte_count = 0;
-            let mut start_pos: Option<LspPosition> = None;
-            let mut current_line = 0;
-            let mut current_col = 0;
+            Some(alias) if tokentype == SemanticTokenType::VARIABLE => match alias.value {
+                AliasValue::Device(_) => DEVICE_ALIAS_TOKEN,
+                AliasValue::Register(_) => REGISTER_ALIAS_TOKEN,
+            },
+            _ => tokentype,
+        };
 
-            for char in document.content.chars() {
-                let char_len = if char == '\n' { 2 } else { 1 };
+        ret.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: node.range().end_point.column as u32 - start.column as u32,
+            token_type: SEMANTIC_SYMBOL_LEGEND
+                .iter()
+                .position(|x| *x == tokentype)
+                .unwrap() as u32,
+            token_modifiers_bitset: semantic_token_modifiers(
+                node,
+                &tokentype,
+                &file_data.type_data,
+                &document.content,
+            ),
+        });
 
-                if byte_count <= config.max_bytes && byte_count + char_len > config.max_bytes {
-                    if start_pos.is_none() {
-                        start_pos = Some(LspPosition::new(current_line, current_col));
-                    }
-                }
-                byte_count += char_len;
+        previous_line = start.row as u32;
+        previous_col = start.column as u32;
+    }
+    ret
+}
 
-                if char == '\n' {
-                    current_line += 1;
-                    current_col = 0;
-                } else {
-                    current_col += 1;
-                }
+/// Modifier bits for a token, indexed by `SEMANTIC_MODIFIER_LEGEND`.
+fn semantic_token_modifiers(
+    node: Node,
+    tokentype: &SemanticTokenType,
+    type_data: &TypeData,
+    content: &str,
+) -> u32 {
+    let text = node.utf8_text(content.as_bytes()).unwrap_or("");
+    let mut modifiers = Vec::new();
+    match node.kind() {
+        "identifier" => {
+            let declaration = node.parent().is_some_and(|parent| {
+                parent.kind() == "label"
+                    || parent.parent().is_some_and(|instruction| {
+                        references::is_declaration_name(instruction, parent, content)
+                    })
+            });
+            if declaration {
+                modifiers.push(SemanticTokenModifier::DECLARATION);
             }
-
-            if byte_count > config.max_bytes {
-                let end_line = document.content.lines().count().saturating_sub(1) as u32;
-                let end_col = document.content.lines().last().map_or(0, |l| l.len()) as u32;
-
-                diagnostics.push(Diagnostic {
-                    range: LspRange::new(
-                        start_pos.unwrap_or_else(|| LspPosition::new(end_line, 0)),
-                        LspPosition::new(end_line, end_col),
-                    ),
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    message: format!(
-                        "Script size ({} bytes) exceeds the maximum limit of {} bytes.",
-                        byte_count, config.max_bytes
-                    ),
-                    ..Default::default()
-                });
+            if type_data.defines.contains_key(text) {
+                modifiers.push(SemanticTokenModifier::READONLY);
             }
-        }
-
-        // Absolute jump to number lint
-        {
-            const BRANCH_INSTRUCTIONS: phf::Set<&'static str> = phf_set!(
-                "bdns", "bdnsal", "bdse", "bdseal", "bap", "bapz", "bapzal", "beq", "beqal",
-                "beqz", "beqzal", "bge", "bgeal", "bgez", "bgezal", "bgt", "bgtal", "bgtz",
-                "bgtzal", "ble", "bleal", "blez", "blezal", "blt", "bltal", "bltz", "bltzal",
-                "bna", "bnaz", "bnazal", "bne", "bneal", "bnez", "bnezal", "j", "jal"
-            );
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(
-                tree_sitter_ic10::language(),
-                "(instruction operand: (operand (number))) @x",
-            )
-            .unwrap();
-            let mut tree_cursor = tree.walk();
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
-            for (capture, _) in captures {
-                let capture = capture.captures[0].node;
-                let Some(operation_node) = capture.child_by_field_name("operation") else {
-                    continue;
-                };
-                let operation = operation_node
-                    .utf8_text(document.content.as_bytes())
-                    .unwrap();
-                if !BRANCH_INSTRUCTIONS.contains(operation) {
-                    continue;
-                }
-
-                tree_cursor.reset(capture);
-                let Some(last_operand) = capture
-                    .children_by_field_name("operand", &mut tree_cursor)
-                    .into_iter()
-                    .last()
-                else {
-                    continue;
-                };
-                if let Some(last_operand) = last_operand.child(0) {
-                    if last_operand.kind() == "number" {
-                        diagnostics.push(Diagnostic::new(
-                            Range::from(capture.range()).into(),
-                            Some(DiagnosticSeverity::WARNING),
-                            Some(NumberOrString::String(LINT_ABSOLUTE_JUMP.to_string())),
-                            None,
-                            "Absolute jump to line number".to_string(),
-                            None,
-                            None,
-                        ));
-                    }
+            if *tokentype == SemanticTokenType::ENUM {
+                let qualified = node
+                    .parent()
+                    .and_then(|x| x.utf8_text(content.as_bytes()).ok())
+                    .map(|x| x.split('#').next().unwrap_or(x).trim())
+                    .filter(|x| x.contains('.'))
+                    .unwrap_or(text);
+                if instructions::enum_info_case_insensitive(qualified).is_some_and(|x| x.3) {
+                    modifiers.push(SemanticTokenModifier::DEPRECATED);
                 }
             }
         }
-
-        // Register usage analysis
-        {
-            let mut register_analyzer = additional_features::RegisterAnalyzer::new();
-            register_analyzer.analyze_register_usage(
-                tree,
-                &document.content,
-                &file_data.type_data.aliases,
-            );
-            let register_diagnostics = register_analyzer.generate_diagnostics();
-            let mut seen = HashSet::new();
-            for existing in diagnostics.iter() {
-                seen.insert(diagnostic_identity(existing));
-            }
-            for diag in register_diagnostics {
-                if seen.insert(diagnostic_identity(&diag)) {
-                    diagnostics.push(diag);
-                }
+        "logictype" => {
+            let deprecated = ["LogicType", "LogicSlotType"].iter().any(|family| {
+                instructions::enum_info(&format!("{}.{}", family, text)).is_some_and(|x| x.2)
+            });
+            if deprecated {
+                modifiers.push(SemanticTokenModifier::DEPRECATED);
             }
         }
-
-        // Global deduplication to avoid duplicate squiggles across all producers
-        {
-            use std::collections::HashSet;
-            let mut seen: HashSet<(u32, u32, u32, u32, String)> = HashSet::new();
-            diagnostics.retain(|d| seen.insert(diagnostic_identity(d)));
+        // Named constants such as pi and nan parse as numbers
+        "number" if text.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+            modifiers.push(SemanticTokenModifier::READONLY);
+            modifiers.push(SemanticTokenModifier::DEFAULT_LIBRARY);
         }
-
-        self.client
-            .publish_diagnostics(uri.to_owned(), diagnostics, None)
-            .await;
+        _ => {}
     }
+    modifiers
+        .iter()
+        .filter_map(|modifier| SEMANTIC_MODIFIER_LEGEND.iter().position(|x| x == modifier))
+        .fold(0, |bits, idx| bits | (1 << idx))
 }
 
-#[derive(Clone, Copy)]
-struct KeywordFlags(u8);
-
-impl KeywordFlags {
-    fn from_bools(logic: bool, slot: bool, batch: bool, reagent: bool) -> Self {
-        KeywordFlags(
-            (logic as u8) | ((slot as u8) << 1) | ((batch as u8) << 2) | ((reagent as u8) << 3),
-        )
-    }
-
-    fn any(self) -> bool {
-        self.0 != 0
-    }
-
-    fn to_union(self) -> instructions::Union<'static> {
-        union_from_mask(self.0)
-    }
-}
-
-fn classify_exact_keyword(ident: &str) -> KeywordFlags {
-    KeywordFlags::from_bools(
-        instructions::LOGIC_TYPES.contains(ident),
-        instructions::SLOT_LOGIC_TYPES.contains(ident),
-        instructions::BATCH_MODES.contains(ident),
-        instructions::REAGENT_MODES.contains(ident),
-    )
-}
-
-fn classify_ci_keyword(ident: &str) -> KeywordFlags {
-    KeywordFlags::from_bools(
-        instructions::LOGIC_TYPES
-            .iter()
-            .any(|x| x.eq_ignore_ascii_case(ident)),
-        instructions::SLOT_LOGIC_TYPES
-            .iter()
-            .any(|x| x.eq_ignore_ascii_case(ident)),
-        instructions::BATCH_MODES
-            .iter()
-            .any(|x| x.eq_ignore_ascii_case(ident)),
-        instructions::REAGENT_MODES
-            .iter()
-            .any(|x| x.eq_ignore_ascii_case(ident)),
-    )
-}
-
-fn union_from_mask(mask: u8) -> instructions::Union<'static> {
-    match mask {
-        0 => instructions::Union(&[]),
-        0b0001 => instructions::Union(&LOGIC_ONLY),
-        0b0010 => instructions::Union(&SLOT_ONLY),
-        0b0100 => instructions::Union(&BATCH_ONLY),
-        0b1000 => instructions::Union(&REAGENT_ONLY),
-        0b0011 => instructions::Union(&LOGIC_SLOT),
-        0b0101 => instructions::Union(&LOGIC_BATCH),
-        0b1001 => instructions::Union(&LOGIC_REAGENT),
-        0b0110 => instructions::Union(&SLOT_BATCH),
-        0b1010 => instructions::Union(&SLOT_REAGENT),
-        0b1100 => instructions::Union(&BATCH_REAGENT),
-        0b0111 => instructions::Union(&LOGIC_SLOT_BATCH),
-        0b1011 => instructions::Union(&LOGIC_SLOT_REAGENT),
-        0b1101 => instructions::Union(&LOGIC_BATCH_REAGENT),
-        0b1110 => instructions::Union(&SLOT_BATCH_REAGENT),
-        0b1111 => instructions::Union(&LOGIC_SLOT_BATCH_REAGENT),
-        _ => instructions::Union(&[]),
-    }
-}
-
-fn diagnostic_identity(diag: &Diagnostic) -> (u32, u32, u32, u32, String) {
-    (
-        diag.range.start.line,
-        diag.range.start.character,
-        diag.range.end.line,
-        diag.range.end.character,
-        diag.message.clone(),
+/// Collect the labels, defines and aliases declared in a document as flat symbols.
+fn collect_document_symbols(uri: &Url, tree: &Tree, content: &str) -> Vec<SymbolInformation> {
+    let mut ret = Vec::new();
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(
+        tree_sitter_ic10::language(),
+        "(instruction (operation \"define\") . (operand)@name)@define
+        (instruction (operation \"alias\") . (operand)@name)@alias
+        (instruction (operation \"label\") . (operand)@name)@alias
+        (label (identifier)@name)@label",
     )
-}
-
-/// Compute diagnostics for a single text buffer using the same logic as the LSP diagnostics.
-fn compute_diagnostics_for_text(content: &str) -> Vec<tower_lsp::lsp_types::Diagnostic> {
-    use tower_lsp::lsp_types::{
-        Diagnostic, DiagnosticSeverity, Position as LspPosition, Range as LspRange,
-    };
-    let mut diagnostics: Vec<Diagnostic> = Vec::new();
-
-    let mut parser = Parser::new();
-    parser
-        .set_language(tree_sitter_ic10::language())
-        .expect("Could not set language");
-    let tree = parser.parse(content, None).unwrap();
-
-    // Syntax errors
-    {
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(tree_sitter_ic10::language(), "(ERROR)@error").unwrap();
-        let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
-        for (capture, _) in captures {
-            diagnostics.push(Diagnostic::new(
-                Range::from(capture.captures[0].node.range()).into(),
-                Some(DiagnosticSeverity::ERROR),
-                None,
-                None,
-                "Syntax error".to_string(),
-                None,
-                None,
-            ));
-        }
-    }
-
-    // Invalid instructions
-    {
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(
-            tree_sitter_ic10::language(),
-            "(instruction (invalid_instruction)@error)",
-        )
-        .unwrap();
-        let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
-        for (capture, _) in captures {
-            let node = capture.captures[0].node;
-            let instruction_text = node.utf8_text(content.as_bytes()).unwrap();
-            if !instructions::INSTRUCTIONS.contains_key(instruction_text) {
-                diagnostics.push(Diagnostic::new(
-                    Range::from(node.range()).into(),
-                    Some(DiagnosticSeverity::ERROR),
-                    None,
-                    None,
-                    "Invalid instruction".to_string(),
-                    None,
-                    None,
-                ));
-            }
-        }
-    }
-
-    // Collect defines/aliases/labels
-    let mut type_data = TypeData::default();
-    {
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(
-            tree_sitter_ic10::language(),
-            "(instruction (operation \"define\"))@define
-                         (instruction (operation \"alias\"))@alias
-                         (instruction (operation \"label\"))@alias
-                         (label (identifier)@label)",
-        )
-        .unwrap();
-
-        let define_idx = query.capture_index_for_name("define").unwrap();
-        let alias_idx = query.capture_index_for_name("alias").unwrap();
-        let label_idx = query.capture_index_for_name("label").unwrap();
-
-        let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
-        for (capture, _) in captures {
-            let capture_idx = capture.captures[0].index;
-            if capture_idx == define_idx || capture_idx == alias_idx {
-                if let Some(name_operand_node) = capture.captures[0].node.child_by_field_name("operand") {
-                    // Prefer inner identifier for the name and trim whitespace
-                    let (name_text, name_range) = if let Some(id_node) = name_operand_node.child_by_field_name("identifier")
-                        .or_else(|| name_operand_node.child(0).filter(|n| n.kind() == "identifier"))
-                    {
-                        (
-                            id_node.utf8_text(content.as_bytes()).unwrap().trim().to_string(),
-                            Range::from(id_node.range()),
-                        )
-                    } else {
-                        (
-                            name_operand_node
-                                .utf8_text(content.as_bytes())
-                                .unwrap()
-                                .trim()
-                                .to_string(),
-                            Range::from(name_operand_node.range()),
-                        )
-                    };
-
-                    if let Some(value_node) = capture.captures[0]
-                        .node
-                        .children_by_field_name("operand", &mut name_operand_node.walk())
-                        .last()
-                    {
-                        let value = value_node.utf8_text(content.as_bytes()).unwrap();
-                        if capture.captures[0].index == define_idx {
-                            let child_kind = value_node.child(0).map(|x| x.kind()).unwrap_or("");
-                            if child_kind == "number"
-                                || child_kind == "function_call"
-                                || child_kind == "hash_preproc"
-                                || child_kind == "preproc_string"
-                                || child_kind == "identifier"
-                            {
-                                type_data.defines.insert(
-                                    name_text,
-                                    DefinitionData::new(
-                                        name_range.into(),
-                                        value.to_string().into(),
-                                    ),
-                                );
-                            }
-                        } else if capture.captures[0].index == alias_idx {
-                            if value_node
-                                .child(0)
-                                .map(|x| x.kind())
-                                .map_or(false, |x| x == "register" || x == "device_spec")
-                            {
-                                type_data.aliases.insert(
-                                    name_text,
-                                    DefinitionData::new(name_range.into(), value.to_owned().into()),
-                                );
-                            }
-                        }
-                    }
+    .unwrap();
+    let define_idx = query.capture_index_for_name("define").unwrap();
+    let alias_idx = query.capture_index_for_name("alias").unwrap();
+    let label_idx = query.capture_index_for_name("label").unwrap();
+    let name_idx = query.capture_index_for_name("name").unwrap();
+
+    let matches = cursor.matches(&query, tree.root_node(), content.as_bytes());
+
+    for matched in matches {
+        let main_match = {
+            let mut ret = None;
+            for cap in matched.captures {
+                if cap.index == define_idx || cap.index == alias_idx || cap.index == label_idx {
+                    ret = Some(cap);
                 }
-            } else if capture_idx == label_idx {
-                let name_node = capture.captures[0].node;
-                let name = name_node.utf8_text(content.as_bytes()).unwrap();
-                type_data.labels.insert(
-                    name.to_owned(),
-                    DefinitionData {
-                        range: Range::from(name_node.range()),
-                        value: name_node.start_position().row as u8,
-                    },
-                );
             }
-        }
-    }
-
-    // Type checking (simplified copy of check_types)
-    {
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(tree_sitter_ic10::language(), "(instruction)@a").unwrap();
-        let captures = cursor.captures(&query, tree.root_node(), content.as_bytes());
-
-        // Register analyzer
-        let mut register_analyzer = additional_features::RegisterAnalyzer::new();
-        register_analyzer.analyze_register_usage(&tree, content, &type_data.aliases);
-
-        for (capture, _) in captures {
-            let capture = capture.captures[0].node;
-            if let Some(operation_node) = capture.child_by_field_name("operation") {
-                let operation = operation_node.utf8_text(content.as_bytes()).unwrap();
-                if let Some(signature) = instructions::INSTRUCTIONS.get(operation) {
-                    let mut argument_count = 0;
-                    let mut tree_cursor = capture.walk();
-                    let operands = capture.children_by_field_name("operand", &mut tree_cursor);
-                    let mut parameters = signature.0.iter();
-                    let mut first_superfluous_arg = None;
-                    let mut pending_define_name: Option<(String, Range)> = None;
-
-                    for operand in operands {
-                        argument_count += 1;
-                        let Some(parameter) = parameters.next() else {
-                            if first_superfluous_arg.is_none() {
-                                first_superfluous_arg = Some(operand);
-                            }
-                            continue;
-                        };
-                        let operand_kind = operand.named_child(0).unwrap().kind();
-                        let expects_name = parameter.match_type(DataType::Name);
-                        let mut underlying_register: Option<String> = None;
-                        let typ = match operand_kind {
-                            "register" => {
-                                if let Some(reg_text) = operand
-                                    .named_child(0)
-                                    .map(|n| n.utf8_text(content.as_bytes()).unwrap_or(""))
-                                {
-                                    underlying_register = Some(reg_text.to_string());
-                                }
-                                instructions::Union(&[DataType::Register])
-                            }
-                            "device_spec" => instructions::Union(&[DataType::Device]),
-                            "number" => instructions::Union(&[DataType::Number]),
-                            "logictype" => {
-                                let ident = operand
-                                    .named_child(0)
-                                    .unwrap()
-                                    .utf8_text(content.as_bytes())
-                                    .unwrap();
-                                let flags = classify_exact_keyword(ident);
-                                if flags.any() {
-                                    flags.to_union()
-                                } else {
-                                    instructions::Union(&[])
-                                }
-                            }
-                            "identifier" => {
-                                let ident_node = operand.named_child(0).unwrap();
-                                let ident =
-                                    ident_node.utf8_text(content.as_bytes()).unwrap();
-
-                                if operation.eq_ignore_ascii_case("define")
-                                    && argument_count == 1
-                                {
-                                    pending_define_name = Some((
-                                        ident.to_string(),
-                                        Range::from(ident_node.range()).into(),
-                                    ));
-                                    instructions::Union(&NAME_ONLY)
-                                } else if expects_name {
-                                    instructions::Union(&NAME_ONLY)
-                                } else if ident.contains('.') {
-                                    if let Some((canonical, _val, _desc, _dep)) =
-                                        instructions::enum_info_case_insensitive(ident)
-                                    {
-                                        if canonical != ident {
-                                            diagnostics.push(Diagnostic::new(
-                                                Range::from(operand.range()).into(),
-                                                Some(DiagnosticSeverity::WARNING),
-                                                None,
-                                                None,
-                                                format!(
-                                                    "Enum '{}' differs in case from canonical '{}'.",
-                                                    ident, canonical
-                                                ),
-                                                None,
-                                                None,
-                                            ));
-                                        }
-                                        instructions::Union(&[DataType::Number])
-                                    } else if type_data.defines.contains_key(ident)
-                                        || type_data.labels.contains_key(ident)
-                                    {
-                                        instructions::Union(&[DataType::Number])
-                                    } else if let Some((canonical, _)) = type_data
-                                        .defines
-                                        .keys()
-                                        .find(|k| k.eq_ignore_ascii_case(ident))
-                                        .map(|k| (k.clone(), ()))
-                                    {
-                                        if canonical != ident {
-                                            diagnostics.push(Diagnostic::new(
-                                                Range::from(operand.range()).into(),
-                                                Some(DiagnosticSeverity::WARNING),
-                                                None,
-                                                None,
-                                                format!(
-                                                    "Define '{}' differs in case from canonical '{}'.",
-                                                    ident, canonical
-                                                ),
-                                                None,
-                                                None,
-                                            ));
-                                        }
-                                        instructions::Union(&[DataType::Number])
-                                    } else if let Some(type_data_val) =
-                                        type_data.aliases.get(ident)
-                                    {
-                                        match type_data_val.value {
-                                            AliasValue::Device(_) => {
-                                                instructions::Union(&[DataType::Device])
-                                            }
-                                            AliasValue::Register(ref reg_name) => {
-                                                underlying_register = Some(reg_name.clone());
-                                                instructions::Union(&[DataType::Register])
-                                            }
-                                        }
-                                    } else {
-                                        instructions::Union(&[])
-                                    }
-                                } else if type_data.defines.contains_key(ident)
-                                    || type_data.labels.contains_key(ident)
-                                {
-                                    instructions::Union(&[DataType::Number])
-                                } else if let Some((canonical, _)) = type_data
-                                    .defines
-                                    .keys()
-                                    .find(|k| k.eq_ignore_ascii_case(ident))
-                                    .map(|k| (k.clone(), ()))
-                                {
-                                    if canonical != ident {
-                                        diagnostics.push(Diagnostic::new(
-                                            Range::from(operand.range()).into(),
-                                            Some(DiagnosticSeverity::WARNING),
-                                            None,
-                                            None,
-                                            format!(
-                                                "Define '{}' differs in case from canonical '{}'.",
-                                                ident, canonical
-                                            ),
-                                            None,
-                                            None,
-                                        ));
-                                    }
-                                    instructions::Union(&[DataType::Number])
-                                } else if let Some(type_data_val) =
-                                    type_data.aliases.get(ident)
-                                {
-                                    match type_data_val.value {
-                                        AliasValue::Device(_) => {
-                                            instructions::Union(&[DataType::Device])
-                                        }
-                                        AliasValue::Register(ref reg_name) => {
-                                            underlying_register = Some(reg_name.clone());
-                                            instructions::Union(&[DataType::Register])
-                                        }
-                                    }
-                                } else {
-                                    let exact_flags = classify_exact_keyword(ident);
-                                    if exact_flags.any() {
-                                        exact_flags.to_union()
-                                    } else {
-                                        let ci_flags = classify_ci_keyword(ident);
-                                        if ci_flags.any() {
-                                            diagnostics.push(Diagnostic::new(
-                                                Range::from(operand.range()).into(),
-                                                Some(DiagnosticSeverity::WARNING),
-                                                None,
-                                                None,
-                                                format!("Identifier '{}' matches a known logic/parameter type by name but differs by case. Consider using proper case or renaming your identifier.", ident),
-                                                None,
-                                                None,
-                                            ));
-                                            ci_flags.to_union()
-                                        } else {
-                                            diagnostics.push(Diagnostic::new(
-                                                Range::from(operand.range()).into(),
-                                                Some(DiagnosticSeverity::ERROR),
-                                                None,
-                                                None,
-                                                format!("Unknown identifier"),
-                                                None,
-                                                None,
-                                            ));
-                                            continue;
-                                        }
-                                    }
-                                }
-                            }
-                            "function_call" | "hash_preproc" => {
-                                let call_text = operand.utf8_text(content.as_bytes()).unwrap();
-                                if is_hash_function_call(call_text) {
-                                    instructions::Union(&[DataType::Number])
-                                } else {
-                                    instructions::Union(&[DataType::Number])
-                                }
-                            }
-                            _ => continue,
-                        };
-
-                        let mut effective_typ = typ;
-                        if parameter.match_type(DataType::Device) {
-                            if let Some(reg_name) = underlying_register.as_ref() {
-                                if register_analyzer.get_register_kind(reg_name)
-                                    == additional_features::ValueKind::DeviceId
-                                {
-                                    effective_typ = instructions::Union(&[DataType::Device]);
-                                }
-                            }
-                        }
-
-                        if !parameter.match_union(&effective_typ) {
-                            diagnostics.push(Diagnostic::new(
-                                Range::from(operand.range()).into(),
-                                Some(DiagnosticSeverity::ERROR),
-                                None,
-                                None,
-                                format!(
-                                    "Type mismatch. Found {}, expected {}",
-                                    effective_typ, parameter
-                                ),
-                                None,
-                                None,
-                            ));
-                        }
+            match ret {
+                Some(ret) => ret,
+                None => continue,
+            }
+        };
 
-                        if operation.eq_ignore_ascii_case("define") && argument_count == 2 {
-                            if let Some((define_name, define_range)) = pending_define_name.clone() {
-                                let value_text = operand
-                                    .utf8_text(content.as_bytes())
-                                    .unwrap()
-                                    .trim()
-                                    .to_string();
-                                type_data.defines.insert(
-                                    define_name,
-                                    DefinitionData::new(define_range, value_text.into()),
-                                );
-                            }
-                        }
-                    }
+        let kind = if main_match.index == define_idx {
+            SymbolKind::NUMBER
+        } else if main_match.index == alias_idx {
+            SymbolKind::VARIABLE
+        } else if main_match.index == label_idx {
+            SymbolKind::FUNCTION
+        } else {
+            SymbolKind::FILE
+        };
 
-                    if argument_count > signature.0.len() {
-                        if let Some(first_superfluous_arg) = first_superfluous_arg {
-                            let plural_str = if argument_count - signature.0.len() > 1 {
-                                "s"
-                            } else {
-                                ""
-                            };
-                            diagnostics.push(Diagnostic::new(
-                                tower_lsp::lsp_types::Range::new(
-                                    Position::from(first_superfluous_arg.start_position()).into(),
-                                    Position::from(capture.end_position()).into(),
-                                ),
-                                Some(DiagnosticSeverity::ERROR),
-                                None,
-                                None,
-                                format!(
-                                    "Superfluous argument{}. '{}' only requires {} arguments.",
-                                    plural_str,
-                                    operation,
-                                    signature.0.len()
-                                ),
-                                None,
-                                None,
-                            ));
-                            continue;
-                        }
-                    }
-                    if argument_count != signature.0.len() {
-                        diagnostics.push(Diagnostic::new(
-                            Range::from(capture.range()).into(),
-                            Some(DiagnosticSeverity::ERROR),
-                            None,
-                            None,
-                            "Invalid number of arguments".to_string(),
-                            None,
-                            None,
-                        ));
-                    }
-                }
-            }
-        }
-    }
+        let Some(name_node) = matched.nodes_for_capture_index(name_idx).next() else {
+            continue;
+        };
 
-    // Register analyzer diagnostics
-    {
-        let mut register_analyzer = additional_features::RegisterAnalyzer::new();
-        register_analyzer.analyze_register_usage(&tree, content, &type_data.aliases);
-        let mut seen = HashSet::new();
-        for existing in diagnostics.iter() {
-            seen.insert(diagnostic_identity(existing));
-        }
-        for diag in register_analyzer.generate_diagnostics() {
-            if seen.insert(diagnostic_identity(&diag)) {
-                diagnostics.push(diag);
-            }
-        }
+        let name = name_node.utf8_text(content.as_bytes()).unwrap();
+        #[allow(deprecated)]
+        ret.push(SymbolInformation {
+            name: name.to_string(),
+            kind,
+            tags: None,
+            deprecated: Some(matched.pattern_index == 2),
+            location: Location::new(uri.clone(), Range::from(name_node.range()).into()),
+            container_name: None,
+        });
     }
-
-    diagnostics
+    ret
 }
 
 fn get_current_parameter(instruction_node: Node, position: usize) -> (usize, Option<Node>) {
@@ -3673,57 +2963,120 @@ fn get_current_parameter(instruction_node: Node, position: usize) -> (usize, Opt
     (ret, operand)
 }
 
-trait NodeEx: Sized {
-    fn find_parent(&self, kind: &str) -> Option<Self>;
-    fn query<'a>(&'a self, query: &str, content: impl AsRef<[u8]>) -> Option<Node<'a>>;
+/// Diagnostics of one script for `--diagnose`, or `None` if it cannot be read.
+fn diagnose_file(
+    path: &Path,
+    config: &Configuration,
+    max_severity: cli::Severity,
+) -> Option<diagnostic_report::FileDiagnostics> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let url = fs::canonicalize(path)
+        .ok()
+        .and_then(|x| Url::from_file_path(x).ok())
+        .unwrap_or_else(|| Url::parse("file:///script.ic10").unwrap());
+    let diagnostics = diagnostics::check_script(&url, &content, path.parent(), config)
+        .into_iter()
+        .filter(|x| max_severity.includes(x.severity))
+        .collect();
+    Some(diagnostic_report::FileDiagnostics {
+        path: path.to_path_buf(),
+        diagnostics,
+    })
 }
 
-impl<'a> NodeEx for Node<'a> {
-    fn find_parent(&self, kind: &str) -> Option<Self> {
-        let mut cur = self.clone();
-        while cur.kind() != kind {
-            cur = cur.parent()?;
-        }
-        Some(cur)
+/// Modification times of a script and of every file it includes.
+type WatchStamp = Vec<(PathBuf, Option<std::time::SystemTime>)>;
+
+/// What decides whether `path` needs checking again: its own modification time and those of the
+/// files its `@include`s read, including ones that do not exist yet.
+fn watch_stamp(path: &Path) -> WatchStamp {
+    let modified = |x: &Path| fs::metadata(x).and_then(|x| x.modified()).ok();
+    let mut stamp = vec![(path.to_path_buf(), modified(path))];
+    let content = fs::read_to_string(path).unwrap_or_default();
+    if preprocessor::has_directives(&content) {
+        let included = std::cell::RefCell::new(Vec::new());
+        preprocessor::expand(&content, path.parent(), |x| {
+            included.borrow_mut().push(x.to_path_buf());
+            fs::read_to_string(x)
+        });
+        stamp.extend(included.into_inner().into_iter().map(|x| {
+            let time = modified(&x);
+            (x, time)
+        }));
     }
+    stamp
+}
 
-    fn query(&self, query: &str, content: impl AsRef<[u8]>) -> Option<Node<'a>> {
-        let mut cursor = QueryCursor::new();
-        let query = match Query::new(tree_sitter_ic10::language(), query) {
-            Ok(q) => q,
-            Err(_e) => {
-                // If the node type in the query doesn't exist in this parser build, fail gracefully
-                return None;
+/// Re-check the `--diagnose` scripts whenever one or a file it includes changes, appears or
+/// disappears, printing only the scripts whose diagnostics changed. Runs until interrupted.
+fn watch_diagnostics(cli: &cli::Cli, config: &Configuration) -> ! {
+    let mut known: HashMap<PathBuf, (WatchStamp, Vec<Diagnostic>)> = HashMap::new();
+    loop {
+        let (paths, _) = script_files::expand(&cli.diagnose);
+        let mut changed = Vec::new();
+        for path in &paths {
+            let modified = watch_stamp(path);
+            if known.get(path).is_some_and(|x| x.0 == modified) {
+                continue;
             }
-        };
+            let diagnostics = diagnose_file(path, config, cli.max_severity)
+                .map(|x| x.diagnostics)
+                .unwrap_or_default();
+            let previous = known.insert(path.clone(), (modified, diagnostics.clone()));
+            if previous.map(|x| x.1) != Some(diagnostics.clone()) {
+                changed.push(diagnostic_report::FileDiagnostics {
+                    path: path.clone(),
+                    diagnostics,
+                });
+            }
+        }
+        known.retain(|path, _| {
+            let kept = paths.contains(path);
+            if !kept {
+                eprintln!("Removed {}", path.display());
+            }
+            kept
+        });
 
-        let mut captures = cursor.captures(&query, self.clone(), content.as_ref());
-        captures
-            .next()
-            .map(|x| x.0.captures)
-            .and_then(|x| x.get(0))
-            .map(|x| x.node)
+        if !changed.is_empty() {
+            print!("{}", diagnostic_report::render(cli.format, &changed));
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+        }
+        std::thread::sleep(WATCH_INTERVAL);
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn hash_define_is_recognized() {
-        let script = r#"define StartButton HASH("StructureLogicButton")
-sb StartButton Setting 34"#;
-        let diagnostics = compute_diagnostics_for_text(script);
-        assert!(
-            diagnostics
-                .iter()
-                .filter(|d| d.severity == Some(tower_lsp::lsp_types::DiagnosticSeverity::ERROR))
-                .all(|d| !d.message.contains("Unknown identifier")),
-            "Unexpected Unknown identifier diagnostics: {:?}",
-            diagnostics
-        );
+/// Write `xml` with the chip sources from the `.ic10` files in `dir` to `copy`.
+fn inject_chips(
+    world: &Path,
+    xml: &str,
+    dir: &Path,
+    copy: &Path,
+) -> std::result::Result<(), String> {
+    if fs::canonicalize(copy).ok() == fs::canonicalize(world).ok() {
+        return Err("--save-copy must not overwrite the save itself".to_string());
     }
+    let mut sources = HashMap::new();
+    for path in workspace_index::find_ic10_files(dir) {
+        let Some(id) = world_save::reference_id_of(&path) else {
+            eprintln!("Skipping {}: no chip reference id in its name", path.display());
+            continue;
+        };
+        let source = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        sources.insert(id, source);
+    }
+    let updated = world_save::inject(xml, &sources)?;
+    fs::write(copy, updated).map_err(|e| format!("Could not write {}: {}", copy.display(), e))?;
+    eprintln!("{} chips updated in {}", sources.len(), copy.display());
+    Ok(())
 }
 
 #[tokio::main]
@@ -3731,43 +3084,208 @@ async fn main() {
     use clap::Parser as _;
     let cli = cli::Cli::parse();
 
-    // Diagnostic runner mode: if files provided with --diagnose, run the diagnostic logic
-    // on each file and print the results to stdout, then exit.
+    // Game data overrides apply to every mode, so they are loaded before any of them runs
+    if let Some(dir) = &cli.data_pack {
+        match ic10lsp::data_pack::DataPack::load(dir) {
+            Ok(pack) => {
+                ic10lsp::data_pack::install(pack);
+            }
+            Err(message) => {
+                eprintln!("Could not load data pack: {}", message);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Diagnostic runner mode: if files, directories or globs are provided with --diagnose, run
+    // the diagnostic logic on each script and print the results to stdout, then exit. The exit
+    // code is non-zero when an error was found, a file could not be read or a glob matched
+    // nothing. With --watch the scripts are re-checked as they change instead.
     if !cli.diagnose.is_empty() {
-        for path in &cli.diagnose {
-            let path_ref = Path::new(path);
-            let content = match fs::read_to_string(path_ref) {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Could not read {}: {}", path_ref.display(), e);
-                    continue;
-                }
+        let config = match &cli.config {
+            Some(path) => Configuration::from_file(path).unwrap_or_else(|e| {
+                eprintln!("Could not load {}: {}", path.display(), e);
+                std::process::exit(1);
+            }),
+            None => Configuration::default(),
+        };
+        let (paths, unmatched) = script_files::expand(&cli.diagnose);
+        for pattern in &unmatched {
+            eprintln!("No scripts match {}", pattern.display());
+        }
+        if cli.watch {
+            watch_diagnostics(&cli, &config);
+        }
+
+        let mut failed = !unmatched.is_empty();
+        let mut files = Vec::new();
+        for path in &paths {
+            let Some(file) = diagnose_file(path, &config, cli.max_severity) else {
+                failed = true;
+                continue;
             };
+            failed |= file
+                .diagnostics
+                .iter()
+                .any(|x| x.severity == Some(DiagnosticSeverity::ERROR));
+            files.push(file);
+        }
+        print!("{}", diagnostic_report::render(cli.format, &files));
+        std::process::exit(if failed { 1 } else { 0 });
+    }
 
-            let diagnostics = compute_diagnostics_for_text(&content);
+    // Minifier mode: print the minified script to stdout and the size report to stderr
+    if let Some(path) = &cli.minify {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        let mut parser = Parser::new();
+        parser
+            .set_language(tree_sitter_ic10::language())
+            .expect("Failed to set language");
+        let tree = parser.parse(&content, None).expect("Failed to parse");
+        match minifier::minify(&tree, &content) {
+            Ok(minified) => {
+                let config = Configuration::default();
+                println!("{}", minified.text);
+                for warning in &minified.warnings {
+                    eprintln!("warning: {}", warning);
+                }
+                eprintln!("{}", minified.report(config.max_lines, config.max_bytes));
+            }
+            Err(message) => {
+                eprintln!("{}: {}", path.display(), message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-            println!("Diagnostics for {}:", path_ref.display());
-            if diagnostics.is_empty() {
-                println!("  (no diagnostics)");
-            } else {
-                for d in diagnostics {
-                    let sev = match d.severity {
-                        Some(tower_lsp::lsp_types::DiagnosticSeverity::ERROR) => "ERROR",
-                        Some(tower_lsp::lsp_types::DiagnosticSeverity::WARNING) => "WARN",
-                        Some(tower_lsp::lsp_types::DiagnosticSeverity::INFORMATION) => "INFO",
-                        Some(tower_lsp::lsp_types::DiagnosticSeverity::HINT) => "HINT",
-                        _ => "UNKNOWN",
-                    };
-                    // Print range start line/char and message
-                    let range = d.range;
-                    println!(
-                        "  {}:{}:{} - {}",
-                        sev, range.start.line, range.start.character, d.message
-                    );
+    // Preprocessor mode: print the expanded script to stdout, optionally writing its source map
+    if let Some(path) = &cli.preprocess {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        let expansion = preprocessor::expand(&content, path.parent(), |x| fs::read_to_string(x));
+        print!("{}", expansion.text);
+        if let Some(map_path) = &cli.source_map {
+            let map = expansion.source_map(&path.display().to_string());
+            if let Err(e) = fs::write(map_path, serde_json::to_string_pretty(&map).unwrap()) {
+                eprintln!("Could not write {}: {}", map_path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        for error in &expansion.errors {
+            eprintln!("{}:{}: {}", path.display(), error.site + 1, error.message);
+        }
+        if !expansion.errors.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Library modes: convert between .ic10 files and the game's saved-script folders
+    if !cli.export_library.is_empty() || cli.import_library.is_some() {
+        let into = cli.into.as_deref().unwrap();
+        let result = match &cli.import_library {
+            Some(scripts_dir) => script_library::import(scripts_dir, into),
+            None => {
+                let (paths, unmatched) = script_files::expand(&cli.export_library);
+                for pattern in &unmatched {
+                    eprintln!("No scripts match {}", pattern.display());
+                }
+                script_library::export(&paths, into, |x| eprintln!("{}", x))
+            }
+        };
+        match result {
+            Ok(written) => {
+                for path in &written {
+                    println!("{}", path.display());
+                }
+                eprintln!("{} scripts written", written.len());
+            }
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Save file mode: list, extract or inject the programs of the chips in a world save
+    if let Some(save) = &cli.save {
+        let world = world_save::world_file(save);
+        let xml = match fs::read_to_string(&world) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", world.display(), e);
+                std::process::exit(1);
+            }
+        };
+        let result = if let Some(dir) = &cli.inject_chips {
+            inject_chips(&world, &xml, dir, cli.save_copy.as_deref().unwrap())
+        } else {
+            world_save::chips(&xml).and_then(|chips| {
+                for chip in &chips {
+                    print!("{}", chip.describe());
                 }
+                eprintln!("{} chips", chips.len());
+                let Some(dir) = &cli.extract_chips else {
+                    return Ok(());
+                };
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                for chip in &chips {
+                    let path = dir.join(chip.file_name());
+                    fs::write(&path, &chip.source)
+                        .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+                }
+                Ok(())
+            })
+        };
+        if let Err(message) = result {
+            eprintln!("{}: {}", world.display(), message);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Optimizer mode: print the optimized script to stdout and each rewrite to stderr
+    if let Some(path) = &cli.optimize {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path.display(), e);
+                std::process::exit(1);
             }
-            println!("");
+        };
+        let mut parser = Parser::new();
+        parser
+            .set_language(tree_sitter_ic10::language())
+            .expect("Failed to set language");
+        let tree = parser.parse(&content, None).expect("Failed to parse");
+
+        let url = fs::canonicalize(path)
+            .ok()
+            .and_then(|x| Url::from_file_path(x).ok())
+            .unwrap_or_else(|| Url::parse("file:///script.ic10").unwrap());
+        let mut type_data = TypeData::default();
+        diagnostics::collect_definitions(&url, &tree, &content, &mut type_data, &mut Vec::new());
+
+        let optimization = optimizer::optimize(&tree, &content, &type_data, 0..=u32::MAX);
+        print!("{}", optimization.apply(&content));
+        for rewrite in &optimization.rewrites {
+            eprintln!("{}", rewrite.description);
         }
+        let count = optimization.rewrites.len();
+        eprintln!("{} rewrite{}", count, if count == 1 { "" } else { "s" });
         return;
     }
 
@@ -3776,12 +3294,20 @@ async fn main() {
         .set_language(tree_sitter_ic10::language())
         .expect("Failed to set language");
 
-    let (service, socket) = LspService::new(|client| Backend {
+    let (service, socket) = LspService::build(|client| Backend {
         client,
-        files: Arc::new(RwLock::new(HashMap::new())),
+        files: Arc::new(documents::DocumentStore::default()),
         config: Arc::new(RwLock::new(Configuration::default())),
         diagnostics_enabled: Arc::new(RwLock::new(true)),
-    });
+        workspace: Arc::new(RwLock::new(workspace_index::WorkspaceIndex::default())),
+        position_encoding: Arc::new(RwLock::new(PositionEncodingKind::UTF16)),
+        semantic_token_cache: Arc::new(RwLock::new(semantic_tokens::TokenCache::default())),
+        pull_diagnostics: Arc::new(RwLock::new(false)),
+        workspace_diagnostics: Arc::new(RwLock::new(pull_diagnostics::WorkspaceCache::default())),
+    })
+    .custom_method("textDocument/diagnostic", Backend::document_diagnostic)
+    .custom_method("workspace/diagnostic", Backend::workspace_diagnostic)
+    .finish();
 
     if !cli.listen && cli.host.is_none() {
         // stdin/stdout
@@ -3821,71 +3347,23 @@ async fn main() {
     }
 }
 
-#[derive(Clone, Copy)]
-struct Position(tower_lsp::lsp_types::Position);
-
-#[derive(Clone, Debug)]
-struct Range(tower_lsp::lsp_types::Range);
-
-impl Range {
-    pub fn contains(&self, position: Position) -> bool {
-        let (start_line, start_char) = (self.0.start.line, self.0.start.character);
-        let (end_line, end_char) = (self.0.end.line, self.0.end.character);
-        let (line, character) = (position.0.line, position.0.character);
-
-        (line > start_line && line < end_line)
-            || (line == start_line && character >= start_char)
-            || (line == end_line && character <= end_char)
-    }
-}
-
-impl From<tree_sitter::Point> for Position {
-    fn from(value: tree_sitter::Point) -> Self {
-        Position(tower_lsp::lsp_types::Position::new(
-            value.row as u32,
-            value.column as u32,
-        ))
-    }
-}
-
-impl From<tower_lsp::lsp_types::Position> for Position {
-    fn from(value: tower_lsp::lsp_types::Position) -> Self {
-        Position(value)
-    }
-}
-
-impl From<Position> for tower_lsp::lsp_types::Position {
-    fn from(value: Position) -> Self {
-        value.0
-    }
-}
-
-impl From<Position> for tree_sitter::Point {
-    fn from(value: Position) -> Self {
-        tree_sitter::Point {
-            row: value.0.line as usize,
-            column: value.0.character as usize,
-        }
-    }
-}
-
-impl From<tree_sitter::Range> for Range {
-    fn from(value: tree_sitter::Range) -> Self {
-        Range(tower_lsp::lsp_types::Range::new(
-            Position::from(value.start_point).into(),
-            Position::from(value.end_point).into(),
-        ))
-    }
-}
-
-impl From<tower_lsp::lsp_types::Range> for Range {
-    fn from(value: tower_lsp::lsp_types::Range) -> Self {
-        Range(value)
-    }
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl From<Range> for tower_lsp::lsp_types::Range {
-    fn from(value: Range) -> Self {
-        value.0
+    #[test]
+    fn hash_define_is_recognized() {
+        let script = r#"define StartButton HASH("StructureLogicButton")
+sb StartButton Setting 34"#;
+        let url = Url::parse("file:///hash.ic10").unwrap();
+        let diagnostics = diagnostics::check_text(&url, script, &Configuration::default());
+        assert!(
+            diagnostics
+                .iter()
+                .filter(|d| d.severity == Some(tower_lsp::lsp_types::DiagnosticSeverity::ERROR))
+                .all(|d| !d.message.contains("Unknown identifier")),
+            "Unexpected Unknown identifier diagnostics: {:?}",
+            diagnostics
+        );
     }
 }
@@ -0,0 +1,261 @@
+//! Minification of scripts to fit the in-game size limits.
+//!
+//! The readable source keeps its comments, defines, aliases and labels; the minified build drops
+//! all of them. Comments and blank lines are removed, defines, aliases and `HASH("...")` values
+//! are replaced by what they stand for, and every label-only line is folded away with its
+//! references rewritten to line numbers. Numeric jumps, absolute and relative, are renumbered so
+//! they keep reaching the same instruction.
+//!
+//! Names declared more than once keep their declarations, since which one applies depends on the
+//! order the program runs in. Jumps through a register other than `ra` cannot be followed and are
+//! reported as warnings.
+
+use std::collections::HashMap;
+
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::code_lens::line_cost;
+use crate::jump_labels::jump_kind;
+use crate::DefineValue;
+
+#[derive(Debug)]
+pub struct Minified {
+    pub text: String,
+    pub lines_before: usize,
+    pub bytes_before: usize,
+    pub lines_after: usize,
+    pub bytes_after: usize,
+    pub warnings: Vec<String>,
+}
+
+impl Minified {
+    /// Before/after line and byte counts against the given limits.
+    pub fn report(&self, max_lines: usize, max_bytes: usize) -> String {
+        let count = |name, before, after, max| {
+            let verdict = if after > max { " (over limit)" } else { "" };
+            format!("{name}: {before} → {after} of {max}{verdict}")
+        };
+        format!(
+            "{}, {}",
+            count("Lines", self.lines_before, self.lines_after, max_lines),
+            count("Bytes", self.bytes_before, self.bytes_after, max_bytes)
+        )
+    }
+}
+
+/// Minify the parsed script `content`. Fails if the script does not parse.
+pub fn minify(tree: &Tree, content: &str) -> Result<Minified, String> {
+    if tree.root_node().has_error() {
+        return Err("The script has syntax errors; fix them before minifying".to_string());
+    }
+
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x (label)@x").unwrap();
+    let lines: Vec<Node> = cursor
+        .captures(&query, tree.root_node(), content.as_bytes())
+        .map(|(capture, _)| capture.captures[0].node)
+        .collect();
+
+    // Declarations that can be substituted everywhere
+    let mut declarations: HashMap<&str, Vec<String>> = HashMap::new();
+    let mut labels = HashMap::new();
+    for node in &lines {
+        if node.kind() == "label" {
+            let name = node
+                .child(0)
+                .unwrap()
+                .utf8_text(content.as_bytes())
+                .unwrap();
+            labels.insert(name, node.start_position().row);
+            continue;
+        }
+        let operation = text(node.child_by_field_name("operation").unwrap(), content);
+        if !matches!(operation, "define" | "alias") {
+            continue;
+        }
+        let operands = operands(*node);
+        let [name, value] = operands[..] else {
+            continue;
+        };
+        declarations
+            .entry(text(name, content))
+            .or_default()
+            .push(substitute_value(value, content));
+    }
+    let replacements: HashMap<&str, String> = declarations
+        .into_iter()
+        .filter(|(_, values)| values.len() == 1)
+        .map(|(name, mut values)| (name, values.pop().unwrap()))
+        .collect();
+
+    let kept: Vec<Node> = lines
+        .into_iter()
+        .filter(|node| {
+            if node.kind() == "label" {
+                return false;
+            }
+            let operation = text(node.child_by_field_name("operation").unwrap(), content);
+            !(matches!(operation, "define" | "alias")
+                && operands(*node)
+                    .first()
+                    .is_some_and(|x| replacements.contains_key(text(*x, content))))
+        })
+        .collect();
+    let kept_rows: Vec<usize> = kept.iter().map(|x| x.start_position().row).collect();
+    // New line of an old line; removed lines continue at the next kept one
+    let new_line = |row: usize| kept_rows.partition_point(|x| *x < row);
+    // Jump target `target` of a jump on `row` in the minified numbering
+    let renumber = |row: usize, relative: bool, target: &str| match target.parse::<i64>() {
+        Ok(offset) if relative && row as i64 + offset >= 0 => {
+            let target = new_line((row as i64 + offset) as usize);
+            Some((target as i64 - new_line(row) as i64).to_string())
+        }
+        Ok(target) if !relative && target >= 0 => Some(new_line(target as usize).to_string()),
+        _ => None,
+    };
+
+    let mut warnings = Vec::new();
+    let mut out = Vec::new();
+    for node in &kept {
+        let row = node.start_position().row;
+        let operation = text(node.child_by_field_name("operation").unwrap(), content);
+        let relative = jump_kind(operation);
+        let operands = operands(*node);
+
+        let mut parts = vec![operation.to_string()];
+        for (idx, operand) in operands.iter().enumerate() {
+            let value = operand.child(0).unwrap();
+            let value_text = text(value, content);
+            let is_target = relative.is_some() && idx == operands.len() - 1;
+            let part = match value.kind() {
+                "identifier" => {
+                    if let Some(label_row) = labels.get(value_text) {
+                        let target = new_line(*label_row);
+                        if relative == Some(true) && is_target {
+                            (target as i64 - new_line(row) as i64).to_string()
+                        } else {
+                            target.to_string()
+                        }
+                    } else if let Some(replacement) = replacements.get(value_text) {
+                        // A define used as a line number moves with the lines like a number
+                        match relative.filter(|_| is_target) {
+                            Some(relative) => renumber(row, relative, replacement)
+                                .unwrap_or_else(|| replacement.clone()),
+                            None => replacement.clone(),
+                        }
+                    } else {
+                        value_text.to_string()
+                    }
+                }
+                "number" if is_target => renumber(row, relative == Some(true), value_text)
+                    .unwrap_or_else(|| value_text.to_string()),
+                "hash_preproc" => substitute_value(value, content),
+                _ => value_text.to_string(),
+            };
+            if is_target && is_register_target(value, &part) {
+                warnings.push(format!(
+                    "Line {} jumps through {}; its target line may have moved",
+                    row + 1,
+                    value_text
+                ));
+            }
+            parts.push(part);
+        }
+        out.push(parts.join(" "));
+    }
+
+    let text = out.join("\n");
+    Ok(Minified {
+        lines_before: content.lines().count(),
+        bytes_before: content.lines().map(line_cost).sum(),
+        lines_after: out.len(),
+        bytes_after: out.iter().map(|x| line_cost(x)).sum(),
+        text,
+        warnings,
+    })
+}
+
+fn text<'a>(node: Node, content: &'a str) -> &'a str {
+    node.utf8_text(content.as_bytes()).unwrap()
+}
+
+fn operands(instruction: Node) -> Vec<Node> {
+    let mut cursor = instruction.walk();
+    instruction
+        .children_by_field_name("operand", &mut cursor)
+        .collect()
+}
+
+/// Shortest spelling of a declared value: `HASH("...")` becomes its number.
+fn substitute_value(operand: Node, content: &str) -> String {
+    let value = text(operand, content);
+    match DefineValue::from(value.to_string()).resolved_numeric() {
+        Some(number) => number.to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Whether a jump target (after substitution) is a register other than `ra`.
+fn is_register_target(value: Node, substituted: &str) -> bool {
+    let is_register = |x: &str| {
+        x.starts_with('r') && x.len() > 1 && x[1..].chars().all(|c| c == 'r' || c.is_ascii_digit())
+    };
+    (value.kind() == "register" || value.kind() == "identifier")
+        && substituted != "ra"
+        && (substituted == "sp" || is_register(substituted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    fn run(source: &str) -> Minified {
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        minify(&tree, source).unwrap()
+    }
+
+    #[test]
+    fn strips_declarations_comments_and_labels() {
+        let source = "# Furnace controller\nalias sensor d0\ndefine Furnace HASH(\"StructureFurnace\")\ndefine Limit 500\n\nstart:\n    l r0 sensor Temperature # read\n    brgt r0 Limit 3\n    sb Furnace On 1\n    j start\n    sb Furnace On 0\nyield\nj 5\n";
+        let minified = run(source);
+        let furnace = crate::hash_utils::compute_crc32("StructureFurnace");
+        assert_eq!(
+            minified.text,
+            format!(
+                "l r0 d0 Temperature\nbrgt r0 500 3\nsb {furnace} On 1\nj 0\nsb {furnace} On 0\nyield\nj 0"
+            )
+        );
+        assert_eq!((minified.lines_before, minified.lines_after), (13, 7));
+        assert!(minified.warnings.is_empty());
+        assert_eq!(
+            minified.report(5, 4096),
+            format!(
+                "Lines: 13 → 7 of 5 (over limit), Bytes: {} → {} of 4096",
+                source.lines().map(line_cost).sum::<usize>(),
+                minified.text.lines().map(line_cost).sum::<usize>()
+            )
+        );
+    }
+
+    #[test]
+    fn relative_jumps_are_renumbered_and_register_jumps_reported() {
+        let source = "alias target r1\nmove r0 0\n# count up\n\nadd r0 r0 1\nbrlt r0 10 -3\nj target\nj ra\n";
+        let minified = run(source);
+        assert_eq!(
+            minified.text,
+            "move r0 0\nadd r0 r0 1\nbrlt r0 10 -1\nj r1\nj ra"
+        );
+        assert_eq!(minified.warnings.len(), 1);
+        assert!(minified.warnings[0].starts_with("Line 7"));
+    }
+
+    #[test]
+    fn defines_used_as_jump_targets_are_renumbered() {
+        let minified = run("# header\ndefine Loop 2\n\nyield\nl r0 d0 Temperature\nj Loop\n");
+        assert_eq!(minified.text, "yield\nl r0 d0 Temperature\nj 0");
+        assert_eq!((minified.bytes_before, minified.bytes_after), (63, 33));
+    }
+}
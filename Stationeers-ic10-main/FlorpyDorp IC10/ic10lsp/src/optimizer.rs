@@ -0,0 +1,446 @@
+//! Peephole optimization of instruction sequences.
+//!
+//! Each rewrite looks at one instruction, or two that always run back to back, and keeps what
+//! the program does:
+//!
+//! - `move r0 r0` is removed.
+//! - `seq r0 a b` followed by `bnez r0 target` becomes `beq a b target` when `r0` is not read
+//!   afterwards (any `s*`/`b*` pair from [`INSTRUCTIONS`]).
+//! - Comparisons against `0` use the `*z` forms (`blt r0 0 x` → `bltz r0 x`).
+//! - Arithmetic whose result is never read (a dead store, found by [`liveness`]) is removed.
+//! - An `s` to a device and logic type immediately overwritten by another is removed, when the
+//!   logic type only holds state (see [`STATE_LOGIC_TYPES`]). Types such as `Activate` act on
+//!   every write, so both writes are kept even when they repeat the same value.
+//!
+//! Two instructions count as back to back only if nothing jumps between them. Removed lines are
+//! deleted unless the program jumps by line number (directly, through a register or through a
+//! define), in which case they are left empty so every line keeps its number.
+
+use std::ops::RangeInclusive;
+
+use tower_lsp::lsp_types::{Position, Range as LspRange, TextEdit};
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::instructions::INSTRUCTIONS;
+use crate::jump_labels::{has_define_targets, jump_kind};
+use crate::register_allocator::{bit, liveness, Liveness};
+use crate::{AliasValue, Range, TypeData};
+
+/// Instructions whose only effect is writing their first operand.
+static PURE_INSTRUCTIONS: phf::Set<&'static str> = phf::phf_set! {
+    "move", "add", "sub", "mul", "div", "mod", "abs", "ceil", "floor", "round", "trunc",
+    "sqrt", "exp", "log", "max", "min", "sin", "cos", "tan", "asin", "acos", "atan", "atan2",
+    "and", "or", "xor", "nor", "not", "sll", "srl", "sla", "sra", "select",
+    "seq", "sne", "slt", "sgt", "sle", "sge", "seqz", "snez", "sltz", "sgtz", "slez", "sgez",
+    "sap", "sna", "sapz", "snaz",
+};
+
+/// Logic types whose writes only set a value the device reads later, so of two writes in a row
+/// only the last is observable.
+static STATE_LOGIC_TYPES: phf::Set<&'static str> = phf::phf_set! {
+    "Setting", "Horizontal", "Vertical", "Color", "Lock", "Mode", "On", "Open",
+};
+
+/// Conditions of `s*` instructions that have a `b*`/`br*` counterpart.
+const CONDITIONS: [&str; 16] = [
+    "eq", "ne", "lt", "gt", "le", "ge", "eqz", "nez", "ltz", "gtz", "lez", "gez", "ap", "na",
+    "apz", "naz",
+];
+
+/// One instruction replaced (`Some`) or removed (`None`).
+#[derive(Debug)]
+pub struct Change {
+    pub range: LspRange,
+    pub new_text: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Rewrite {
+    pub description: String,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug)]
+pub struct Optimization {
+    pub rewrites: Vec<Rewrite>,
+    /// Removed lines are emptied rather than deleted.
+    keep_numbering: bool,
+}
+
+impl Optimization {
+    pub fn edits(&self, content: &str) -> Vec<TextEdit> {
+        let lines: Vec<&str> = content.lines().collect();
+        let mut ret = Vec::new();
+        for change in self.rewrites.iter().flat_map(|x| &x.changes) {
+            let line = change.range.start.line;
+            let edit = match &change.new_text {
+                Some(text) => TextEdit::new(change.range, text.clone()),
+                None => {
+                    let line_text = lines.get(line as usize).copied().unwrap_or("");
+                    let instruction_only = line_text.trim().len()
+                        == (change.range.end.character - change.range.start.character) as usize;
+                    if self.keep_numbering || !instruction_only {
+                        TextEdit::new(change.range, String::new())
+                    } else {
+                        TextEdit::new(
+                            LspRange::new(Position::new(line, 0), Position::new(line + 1, 0)),
+                            String::new(),
+                        )
+                    }
+                }
+            };
+            ret.push(edit);
+        }
+        ret
+    }
+
+    /// `content` with every rewrite applied.
+    pub fn apply(&self, content: &str) -> String {
+        let mut line_starts = vec![0];
+        line_starts.extend(content.match_indices('\n').map(|(x, _)| x + 1));
+        let offset = |position: Position| {
+            line_starts
+                .get(position.line as usize)
+                .map_or(content.len(), |x| x + position.character as usize)
+                .min(content.len())
+        };
+
+        let mut edits = self.edits(content);
+        edits.sort_by_key(|x| std::cmp::Reverse(x.range.start));
+        let mut ret = content.to_string();
+        for edit in edits {
+            ret.replace_range(
+                offset(edit.range.start)..offset(edit.range.end),
+                &edit.new_text,
+            );
+        }
+        ret
+    }
+}
+
+struct Instruction<'a> {
+    node: Node<'a>,
+    row: usize,
+    operation: &'a str,
+    operands: Vec<Node<'a>>,
+}
+
+/// Find the rewrites for instructions on the given lines.
+pub fn optimize(
+    tree: &Tree,
+    content: &str,
+    type_data: &TypeData,
+    lines: RangeInclusive<u32>,
+) -> Optimization {
+    let aliases = &type_data.aliases;
+    let mut instructions = Vec::new();
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
+    for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+        let node = capture.captures[0].node;
+        let Some(operation) = node
+            .child_by_field_name("operation")
+            .and_then(|x| x.utf8_text(content.as_bytes()).ok())
+        else {
+            continue;
+        };
+        let mut tree_cursor = node.walk();
+        let operands = node
+            .children_by_field_name("operand", &mut tree_cursor)
+            .filter_map(|x| x.child(0))
+            .collect();
+        instructions.push(Instruction {
+            node,
+            row: node.start_position().row,
+            operation,
+            operands,
+        });
+    }
+
+    let text = |node: Node| node.utf8_text(content.as_bytes()).unwrap();
+    // Register or device an operand names, looking through aliases
+    let resolve = |node: Node| -> String {
+        match aliases.get(text(node)).map(|x| &x.value) {
+            Some(AliasValue::Register(x) | AliasValue::Device(x)) => x.clone(),
+            None => text(node).to_string(),
+        }
+    };
+    let register = |node: Node| -> Option<usize> {
+        resolve(node)
+            .strip_prefix('r')?
+            .parse::<usize>()
+            .ok()
+            .filter(|x| *x < 16)
+    };
+    let spell = |operation: &str, operands: &[Node]| -> String {
+        std::iter::once(operation)
+            .chain(operands.iter().map(|x| text(*x)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let liveness = liveness(tree, content, aliases).ok();
+    // Lines are renumbered by deletions, so keep them when anything jumps by number
+    let keep_numbering = instructions.iter().any(|x| {
+        jump_kind(x.operation).is_some()
+            && x.operands.last().is_some_and(|target| {
+                target.kind() == "number"
+                    || (resolve(*target) != "ra" && register(*target).is_some())
+                    || resolve(*target) == "sp"
+            })
+    }) || has_define_targets(tree, content, &type_data.defines);
+    let back_to_back = |liveness: &Liveness, first: usize, second: usize| {
+        (first + 1..=second).all(|line| liveness.predecessors(line) == [line - 1])
+    };
+
+    let mut rewrites = Vec::new();
+    let mut consumed = vec![false; instructions.len()];
+    for idx in 0..instructions.len() {
+        let current = &instructions[idx];
+        if consumed[idx] || !lines.contains(&(current.row as u32)) {
+            continue;
+        }
+        let next = instructions
+            .get(idx + 1)
+            .filter(|x| lines.contains(&(x.row as u32)))
+            .filter(|x| {
+                liveness
+                    .as_ref()
+                    .is_some_and(|liveness| back_to_back(liveness, current.row, x.row))
+            });
+        let range = Range::from(current.node.range()).0;
+        let remove = |description: String| Rewrite {
+            description,
+            changes: vec![Change {
+                range,
+                new_text: None,
+            }],
+        };
+        let line = current.row + 1;
+        let original = text(current.node);
+
+        // move r0 r0
+        if current.operation == "move"
+            && current.operands.len() == 2
+            && resolve(current.operands[0]) == resolve(current.operands[1])
+        {
+            rewrites.push(remove(format!("line {line}: remove `{original}`")));
+            consumed[idx] = true;
+            continue;
+        }
+
+        // Dead store
+        if let (Some(liveness), Some(target)) = (
+            &liveness,
+            current.operands.first().and_then(|x| register(*x)),
+        ) {
+            if PURE_INSTRUCTIONS.contains(current.operation)
+                && liveness.defs[current.row] == bit(target)
+                && liveness.live_out[current.row] & bit(target) == 0
+            {
+                rewrites.push(remove(format!(
+                    "line {line}: remove `{original}`, r{target} is never read"
+                )));
+                consumed[idx] = true;
+                continue;
+            }
+        }
+
+        // s* + bnez → b*
+        if let (Some(next), Some(liveness)) = (next, &liveness) {
+            let condition = current.operation.strip_prefix('s').unwrap_or("");
+            let branch_prefix = match next.operation {
+                "bnez" => Some("b"),
+                "brnez" => Some("br"),
+                _ => None,
+            };
+            if let (Some(branch_prefix), Some(flag), &[_, target]) = (
+                branch_prefix,
+                current.operands.first().and_then(|x| register(*x)),
+                next.operands.as_slice(),
+            ) {
+                let branch = format!("{branch_prefix}{condition}");
+                if CONDITIONS.contains(&condition)
+                    && INSTRUCTIONS.contains_key(branch.as_str())
+                    && register(next.operands[0]) == Some(flag)
+                    && liveness.live_out[next.row] & bit(flag) == 0
+                {
+                    let mut operands: Vec<String> = current.operands[1..]
+                        .iter()
+                        .map(|x| text(*x).to_string())
+                        .collect();
+                    // A relative offset now counts from the earlier line
+                    operands.push(match (branch_prefix, text(target).parse::<i64>()) {
+                        ("br", Ok(offset)) => {
+                            (offset + (next.row - current.row) as i64).to_string()
+                        }
+                        _ => text(target).to_string(),
+                    });
+                    let new_text = format!("{} {}", branch, operands.join(" "));
+                    rewrites.push(Rewrite {
+                        description: format!(
+                            "line {line}: `{original}` + `{}` → `{new_text}`",
+                            text(next.node)
+                        ),
+                        changes: vec![
+                            Change {
+                                range,
+                                new_text: Some(new_text),
+                            },
+                            Change {
+                                range: Range::from(next.node.range()).0,
+                                new_text: None,
+                            },
+                        ],
+                    });
+                    consumed[idx] = true;
+                    consumed[idx + 1] = true;
+                    continue;
+                }
+            }
+        }
+
+        // Comparison against 0
+        if let Some((operation, operands)) = zero_form(current, content) {
+            let new_text = spell(&operation, &operands);
+            rewrites.push(Rewrite {
+                description: format!("line {line}: `{original}` → `{new_text}`"),
+                changes: vec![Change {
+                    range,
+                    new_text: Some(new_text),
+                }],
+            });
+            consumed[idx] = true;
+            continue;
+        }
+
+        // s overwritten by the next s
+        if let Some(next) = next {
+            if current.operation == "s"
+                && next.operation == "s"
+                && current.operands.len() == 3
+                && next.operands.len() == 3
+                && resolve(current.operands[0]) == resolve(next.operands[0])
+                && text(current.operands[1]) == text(next.operands[1])
+                && STATE_LOGIC_TYPES.contains(text(current.operands[1]))
+            {
+                rewrites.push(remove(format!(
+                    "line {line}: remove `{original}`, overwritten on line {}",
+                    next.row + 1
+                )));
+                consumed[idx] = true;
+            }
+        }
+    }
+
+    Optimization {
+        rewrites,
+        keep_numbering,
+    }
+}
+
+/// `blt r0 0 x` → `bltz r0 x`, `sgt r1 0 r0` → `sltz r1 r0` and the like.
+fn zero_form<'a>(instruction: &Instruction<'a>, content: &str) -> Option<(String, Vec<Node<'a>>)> {
+    let (prefix, rest) = ["br", "b", "s"]
+        .into_iter()
+        .find_map(|x| Some((x, instruction.operation.strip_prefix(x)?)))?;
+    let (condition, suffix) = match rest.strip_suffix("al") {
+        Some(condition) if prefix != "s" => (condition, "al"),
+        _ => (rest, ""),
+    };
+    if !["eq", "ne", "lt", "gt", "le", "ge"].contains(&condition) {
+        return None;
+    }
+
+    let operands = &instruction.operands;
+    let (first, second) = if prefix == "s" { (1, 2) } else { (0, 1) };
+    if operands.len() != 3 {
+        return None;
+    }
+    let is_zero = |node: Node| {
+        node.kind() == "number"
+            && node
+                .utf8_text(content.as_bytes())
+                .ok()
+                .and_then(|x| x.parse::<f64>().ok())
+                == Some(0.0)
+    };
+    let (condition, kept) = if is_zero(operands[second]) {
+        (condition, operands[first])
+    } else if is_zero(operands[first]) {
+        // 0 < x is x > 0
+        let mirrored = match condition {
+            "lt" => "gt",
+            "gt" => "lt",
+            "le" => "ge",
+            "ge" => "le",
+            x => x,
+        };
+        (mirrored, operands[second])
+    } else {
+        return None;
+    };
+
+    let operation = format!("{prefix}{condition}z{suffix}");
+    if !INSTRUCTIONS.contains_key(operation.as_str()) {
+        return None;
+    }
+    let new_operands = if prefix == "s" {
+        vec![operands[0], kept]
+    } else {
+        vec![kept, operands[2]]
+    };
+    Some((operation, new_operands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::collect_definitions;
+    use tower_lsp::lsp_types::Url;
+    use tree_sitter::Parser;
+
+    fn run(source: &str, lines: RangeInclusive<u32>) -> String {
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let mut type_data = TypeData::default();
+        let url = Url::parse("file:///test.ic10").unwrap();
+        collect_definitions(&url, &tree, source, &mut type_data, &mut Vec::new());
+        optimize(&tree, source, &type_data, lines).apply(source)
+    }
+
+    #[test]
+    fn rewrites_redundant_sequences() {
+        let source = "move r0 r0\nseq r1 r2 5\nbnez r1 done\nblt r3 0 done\nsgt r4 0 r5\ns d0 Setting r4\ns d0 Setting r3\ndone:\nyield\n";
+        assert_eq!(
+            run(source, 0..=u32::MAX),
+            "beq r2 5 done\nbltz r3 done\nsltz r4 r5\ns d0 Setting r3\ndone:\nyield\n"
+        );
+    }
+
+    #[test]
+    fn overwritten_writes_keep_pulses() {
+        let source = "s d0 Activate 1\ns d0 Activate 0\n";
+        assert_eq!(run(source, 0..=u32::MAX), source);
+        let source = "s d0 Activate 1\ns d0 Activate 1\n";
+        assert_eq!(run(source, 0..=u32::MAX), source);
+    }
+
+    #[test]
+    fn dead_stores_keep_line_numbers_for_numeric_jumps() {
+        let source = "move r0 5\nmove r0 6\ns db Setting r0\nj 0\n";
+        assert_eq!(
+            run(source, 0..=u32::MAX),
+            "\nmove r0 6\ns db Setting r0\nj 0\n"
+        );
+        // Outside the selection nothing changes
+        assert_eq!(run(source, 1..=3), source);
+    }
+
+    #[test]
+    fn define_targets_keep_line_numbers() {
+        let source = "define Top 2\nmove r0 r0\nyield\nj Top\n";
+        assert_eq!(run(source, 0..=u32::MAX), "define Top 2\n\nyield\nj Top\n");
+    }
+}
@@ -0,0 +1,446 @@
+//! Source preprocessor for `@include` and parameterized macros.
+//!
+//! Directives live in comments, so an unexpanded script still parses as plain IC10:
+//!
+//! ```text
+//! # @include "pid.ic10"
+//! # @macro clamp(reg, low, high)
+//! max reg reg low
+//! min reg reg high
+//! # @end
+//! # @clamp(r0, 0, 100)
+//! ```
+//!
+//! An included file is pasted in place of its `@include`, once per program, with paths relative
+//! to the including file. A macro invocation is replaced by the macro body with every parameter
+//! substituted as a whole word; labels declared in the body get a numbered suffix per expansion
+//! so a macro can be used more than once. Macro bodies are dropped from the output.
+//!
+//! Every output line records where it came from and the line of the root script that produced
+//! it (the include or invocation site), so diagnostics on the expanded program can be reported
+//! on the script being edited.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::{Diagnostic, Position, Range};
+
+/// Macro invocations may expand other macros up to this depth.
+const MAX_DEPTH: usize = 16;
+
+/// Origin of one line of the expanded program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    /// File the line was written in, `None` for the root script.
+    pub file: Option<PathBuf>,
+    pub line: u32,
+    /// Line of the root script that produced it.
+    pub site: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreprocessError {
+    /// Line of the root script the error is reported on.
+    pub site: u32,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Expansion {
+    pub text: String,
+    /// Origin of every line of `text`.
+    pub lines: Vec<SourceLine>,
+    pub errors: Vec<PreprocessError>,
+}
+
+impl Expansion {
+    /// Move a diagnostic on the expanded program onto the root script. Diagnostics on lines that
+    /// came from elsewhere cover the whole site line and say where the line came from.
+    pub fn map_diagnostic(&self, mut diagnostic: Diagnostic, root: &str) -> Diagnostic {
+        let Some(source) = self.lines.get(diagnostic.range.start.line as usize) else {
+            return diagnostic;
+        };
+        if source.file.is_none() && source.line == source.site {
+            let span = diagnostic.range.end.line - diagnostic.range.start.line;
+            diagnostic.range.start.line = source.site;
+            diagnostic.range.end.line = source.site + span;
+            return diagnostic;
+        }
+
+        let length = root.lines().nth(source.site as usize).map_or(0, str::len);
+        diagnostic.range = Range::new(
+            Position::new(source.site, 0),
+            Position::new(source.site, length as u32),
+        );
+        let origin = match &source.file {
+            Some(file) => format!("{}:{}", file.display(), source.line + 1),
+            None => format!("macro body on line {}", source.line + 1),
+        };
+        diagnostic.message = format!("{}: {}", origin, diagnostic.message);
+        diagnostic
+    }
+
+    /// JSON source map: `sources` lists the root script and every included file, and
+    /// `mappings` holds a `[source, line]` pair (both 0-based) for every output line.
+    pub fn source_map(&self, root: &str) -> serde_json::Value {
+        let mut sources = vec![root.to_string()];
+        let mappings: Vec<[usize; 2]> = self
+            .lines
+            .iter()
+            .map(|x| {
+                let source = match &x.file {
+                    None => 0,
+                    Some(file) => {
+                        let name = file.display().to_string();
+                        match sources.iter().position(|x| *x == name) {
+                            Some(idx) => idx,
+                            None => {
+                                sources.push(name);
+                                sources.len() - 1
+                            }
+                        }
+                    }
+                };
+                [source, x.line as usize]
+            })
+            .collect();
+        serde_json::json!({
+            "version": 1,
+            "sources": sources,
+            "mappings": mappings,
+        })
+    }
+}
+
+/// Whether `content` uses any preprocessor directive.
+pub fn has_directives(content: &str) -> bool {
+    content
+        .lines()
+        .any(|x| !matches!(directive(x), Directive::None))
+}
+
+/// Expand `content`, the script in directory `dir`, reading included files with `read`.
+pub fn expand(
+    content: &str,
+    dir: Option<&Path>,
+    read: impl Fn(&Path) -> std::io::Result<String>,
+) -> Expansion {
+    let mut preprocessor = Preprocessor {
+        read: &read,
+        macros: HashMap::new(),
+        included: HashSet::new(),
+        expansions: HashMap::new(),
+        out: Vec::new(),
+        errors: Vec::new(),
+    };
+    preprocessor.file(content, None, dir, None);
+
+    let mut text: String = preprocessor
+        .out
+        .iter()
+        .map(|(line, _)| line.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    Expansion {
+        text,
+        lines: preprocessor.out.into_iter().map(|(_, x)| x).collect(),
+        errors: preprocessor.errors,
+    }
+}
+
+enum Directive<'a> {
+    None,
+    Include(&'a str),
+    Macro(&'a str, Vec<&'a str>),
+    End,
+    Invoke(&'a str, Vec<&'a str>),
+}
+
+fn directive(line: &str) -> Directive<'_> {
+    let Some(rest) = line
+        .trim()
+        .strip_prefix('#')
+        .and_then(|x| x.trim_start().strip_prefix('@'))
+    else {
+        return Directive::None;
+    };
+    let rest = rest.trim();
+    if let Some(path) = rest.strip_prefix("include") {
+        return Directive::Include(path.trim().trim_matches('"'));
+    }
+    if rest == "end" {
+        return Directive::End;
+    }
+    let (is_macro, call) = match rest.strip_prefix("macro ") {
+        Some(x) => (true, x.trim()),
+        None => (false, rest),
+    };
+    let Some((name, arguments)) = call
+        .strip_suffix(')')
+        .and_then(|x| x.split_once('('))
+        .filter(|(name, _)| is_word(name))
+    else {
+        return Directive::None;
+    };
+    let arguments: Vec<&str> = arguments
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect();
+    if is_macro {
+        Directive::Macro(name, arguments)
+    } else {
+        Directive::Invoke(name, arguments)
+    }
+}
+
+fn is_word(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(is_word_char)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    /// Body lines with the file and line they were written on.
+    body: Vec<(String, Option<PathBuf>, u32)>,
+}
+
+struct Preprocessor<'r> {
+    read: &'r dyn Fn(&Path) -> std::io::Result<String>,
+    macros: HashMap<String, Macro>,
+    included: HashSet<PathBuf>,
+    /// Times each macro has been expanded, numbering its labels.
+    expansions: HashMap<String, usize>,
+    out: Vec<(String, SourceLine)>,
+    errors: Vec<PreprocessError>,
+}
+
+impl Preprocessor<'_> {
+    /// Process a whole file. `site` is the include line, `None` for the root script.
+    fn file(
+        &mut self,
+        content: &str,
+        file: Option<PathBuf>,
+        dir: Option<&Path>,
+        site: Option<u32>,
+    ) {
+        let mut definition: Option<(String, Macro, u32)> = None;
+        for (idx, text) in content.lines().enumerate() {
+            let idx = idx as u32;
+            let site = site.unwrap_or(idx);
+            if let Some((_, body, _)) = &mut definition {
+                if matches!(directive(text), Directive::End) {
+                    let (name, body, _) = definition.take().unwrap();
+                    self.macros.insert(name, body);
+                } else {
+                    body.body.push((text.to_string(), file.clone(), idx));
+                }
+                continue;
+            }
+            match directive(text) {
+                Directive::Macro(name, parameters) => {
+                    let parameters = parameters.into_iter().map(str::to_string).collect();
+                    let body = Macro {
+                        parameters,
+                        body: Vec::new(),
+                    };
+                    definition = Some((name.to_string(), body, site));
+                }
+                Directive::End => self.error(site, "`@end` without a `@macro`".to_string()),
+                _ => self.line(text, file.clone(), idx, site, dir, 0),
+            }
+        }
+        if let Some((name, _, site)) = definition {
+            self.error(site, format!("Macro '{}' is missing its `@end`", name));
+        }
+    }
+
+    /// Emit one line, expanding includes and macro invocations.
+    fn line(
+        &mut self,
+        text: &str,
+        file: Option<PathBuf>,
+        line: u32,
+        site: u32,
+        dir: Option<&Path>,
+        depth: usize,
+    ) {
+        match directive(text) {
+            Directive::Include(path) => {
+                let Some(dir) = dir else {
+                    self.error(
+                        site,
+                        format!("Cannot include \"{}\" from an unsaved script", path),
+                    );
+                    return;
+                };
+                let path = dir.join(path);
+                if !self.included.insert(path.clone()) {
+                    return;
+                }
+                match (self.read)(&path) {
+                    Ok(content) => {
+                        let parent = path.parent().map(Path::to_path_buf);
+                        self.file(&content, Some(path), parent.as_deref(), Some(site));
+                    }
+                    Err(e) => {
+                        self.error(site, format!("Could not include {}: {}", path.display(), e))
+                    }
+                }
+            }
+            Directive::Invoke(name, arguments) => {
+                if depth >= MAX_DEPTH {
+                    self.error(site, format!("Macro '{}' expands too deeply", name));
+                    return;
+                }
+                let Some(body) = self.macros.get(name) else {
+                    self.error(site, format!("Unknown macro '{}'", name));
+                    return;
+                };
+                if body.parameters.len() != arguments.len() {
+                    let message = format!(
+                        "Macro '{}' takes {} arguments, got {}",
+                        name,
+                        body.parameters.len(),
+                        arguments.len()
+                    );
+                    self.error(site, message);
+                    return;
+                }
+
+                let expansion = self.expansions.entry(name.to_string()).or_default();
+                *expansion += 1;
+                let expansion = *expansion;
+                let body = &self.macros[name];
+                let mut substitutions: HashMap<&str, String> = body
+                    .parameters
+                    .iter()
+                    .map(String::as_str)
+                    .zip(arguments.iter().map(|x| x.to_string()))
+                    .collect();
+                for (text, _, _) in &body.body {
+                    if let Some(label) = text.trim().strip_suffix(':').filter(|x| is_word(x)) {
+                        substitutions.insert(label, format!("{}_{}", label, expansion));
+                    }
+                }
+                let lines: Vec<_> = body
+                    .body
+                    .iter()
+                    .map(|(text, file, line)| {
+                        (substitute(text, &substitutions), file.clone(), *line)
+                    })
+                    .collect();
+                for (text, file, line) in lines {
+                    self.line(&text, file, line, site, dir, depth + 1);
+                }
+            }
+            _ => self
+                .out
+                .push((text.to_string(), SourceLine { file, line, site })),
+        }
+    }
+
+    fn error(&mut self, site: u32, message: String) {
+        self.errors.push(PreprocessError { site, message });
+    }
+}
+
+/// Replace whole words of `text` outside strings and comments.
+fn substitute(text: &str, substitutions: &HashMap<&str, String>) -> String {
+    let mut ret = String::with_capacity(text.len());
+    let mut word = String::new();
+    let mut in_string = false;
+    let mut chars = text.chars();
+    let flush = |word: &mut String, ret: &mut String| {
+        match substitutions.get(word.as_str()) {
+            Some(replacement) => ret.push_str(replacement),
+            None => ret.push_str(word),
+        }
+        word.clear();
+    };
+    for c in chars.by_ref() {
+        if !in_string && is_word_char(c) {
+            word.push(c);
+            continue;
+        }
+        flush(&mut word, &mut ret);
+        if c == '#' && !in_string {
+            ret.push(c);
+            break;
+        }
+        if c == '"' {
+            in_string = !in_string;
+        }
+        ret.push(c);
+    }
+    flush(&mut word, &mut ret);
+    ret.extend(chars);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(path: &Path) -> std::io::Result<String> {
+        match path.to_str() {
+            Some("/scripts/lib/pid.ic10") => {
+                Ok("# @include \"util.ic10\"\npid:\nmul r0 r0 2\nj ra\n".into())
+            }
+            Some("/scripts/lib/util.ic10") => Ok(
+                "# @macro clamp(reg, low, high)\nmax reg reg low\nmin reg reg high\n# @end\n"
+                    .into(),
+            ),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        }
+    }
+
+    #[test]
+    fn includes_and_macros_expand_with_their_origins() {
+        let source = "# @include \"lib/pid.ic10\"\n# @macro wait(t)\nwait:\nbgtz t wait # spin\nsub t t 1\n# @end\n# @clamp(r0, 0, 100)\n# @wait(r1)\n# @wait(r2)\n# @include \"lib/pid.ic10\"\n";
+        let expansion = expand(source, Some(Path::new("/scripts")), files);
+        assert!(expansion.errors.is_empty(), "{:?}", expansion.errors);
+        assert_eq!(
+            expansion.text,
+            "pid:\nmul r0 r0 2\nj ra\nmax r0 r0 0\nmin r0 r0 100\nwait_1:\nbgtz r1 wait_1 # spin\nsub r1 r1 1\nwait_2:\nbgtz r2 wait_2 # spin\nsub r2 r2 1\n"
+        );
+        assert_eq!(
+            expansion.lines[3],
+            SourceLine {
+                file: Some(PathBuf::from("/scripts/lib/util.ic10")),
+                line: 1,
+                site: 6,
+            }
+        );
+        let map = expansion.source_map("main.ic10");
+        assert_eq!(map["sources"][1], "/scripts/lib/pid.ic10");
+        assert_eq!(map["mappings"][0], serde_json::json!([1, 1]));
+    }
+
+    #[test]
+    fn diagnostics_and_errors_land_on_the_include_site() {
+        let source =
+            "move r0 1\n# @include \"lib/pid.ic10\"\n# @missing(r0)\n# @include \"nope.ic10\"\n";
+        let expansion = expand(source, Some(Path::new("/scripts")), files);
+        let sites: Vec<u32> = expansion.errors.iter().map(|x| x.site).collect();
+        assert_eq!(sites, vec![2, 3]);
+
+        let diagnostic = Diagnostic {
+            range: Range::new(Position::new(2, 0), Position::new(2, 3)),
+            message: "Unknown register".to_string(),
+            ..Default::default()
+        };
+        let mapped = expansion.map_diagnostic(diagnostic, source);
+        assert_eq!(
+            mapped.range,
+            Range::new(Position::new(1, 0), Position::new(1, 25))
+        );
+        assert_eq!(mapped.message, "/scripts/lib/pid.ic10:3: Unknown register");
+    }
+}
@@ -0,0 +1,185 @@
+//! Reports for pull-model diagnostics (`textDocument/diagnostic` and `workspace/diagnostic`).
+//!
+//! Every report carries a result id derived from the diagnostics themselves. When a client
+//! asks again with the id it already holds and nothing changed, an "unchanged" report is sent
+//! instead of the full list.
+//!
+//! A workspace pass checks every script that is not open. [`WorkspaceCache`] keeps the
+//! diagnostics of each one until its modification time or the settings change, so a repeated
+//! pass only reads metadata for the files that stayed the same.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tower_lsp::lsp_types::{
+    Diagnostic, DocumentDiagnosticReport, DocumentDiagnosticReportResult,
+    FullDocumentDiagnosticReport, RelatedFullDocumentDiagnosticReport,
+    RelatedUnchangedDocumentDiagnosticReport, UnchangedDocumentDiagnosticReport, Url,
+    WorkspaceDocumentDiagnosticReport, WorkspaceFullDocumentDiagnosticReport,
+    WorkspaceUnchangedDocumentDiagnosticReport,
+};
+
+use crate::{diagnostics, preprocessor, Configuration};
+
+/// Identifier of a diagnostic list; equal lists get equal ids.
+pub fn result_id(diagnostics: &[Diagnostic]) -> String {
+    let serialized = serde_json::to_vec(diagnostics).unwrap_or_default();
+    format!("{:08x}", crc32fast::hash(&serialized))
+}
+
+/// Report for an open document, given the result id the client already has.
+pub fn document_report(
+    diagnostics: Vec<Diagnostic>,
+    previous_result_id: Option<&str>,
+) -> DocumentDiagnosticReportResult {
+    let result_id = result_id(&diagnostics);
+    let report = if previous_result_id == Some(result_id.as_str()) {
+        DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+            related_documents: None,
+            unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport { result_id },
+        })
+    } else {
+        DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+            related_documents: None,
+            full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items: diagnostics,
+            },
+        })
+    };
+    DocumentDiagnosticReportResult::Report(report)
+}
+
+/// Report for one file of a workspace diagnostic pass.
+pub fn workspace_report(
+    uri: Url,
+    diagnostics: Vec<Diagnostic>,
+    previous_result_id: Option<&str>,
+) -> WorkspaceDocumentDiagnosticReport {
+    let result_id = result_id(&diagnostics);
+    if previous_result_id == Some(result_id.as_str()) {
+        WorkspaceDocumentDiagnosticReport::Unchanged(WorkspaceUnchangedDocumentDiagnosticReport {
+            uri,
+            version: None,
+            unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport { result_id },
+        })
+    } else {
+        WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+            uri,
+            version: None,
+            full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items: diagnostics,
+            },
+        })
+    }
+}
+
+/// Diagnostics of unopened scripts by path, with the modification time they were computed for.
+#[derive(Default)]
+pub struct WorkspaceCache {
+    config: Option<Configuration>,
+    files: HashMap<PathBuf, (SystemTime, Vec<Diagnostic>)>,
+}
+
+impl WorkspaceCache {
+    /// Diagnostics of the script at `path`, or `None` if it cannot be read. Scripts using
+    /// `@include` are always checked again, as an included file may have changed.
+    pub fn check(
+        &mut self,
+        path: &Path,
+        uri: &Url,
+        config: &Configuration,
+    ) -> Option<Vec<Diagnostic>> {
+        if self.config.as_ref() != Some(config) {
+            self.files.clear();
+            self.config = Some(config.clone());
+        }
+        let modified = fs::metadata(path).and_then(|x| x.modified()).ok();
+        if let Some((cached, diagnostics)) = self.files.get(path) {
+            if Some(*cached) == modified {
+                return Some(diagnostics.clone());
+            }
+        }
+        let content = fs::read_to_string(path).ok()?;
+        let diagnostics = diagnostics::check_script(uri, &content, path.parent(), config);
+        match modified {
+            Some(modified) if !preprocessor::has_directives(&content) => {
+                self.files
+                    .insert(path.to_path_buf(), (modified, diagnostics.clone()));
+            }
+            _ => {
+                self.files.remove(path);
+            }
+        }
+        Some(diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::{Position, Range};
+
+    fn diagnostic(message: &str) -> Diagnostic {
+        Diagnostic {
+            range: Range::new(Position::new(0, 0), Position::new(0, 4)),
+            message: message.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn known_result_id_is_reported_unchanged() {
+        let first = document_report(vec![diagnostic("Syntax error")], None);
+        let DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(full)) = first
+        else {
+            panic!("expected a full report");
+        };
+        let id = full.full_document_diagnostic_report.result_id.unwrap();
+
+        let again = document_report(vec![diagnostic("Syntax error")], Some(&id));
+        assert!(matches!(
+            again,
+            DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Unchanged(_))
+        ));
+
+        let changed = document_report(vec![diagnostic("Invalid instruction")], Some(&id));
+        assert!(matches!(
+            changed,
+            DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(_))
+        ));
+    }
+
+    #[test]
+    fn unchanged_files_are_not_checked_again() {
+        let dir = std::env::temp_dir().join(format!("ic10-workspace-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.ic10");
+        let uri = Url::from_file_path(&path).unwrap();
+        fs::write(&path, "yield\n").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+        let mut cache = WorkspaceCache::default();
+        let config = Configuration::default();
+        assert_eq!(cache.check(&path, &uri, &config), Some(Vec::new()));
+
+        // Same modification time: the cached result is used
+        fs::write(&path, "bogus r0\n").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(cache.check(&path, &uri, &config), Some(Vec::new()));
+
+        // Other settings invalidate it
+        let mut config = config;
+        config.max_lines = 1;
+        assert!(!cache.check(&path, &uri, &config).unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! Index of identifier references in a document.
+//!
+//! Every identifier used as an operand is recorded under its exact text, except the name
+//! operand of `define`, `alias` and `label`, which declares rather than references it.
+//! Label definitions (`name:`) are not operands and are never recorded.
+
+use std::collections::HashMap;
+
+use tree_sitter::{Query, QueryCursor, Tree};
+
+use crate::Range;
+
+#[derive(Debug, Default)]
+pub struct ReferenceIndex {
+    references: HashMap<String, Vec<Range>>,
+}
+
+impl ReferenceIndex {
+    pub fn build(tree: &Tree, content: &str) -> Self {
+        let mut index = ReferenceIndex::default();
+
+        let mut cursor = QueryCursor::new();
+        let query = Query::new(
+            tree_sitter_ic10::language(),
+            "(instruction operand: (operand (identifier)@identifier))",
+        )
+        .unwrap();
+
+        for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+            let node = capture.captures[0].node;
+            let Some(operand) = node.parent() else {
+                continue;
+            };
+            let Some(instruction) = operand.parent() else {
+                continue;
+            };
+            if is_declaration_name(instruction, operand, content) {
+                continue;
+            }
+            let name = node.utf8_text(content.as_bytes()).unwrap();
+            index
+                .references
+                .entry(name.to_string())
+                .or_default()
+                .push(Range::from(node.range()));
+        }
+
+        index
+    }
+
+    /// Ranges of every reference to `name`, in document order.
+    pub fn get(&self, name: &str) -> &[Range] {
+        self.references.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn count(&self, name: &str) -> usize {
+        self.get(name).len()
+    }
+
+    /// The declaration of `name` followed by every reference to it, provided `position` lies
+    /// on one of them. These are the ranges an editor keeps in sync while the name is typed.
+    pub fn linked_ranges(
+        &self,
+        name: &str,
+        declaration: &Range,
+        position: tower_lsp::lsp_types::Position,
+    ) -> Option<Vec<Range>> {
+        let mut ranges = vec![declaration.clone()];
+        ranges.extend(self.get(name).iter().cloned());
+        ranges
+            .iter()
+            .any(|x| x.0.start <= position && position <= x.0.end)
+            .then_some(ranges)
+    }
+}
+
+/// Whether `operand` is the name being declared by a `define`, `alias` or `label` instruction.
+pub fn is_declaration_name(
+    instruction: tree_sitter::Node,
+    operand: tree_sitter::Node,
+    content: &str,
+) -> bool {
+    let Some(operation) = instruction.child_by_field_name("operation") else {
+        return false;
+    };
+    let operation = operation.utf8_text(content.as_bytes()).unwrap_or("");
+    if !matches!(operation, "define" | "alias" | "label") {
+        return false;
+    }
+    instruction.child_by_field_name("operand").map(|x| x.id()) == Some(operand.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    #[test]
+    fn declarations_are_not_references() {
+        let source = "define Limit 5\nalias sensor d0\nloop:\nl r0 sensor Temperature\nblt r0 Limit loop\nj loop\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let index = ReferenceIndex::build(&tree, source);
+
+        assert_eq!(index.count("Limit"), 1);
+        assert_eq!(index.count("sensor"), 1);
+        assert_eq!(index.count("loop"), 2);
+        assert_eq!(index.get("loop")[0].0.start.line, 4);
+        assert_eq!(index.count("missing"), 0);
+
+        let declaration = Range(tower_lsp::lsp_types::Range::new(
+            tower_lsp::lsp_types::Position::new(2, 0),
+            tower_lsp::lsp_types::Position::new(2, 4),
+        ));
+        let on_reference = tower_lsp::lsp_types::Position::new(5, 3);
+        let ranges = index
+            .linked_ranges("loop", &declaration, on_reference)
+            .unwrap();
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[0].0, declaration.0);
+        let elsewhere = tower_lsp::lsp_types::Position::new(0, 3);
+        assert!(index
+            .linked_ranges("loop", &declaration, elsewhere)
+            .is_none());
+    }
+}
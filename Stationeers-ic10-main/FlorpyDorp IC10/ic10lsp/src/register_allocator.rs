@@ -0,0 +1,424 @@
+//! Register reallocation to free up general-purpose registers.
+//!
+//! Each of `r0`–`r15` is treated as one variable for the whole program, using the reads and
+//! assignments found by [`RegisterAnalyzer`]. Liveness is computed over the line-level control
+//! flow graph, two registers interfere when both hold a needed value at the same line, and the
+//! interference graph is coloured greedily so registers that are never live together share one
+//! register. Every raw operand, `alias` target and `drN` device is then rewritten.
+//!
+//! Control flow is approximated conservatively: `j ra` may return after any call, a jump through
+//! any other register may land anywhere, and running off the end continues at line 0.
+
+use std::collections::{BTreeMap, HashMap};
+
+use tower_lsp::lsp_types::TextEdit;
+use tree_sitter::{Query, QueryCursor, Tree};
+
+use crate::call_hierarchy::CALL_INSTRUCTIONS;
+use crate::jump_labels::jump_kind;
+use crate::register_analysis::RegisterAnalyzer;
+use crate::{AliasValue, DefinitionData, Range};
+
+/// Number of general-purpose registers, `r0` to `r15`.
+const REGISTER_COUNT: usize = 16;
+
+pub(crate) type RegisterSet = u16;
+
+pub(crate) fn bit(register: usize) -> RegisterSet {
+    1 << register
+}
+
+/// Per-line register reads, writes and liveness, indexed by line.
+pub(crate) struct Liveness {
+    pub successors: Vec<Vec<usize>>,
+    pub uses: Vec<RegisterSet>,
+    pub defs: Vec<RegisterSet>,
+    pub live_in: Vec<RegisterSet>,
+    pub live_out: Vec<RegisterSet>,
+}
+
+impl Liveness {
+    /// Lines that may continue at `line`.
+    pub fn predecessors(&self, line: usize) -> Vec<usize> {
+        (0..self.successors.len())
+            .filter(|x| self.successors[*x].contains(&line))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Allocation {
+    /// New register for every register the program uses.
+    pub mapping: BTreeMap<usize, usize>,
+    /// Registers in use before and after reallocation.
+    pub before: usize,
+    pub after: usize,
+    pub edits: Vec<TextEdit>,
+}
+
+/// Compute a reallocation of `r0`–`r15`. Fails for programs that address registers indirectly
+/// (`rr0`, `drr0`), since any register could then be read or written.
+pub fn allocate(
+    tree: &Tree,
+    content: &str,
+    aliases: &HashMap<String, DefinitionData<AliasValue>>,
+) -> Result<Allocation, String> {
+    let operands = collect_register_operands(tree, content);
+    let Liveness {
+        uses,
+        defs,
+        live_in,
+        live_out,
+        ..
+    } = liveness(tree, content, aliases).map_err(|line| {
+        format!(
+            "Line {} addresses registers indirectly; registers cannot be reassigned safely",
+            line + 1
+        )
+    })?;
+    let line_count = uses.len();
+
+    let mut interference = [0 as RegisterSet; REGISTER_COUNT];
+    for line in 0..line_count {
+        for (register, neighbours) in interference.iter_mut().enumerate() {
+            if live_in[line] & bit(register) != 0 {
+                *neighbours |= live_in[line];
+            }
+            if defs[line] & bit(register) != 0 {
+                *neighbours |= live_out[line];
+            }
+        }
+    }
+    for (register, neighbours) in interference.iter_mut().enumerate() {
+        *neighbours &= !bit(register);
+    }
+    // A written register clobbers whatever is live there, both ways
+    for register in 0..REGISTER_COUNT {
+        for other in 0..REGISTER_COUNT {
+            if interference[register] & bit(other) != 0 {
+                interference[other] |= bit(register);
+            }
+        }
+    }
+
+    let used: RegisterSet = uses.iter().chain(&defs).fold(0, |acc, x| acc | x);
+    let mut mapping = BTreeMap::new();
+    for register in (0..REGISTER_COUNT).filter(|x| used & bit(*x) != 0) {
+        let taken = mapping
+            .iter()
+            .filter(|(other, _)| interference[register] & bit(**other) != 0)
+            .fold(0, |acc, (_, color)| acc | bit(*color));
+        let color = (0..REGISTER_COUNT).find(|x| taken & bit(*x) == 0).unwrap();
+        mapping.insert(register, color);
+    }
+
+    let before = mapping.len();
+    let mut after: Vec<_> = mapping.values().collect();
+    after.sort();
+    after.dedup();
+    let after = after.len();
+
+    let edits = if after < before {
+        operands
+            .iter()
+            .filter(|x| mapping.get(&x.register).is_some_and(|y| *y != x.register))
+            .map(|x| {
+                let prefix = if x.device { "dr" } else { "r" };
+                TextEdit::new(
+                    x.range.clone().into(),
+                    format!("{}{}", prefix, mapping[&x.register]),
+                )
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Ok(Allocation {
+        mapping,
+        before,
+        after,
+        edits,
+    })
+}
+
+/// Register liveness over the line-level control flow graph. Fails with the (0-based) line of
+/// the first indirect register access (`rr0`, `drr0`), since any register could then be read or
+/// written.
+pub(crate) fn liveness(
+    tree: &Tree,
+    content: &str,
+    aliases: &HashMap<String, DefinitionData<AliasValue>>,
+) -> Result<Liveness, u32> {
+    let line_count = content.lines().count().max(1);
+    let operands = collect_register_operands(tree, content);
+    if let Some(indirect) = operands.iter().find(|x| x.indirect) {
+        return Err(indirect.range.0.start.line);
+    }
+
+    // Reads and writes per line
+
+    let mut uses = vec![0 as RegisterSet; line_count];
+    let mut defs = vec![0 as RegisterSet; line_count];
+    let mut analyzer = RegisterAnalyzer::new();
+    analyzer.analyze_register_usage(tree, content, aliases);
+    for (name, usage) in analyzer.get_register_usage() {
+        let Some(register) = register_index(name) else {
+            continue;
+        };
+        for range in &usage.assignments {
+            if let Some(x) = defs.get_mut(range.0.start.line as usize) {
+                *x |= bit(register);
+            }
+        }
+        for range in &usage.reads {
+            if let Some(x) = uses.get_mut(range.0.start.line as usize) {
+                *x |= bit(register);
+            }
+        }
+    }
+    // `drN` reads rN to find the device
+    for operand in operands.iter().filter(|x| x.device) {
+        uses[operand.range.0.start.line as usize] |= bit(operand.register);
+    }
+    // Naming a register in an `alias` does not read it
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(instruction (operation)@x)").unwrap();
+    for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+        let node = capture.captures[0].node;
+        if node.utf8_text(content.as_bytes()) == Ok("alias") {
+            uses[node.start_position().row] = 0;
+        }
+    }
+
+    let successors = control_flow(tree, content, line_count, aliases);
+
+    // Backwards liveness until nothing changes
+    let mut live_in = vec![0 as RegisterSet; line_count];
+    let mut live_out = vec![0 as RegisterSet; line_count];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for line in (0..line_count).rev() {
+            let out = successors[line].iter().fold(0, |acc, x| acc | live_in[*x]);
+            let input = uses[line] | (out & !defs[line]);
+            if out != live_out[line] || input != live_in[line] {
+                live_out[line] = out;
+                live_in[line] = input;
+                changed = true;
+            }
+        }
+    }
+
+    Ok(Liveness {
+        successors,
+        uses,
+        defs,
+        live_in,
+        live_out,
+    })
+}
+
+/// `3` for `r3`, `None` for anything that is not a direct general-purpose register.
+fn register_index(name: &str) -> Option<usize> {
+    name.strip_prefix('r')?
+        .parse::<usize>()
+        .ok()
+        .filter(|x| *x < REGISTER_COUNT)
+}
+
+#[derive(Debug)]
+struct RegisterOperand {
+    register: usize,
+    /// `drN` rather than `rN`
+    device: bool,
+    /// `rrN` or `drrN`
+    indirect: bool,
+    range: Range,
+}
+
+/// Every register and register-addressed device written in the source.
+fn collect_register_operands(tree: &Tree, content: &str) -> Vec<RegisterOperand> {
+    let mut ret = Vec::new();
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(register)@x (device)@x").unwrap();
+    for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+        let node = capture.captures[0].node;
+        let text = node.utf8_text(content.as_bytes()).unwrap();
+        let device = node.kind() == "device";
+        let text = if device { &text[1..] } else { text };
+        let digits = text.trim_start_matches('r');
+        let prefix = text.len() - digits.len();
+        let Ok(register) = digits.parse::<usize>() else {
+            continue;
+        };
+        if prefix == 0 || register >= REGISTER_COUNT {
+            continue;
+        }
+        ret.push(RegisterOperand {
+            register,
+            device,
+            indirect: prefix > 1,
+            range: node.range().into(),
+        });
+    }
+    ret
+}
+
+/// Lines each line may continue at.
+fn control_flow(
+    tree: &Tree,
+    content: &str,
+    line_count: usize,
+    aliases: &HashMap<String, DefinitionData<AliasValue>>,
+) -> Vec<Vec<usize>> {
+    let mut labels = HashMap::new();
+    let mut cursor = QueryCursor::new();
+    let query = Query::new(tree_sitter_ic10::language(), "(label (identifier)@x)").unwrap();
+    for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+        let node = capture.captures[0].node;
+        labels.insert(
+            node.utf8_text(content.as_bytes()).unwrap(),
+            node.start_position().row,
+        );
+    }
+
+    let mut successors: Vec<Vec<usize>> = (0..line_count)
+        .map(|x| vec![(x + 1) % line_count])
+        .collect();
+    let mut returns = Vec::new();
+    let mut return_jumps = Vec::new();
+
+    let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
+    for (capture, _) in cursor.captures(&query, tree.root_node(), content.as_bytes()) {
+        let node = capture.captures[0].node;
+        let line = node.start_position().row;
+        let Some(operation) = node
+            .child_by_field_name("operation")
+            .and_then(|x| x.utf8_text(content.as_bytes()).ok())
+        else {
+            continue;
+        };
+        let Some(relative) = jump_kind(operation) else {
+            continue;
+        };
+        if CALL_INSTRUCTIONS.contains(operation) {
+            returns.push((line + 1) % line_count);
+        }
+        if matches!(operation, "j" | "jr" | "jal") {
+            successors[line].clear();
+        }
+
+        let mut tree_cursor = node.walk();
+        let Some(target) = node
+            .children_by_field_name("operand", &mut tree_cursor)
+            .last()
+            .and_then(|x| x.child(0))
+        else {
+            continue;
+        };
+        let text = target.utf8_text(content.as_bytes()).unwrap();
+        let resolved = match target.kind() {
+            "number" => text
+                .parse::<i64>()
+                .ok()
+                .map(|x| if relative { line as i64 + x } else { x }),
+            "identifier" => labels.get(text).map(|x| *x as i64),
+            _ => None,
+        };
+        match resolved {
+            Some(target) if (0..line_count as i64).contains(&target) => {
+                successors[line].push(target as usize);
+            }
+            // Jumping past the end starts over
+            Some(_) => successors[line].push(0),
+            None => {
+                let register = match aliases.get(text).map(|x| &x.value) {
+                    Some(AliasValue::Register(register)) => register.as_str(),
+                    _ => text,
+                };
+                if register == "ra" {
+                    return_jumps.push(line);
+                } else {
+                    successors[line].extend(0..line_count);
+                }
+            }
+        }
+    }
+    for line in return_jumps {
+        successors[line].extend(returns.iter().copied());
+    }
+
+    successors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    fn run(source: &str) -> Result<Allocation, String> {
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        allocate(&tree, source, &HashMap::new())
+    }
+
+    #[test]
+    fn disjoint_registers_share_one() {
+        let source = "l r0 d0 Temperature\ns db Setting r0\nl r5 d1 Pressure\ns db Setting r5\nl r9 d2 Ratio\ns db Setting r9\nyield\n";
+        let allocation = run(source).unwrap();
+        assert_eq!((allocation.before, allocation.after), (3, 1));
+        assert_eq!(allocation.edits.len(), 4);
+        assert!(allocation.edits.iter().all(|x| x.new_text == "r0"));
+    }
+
+    #[test]
+    fn values_live_around_a_loop_keep_their_register() {
+        // r1 is read at the top of every iteration, so it is live across the whole loop
+        let source = "move r1 0\nloop:\nadd r1 r1 1\nl r2 d0 On\ns d1 On r2\nj loop\n";
+        let allocation = run(source).unwrap();
+        assert_eq!((allocation.before, allocation.after), (2, 2));
+        assert!(allocation.edits.is_empty());
+    }
+
+    #[test]
+    fn alias_targets_are_rewritten() {
+        let source = "alias temp r4\nalias count r7\nl temp d0 Temperature\ns db Setting temp\nl count d1 Pressure\ns db Setting count\nyield\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let mut aliases = HashMap::new();
+        for (name, register, line) in [("temp", "r4", 0), ("count", "r7", 1)] {
+            aliases.insert(
+                name.to_string(),
+                DefinitionData::new(
+                    tower_lsp::lsp_types::Range::new(
+                        tower_lsp::lsp_types::Position::new(line, 0),
+                        tower_lsp::lsp_types::Position::new(line, 13),
+                    )
+                    .into(),
+                    AliasValue::Register(register.to_string()),
+                ),
+            );
+        }
+        let allocation = allocate(&tree, source, &aliases).unwrap();
+        // The values never overlap, so both aliases end up naming r0
+        assert_eq!((allocation.before, allocation.after), (2, 1));
+        let alias_edits: Vec<_> = allocation
+            .edits
+            .iter()
+            .filter(|x| x.range.start.line < 2)
+            .map(|x| {
+                let start = x.range.start;
+                (start.line, start.character, x.new_text.as_str())
+            })
+            .collect();
+        assert_eq!(alias_edits, vec![(0, 11, "r0"), (1, 12, "r0")]);
+    }
+
+    #[test]
+    fn indirect_access_is_refused() {
+        assert!(run("move r0 1\nmove rr0 2\n").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Expansion of `--diagnose` arguments into script paths.
+//!
+//! An argument naming a directory stands for every `.ic10` file below it. An argument with `*`,
+//! `?` or `**` in it is a glob matched against the `.ic10` files below its literal leading
+//! directories; `*` and `?` stay within one path component and `**` spans any number of them,
+//! so `bases/**/*.ic10` finds the scripts of every base at any depth. Anything else is a file.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::workspace_index::find_ic10_files;
+
+/// Scripts named by `args`, each once and in argument order, and the globs that matched nothing.
+pub fn expand(args: &[PathBuf]) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut unmatched = Vec::new();
+    for arg in args {
+        let found = if arg.is_dir() {
+            find_ic10_files(arg)
+        } else if is_glob(arg) {
+            let found = glob(arg);
+            if found.is_empty() {
+                unmatched.push(arg.clone());
+            }
+            found
+        } else {
+            vec![arg.clone()]
+        };
+        for path in found {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    (paths, unmatched)
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_str().is_some_and(|x| x.contains(['*', '?']))
+}
+
+/// The `.ic10` files matching `pattern`.
+fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let components: Vec<Component> = pattern.components().collect();
+    let literal = components
+        .iter()
+        .take_while(|x| !is_glob(Path::new(x.as_os_str())))
+        .count();
+    let base: PathBuf = components[..literal].iter().collect();
+    let rest: Vec<&str> = components[literal..]
+        .iter()
+        .filter_map(|x| x.as_os_str().to_str())
+        .collect();
+
+    let root = if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base.as_path()
+    };
+    find_ic10_files(root)
+        .into_iter()
+        .filter(|path| {
+            let relative: Vec<&str> = path
+                .strip_prefix(root)
+                .unwrap_or(path)
+                .components()
+                .filter_map(|x| x.as_os_str().to_str())
+                .collect();
+            matches_components(&rest, &relative)
+        })
+        .map(|path| {
+            // Keep the spelling of the pattern rather than the `./` of the search root
+            if base.as_os_str().is_empty() {
+                path.strip_prefix(root)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(path)
+            } else {
+                path
+            }
+        })
+        .collect()
+}
+
+fn matches_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| matches_components(rest, &path[skip..])),
+        Some((first, rest)) => path.split_first().is_some_and(|(name, tail)| {
+            matches_name(first, name) && matches_components(rest, tail)
+        }),
+    }
+}
+
+/// Whether one path component matches a pattern of literal characters, `*` and `?`.
+fn matches_name(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Positions to resume from after the last `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn directories_and_globs_expand_to_scripts() {
+        let root = std::env::temp_dir().join(format!("ic10-script-files-{}", std::process::id()));
+        for file in [
+            "alpha/airlock.ic10",
+            "alpha/notes.txt",
+            "alpha/power/solar.ic10",
+            "beta/furnace.ic10",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "yield\n").unwrap();
+        }
+
+        let (paths, unmatched) = expand(&[
+            root.join("alpha/**/*.ic10"),
+            root.join("beta"),
+            root.join("alpha/airlock.ic10"),
+            root.join("*/missing?.ic10"),
+        ]);
+        assert_eq!(
+            paths,
+            vec![
+                root.join("alpha/airlock.ic10"),
+                root.join("alpha/power/solar.ic10"),
+                root.join("beta/furnace.ic10"),
+            ]
+        );
+        assert_eq!(unmatched, vec![root.join("*/missing?.ic10")]);
+
+        assert!(matches_name("sol*.ic10", "solar.ic10"));
+        assert!(!matches_name("sol?.ic10", "solar.ic10"));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
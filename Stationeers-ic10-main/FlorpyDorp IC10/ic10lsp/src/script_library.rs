@@ -0,0 +1,381 @@
+//! Conversion between `.ic10` files and the game's saved-script library.
+//!
+//! The game keeps each library script in its own folder under `scripts/`, as an `instruction.xml`
+//! holding the title, description, author, game version and the program text. In an `.ic10` file
+//! the metadata lives in a header of `# Key: value` comments at the top, so a library can be kept
+//! under version control and converted back and forth without losing it:
+//!
+//! ```text
+//! # Title: Furnace Controller
+//! # Author: Ada
+//! # Description: Keeps the furnace below its limit
+//! # Game Version: 0.2.5228.24247
+//!
+//! l r0 d0 Temperature
+//! ```
+//!
+//! A description spanning several lines repeats the `Description` key once per line.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name of a saved script inside its folder.
+pub const INSTRUCTION_FILE: &str = "instruction.xml";
+
+/// One script of the in-game library.
+#[derive(Debug, Default, PartialEq)]
+pub struct SavedScript {
+    pub title: String,
+    pub description: String,
+    pub author: String,
+    pub game_version: String,
+    pub instructions: String,
+}
+
+impl SavedScript {
+    /// Read a script from `.ic10` text, taking its metadata from the header comments. The title
+    /// falls back to `name` when the header has none.
+    pub fn from_ic10(name: &str, content: &str) -> Self {
+        let mut script = SavedScript {
+            title: name.to_string(),
+            ..Default::default()
+        };
+        let mut description = Vec::new();
+        let mut lines = content.lines().peekable();
+        let mut header = 0;
+        while let Some(line) = lines.peek() {
+            let Some((key, value)) = header_field(line) else {
+                break;
+            };
+            match key.as_str() {
+                "title" => script.title = value,
+                "description" => description.push(value),
+                "author" => script.author = value,
+                _ => script.game_version = value,
+            }
+            lines.next();
+            header += 1;
+        }
+        if header > 0 && lines.peek().is_some_and(|x| x.trim().is_empty()) {
+            lines.next();
+        }
+        script.description = description.join("\n");
+        script.instructions = lines.collect::<Vec<_>>().join("\n");
+        script
+    }
+
+    /// The script as `.ic10` text with its metadata in header comments.
+    pub fn to_ic10(&self) -> String {
+        let mut ret = format!("# Title: {}\n", self.title);
+        if !self.author.is_empty() {
+            ret += &format!("# Author: {}\n", self.author);
+        }
+        if !self.description.is_empty() {
+            for line in self.description.lines() {
+                ret += &format!("# Description: {}\n", line);
+            }
+        }
+        if !self.game_version.is_empty() {
+            ret += &format!("# Game Version: {}\n", self.game_version);
+        }
+        ret += "\n";
+        ret += self.instructions.trim_end_matches('\n');
+        ret += "\n";
+        ret
+    }
+
+    /// Read a script from the contents of an `instruction.xml`.
+    pub fn from_xml(xml: &str) -> Result<Self, String> {
+        if !xml.contains("<InstructionData") {
+            return Err("not a saved script: missing <InstructionData>".to_string());
+        }
+        Ok(SavedScript {
+            title: element(xml, "Title").unwrap_or_default(),
+            description: element(xml, "Description").unwrap_or_default(),
+            author: element(xml, "Author").unwrap_or_default(),
+            game_version: element(xml, "GameVersion").unwrap_or_default(),
+            instructions: element(xml, "Instructions").unwrap_or_default(),
+        })
+    }
+
+    /// The script as the contents of an `instruction.xml`, stamped with the current time.
+    pub fn to_xml(&self) -> String {
+        // .NET ticks (100ns since 0001-01-01), which the game sorts its library by
+        let ticks = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs() + 62_135_596_800)
+            * 10_000_000;
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <InstructionData xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n  \
+             <DateTime>{}</DateTime>\n  \
+             <GameVersion>{}</GameVersion>\n  \
+             <Title>{}</Title>\n  \
+             <Description>{}</Description>\n  \
+             <Author>{}</Author>\n  \
+             <WorkshopFileHandle>0</WorkshopFileHandle>\n  \
+             <Instructions>{}</Instructions>\n\
+             </InstructionData>\n",
+            ticks,
+            escape(&self.game_version),
+            escape(&self.title),
+            escape(&self.description),
+            escape(&self.author),
+            escape(&self.instructions),
+        )
+    }
+}
+
+/// Write every script of `files` into its own folder below `scripts_dir`, named after its title.
+/// A title already exported in this run gets a numbered folder, like `Furnace (2)`. `warn` is
+/// told about renamed folders and about each existing `instruction.xml` before it is replaced.
+/// Returns the written `instruction.xml` paths.
+pub fn export(
+    files: &[PathBuf],
+    scripts_dir: &Path,
+    mut warn: impl FnMut(&str),
+) -> Result<Vec<PathBuf>, String> {
+    let mut ret = Vec::new();
+    // Folder names used so far, lowercased as the game's file systems ignore case
+    let mut used = HashSet::new();
+    for path in files {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap_or("Script");
+        let script = SavedScript::from_ic10(name, &content);
+        let base = folder_name(&script.title);
+        let folder = (1..)
+            .map(|x| match x {
+                1 => base.clone(),
+                x => format!("{} ({})", base, x),
+            })
+            .find(|x| used.insert(x.to_lowercase()))
+            .unwrap();
+        if folder != base {
+            warn(&format!(
+                "{}: another script is titled '{}', writing it to {}",
+                path.display(),
+                script.title,
+                folder
+            ));
+        }
+        let dir = scripts_dir.join(folder);
+        let target = dir.join(INSTRUCTION_FILE);
+        if target.exists() {
+            warn(&format!("Replacing {}", target.display()));
+        }
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&target, script.to_xml()))
+            .map_err(|e| format!("Could not write {}: {}", target.display(), e))?;
+        ret.push(target);
+    }
+    Ok(ret)
+}
+
+/// Write every saved script below `scripts_dir` to `<folder>.ic10` in `out_dir`. Returns the
+/// written paths.
+pub fn import(scripts_dir: &Path, out_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(scripts_dir)
+        .map_err(|e| format!("Could not read {}: {}", scripts_dir.display(), e))?;
+    let mut folders: Vec<PathBuf> = entries
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| x.join(INSTRUCTION_FILE).is_file())
+        .collect();
+    folders.sort();
+
+    fs::create_dir_all(out_dir)
+        .map_err(|e| format!("Could not create {}: {}", out_dir.display(), e))?;
+    let mut ret = Vec::new();
+    for folder in folders {
+        let source = folder.join(INSTRUCTION_FILE);
+        let xml = fs::read_to_string(&source)
+            .map_err(|e| format!("Could not read {}: {}", source.display(), e))?;
+        let script =
+            SavedScript::from_xml(&xml).map_err(|e| format!("{}: {}", source.display(), e))?;
+        let name = folder.file_name().unwrap().to_string_lossy();
+        let target = out_dir.join(format!("{}.ic10", name));
+        fs::write(&target, script.to_ic10())
+            .map_err(|e| format!("Could not write {}: {}", target.display(), e))?;
+        ret.push(target);
+    }
+    Ok(ret)
+}
+
+/// `(key, value)` of a `# Key: value` header comment with a known key.
+fn header_field(line: &str) -> Option<(String, String)> {
+    let (key, value) = line.trim().strip_prefix('#')?.split_once(':')?;
+    let key = key.trim().to_ascii_lowercase().replace(' ', "");
+    let key = match key.as_str() {
+        "title" | "description" | "author" => key,
+        "gameversion" | "version" => "gameversion".to_string(),
+        _ => return None,
+    };
+    Some((key, value.trim().to_string()))
+}
+
+/// A folder name for `title` without characters file systems reject.
+fn folder_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| {
+            if c.is_control() || "<>:\"/\\|?*".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    match name.trim().trim_end_matches('.') {
+        "" => "Script".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Text of the first `<tag>` element, unescaped. An empty `<tag />` yields an empty string.
+fn element(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}", tag);
+    let mut from = 0;
+    while let Some(found) = xml[from..].find(&open) {
+        let start = from + found + open.len();
+        let rest = &xml[start..];
+        // Skip longer names sharing the prefix, like <TitleId>
+        match rest.chars().next() {
+            Some('>') => {
+                let end = rest.find(&format!("</{}>", tag))?;
+                return Some(unescape(&rest[1..end]));
+            }
+            Some(c) if c.is_whitespace() || c == '/' => {
+                let close = rest.find('>')?;
+                if rest[..close].ends_with('/') {
+                    return Some(String::new());
+                }
+                let end = rest.find(&format!("</{}>", tag))?;
+                return Some(unescape(&rest[close + 1..end]));
+            }
+            _ => from = start,
+        }
+    }
+    None
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub(crate) fn unescape(text: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        ret += &rest[..amp];
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|x| u32::from_str_radix(x, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|x| x.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                ret.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                ret.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    ret += rest;
+    ret.replace("\r\n", "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_comments_round_trip_through_xml() {
+        let source = "# Title: Furnace <Main>\n# Author: Ada\n# Description: Keeps the furnace\n# Description: below its limit\n# Game Version: 0.2.5228.24247\n\n# Loop\nl r0 d0 Temperature\nbrgt r0 500 -1\n";
+        let script = SavedScript::from_ic10("furnace", source);
+        assert_eq!(script.title, "Furnace <Main>");
+        assert_eq!(script.description, "Keeps the furnace\nbelow its limit");
+        assert_eq!(
+            script.instructions,
+            "# Loop\nl r0 d0 Temperature\nbrgt r0 500 -1"
+        );
+
+        let xml = script.to_xml();
+        assert!(xml.contains("<Title>Furnace &lt;Main&gt;</Title>"));
+        let read = SavedScript::from_xml(&xml).unwrap();
+        assert_eq!(read, script);
+        assert_eq!(read.to_ic10(), source);
+        assert_eq!(folder_name(&script.title), "Furnace _Main_");
+    }
+
+    #[test]
+    fn game_files_with_empty_elements_are_read() {
+        let xml = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n<InstructionData xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">\r\n  <DateTime>638412345678901234</DateTime>\r\n  <GameVersion>0.2.4810.22432</GameVersion>\r\n  <Title>Airlock</Title>\r\n  <Description />\r\n  <Author>Bob</Author>\r\n  <Instructions>alias door d0&#xD;\ns door Open 1&#10;</Instructions>\r\n</InstructionData>";
+        let script = SavedScript::from_xml(xml).unwrap();
+        assert_eq!(script.description, "");
+        assert_eq!(script.author, "Bob");
+        assert_eq!(script.instructions, "alias door d0\ns door Open 1\n");
+        assert_eq!(
+            SavedScript::from_ic10("airlock", "alias door d0\n").title,
+            "airlock"
+        );
+    }
+
+    #[test]
+    fn export_numbers_duplicate_titles_and_warns_before_replacing() {
+        let dir = std::env::temp_dir().join(format!("ic10-library-{}", std::process::id()));
+        let sources = dir.join("src");
+        let scripts = dir.join("scripts");
+        fs::create_dir_all(&sources).unwrap();
+        let files: Vec<PathBuf> = ["a", "b"]
+            .iter()
+            .map(|x| {
+                let path = sources.join(format!("{}.ic10", x));
+                fs::write(&path, "# Title: Furnace\n\nyield\n").unwrap();
+                path
+            })
+            .collect();
+
+        let mut warnings = Vec::new();
+        let written = export(&files, &scripts, |x| warnings.push(x.to_string())).unwrap();
+        assert_eq!(
+            written,
+            vec![
+                scripts.join("Furnace").join(INSTRUCTION_FILE),
+                scripts.join("Furnace (2)").join(INSTRUCTION_FILE),
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].ends_with("writing it to Furnace (2)"));
+
+        warnings.clear();
+        export(&files[..1], &scripts, |x| warnings.push(x.to_string())).unwrap();
+        assert_eq!(
+            warnings,
+            vec![format!("Replacing {}", written[0].display())]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
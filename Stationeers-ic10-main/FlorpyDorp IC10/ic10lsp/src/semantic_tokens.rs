@@ -0,0 +1,127 @@
+//! Result tracking for semantic token delta requests.
+//!
+//! Each full response is stored under a fresh result id. When the client later asks for a
+//! delta against that id, only the span of tokens that changed is sent back.
+
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokens, SemanticTokensDelta, SemanticTokensEdit,
+    SemanticTokensFullDeltaResult, Url,
+};
+
+/// Number of integers each token occupies in the encoded data array.
+const TOKEN_WIDTH: u32 = 5;
+
+#[derive(Default)]
+pub struct TokenCache {
+    next_id: u64,
+    results: HashMap<Url, (String, Vec<SemanticToken>)>,
+}
+
+impl TokenCache {
+    fn store(&mut self, uri: &Url, tokens: Vec<SemanticToken>) -> String {
+        self.next_id += 1;
+        let result_id = self.next_id.to_string();
+        self.results
+            .insert(uri.clone(), (result_id.clone(), tokens));
+        result_id
+    }
+
+    /// Record `tokens` as the latest full result for `uri`.
+    pub fn full(&mut self, uri: &Url, tokens: Vec<SemanticToken>) -> SemanticTokens {
+        let result_id = self.store(uri, tokens.clone());
+        SemanticTokens {
+            result_id: Some(result_id),
+            data: tokens,
+        }
+    }
+
+    /// Edits from the result `previous_result_id` to `tokens`, or the full tokens if that
+    /// result is no longer known.
+    pub fn delta(
+        &mut self,
+        uri: &Url,
+        previous_result_id: &str,
+        tokens: Vec<SemanticToken>,
+    ) -> SemanticTokensFullDeltaResult {
+        let edits = match self.results.get(uri) {
+            Some((result_id, previous)) if result_id == previous_result_id => {
+                diff(previous, &tokens)
+            }
+            _ => return SemanticTokensFullDeltaResult::Tokens(self.full(uri, tokens)),
+        };
+        let result_id = self.store(uri, tokens);
+        SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+            result_id: Some(result_id),
+            edits,
+        })
+    }
+}
+
+/// A single edit replacing the changed middle of `old` with that of `new`.
+fn diff(old: &[SemanticToken], new: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = old.iter().zip(new).take_while(|(x, y)| x == y).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let deleted = old.len() - prefix - suffix;
+    let inserted = &new[prefix..new.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return Vec::new();
+    }
+    vec![SemanticTokensEdit {
+        start: prefix as u32 * TOKEN_WIDTH,
+        delete_count: deleted as u32 * TOKEN_WIDTH,
+        data: Some(inserted.to_vec()),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(delta_line: u32, token_type: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start: 0,
+            length: 1,
+            token_type,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    #[test]
+    fn delta_against_known_result_sends_changed_span() {
+        let uri = Url::parse("file:///base.ic10").unwrap();
+        let mut cache = TokenCache::default();
+        let first = cache.full(&uri, vec![token(0, 1), token(1, 2), token(1, 3)]);
+
+        let result = cache.delta(
+            &uri,
+            first.result_id.as_deref().unwrap(),
+            vec![token(0, 1), token(1, 4), token(1, 4), token(1, 3)],
+        );
+        let SemanticTokensFullDeltaResult::TokensDelta(delta) = result else {
+            panic!("expected a delta");
+        };
+        assert_eq!(delta.edits.len(), 1);
+        assert_eq!(delta.edits[0].start, 5);
+        assert_eq!(delta.edits[0].delete_count, 5);
+        assert_eq!(delta.edits[0].data.as_ref().unwrap().len(), 2);
+        assert_ne!(delta.result_id, first.result_id);
+    }
+
+    #[test]
+    fn unknown_result_falls_back_to_full_tokens() {
+        let uri = Url::parse("file:///base.ic10").unwrap();
+        let mut cache = TokenCache::default();
+        cache.full(&uri, vec![token(0, 1)]);
+        let result = cache.delta(&uri, "stale", vec![token(0, 2)]);
+        assert!(matches!(result, SemanticTokensFullDeltaResult::Tokens(_)));
+    }
+}
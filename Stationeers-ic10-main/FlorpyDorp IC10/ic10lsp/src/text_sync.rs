@@ -0,0 +1,157 @@
+//! Incremental document synchronisation.
+//!
+//! The client sends only the edited ranges of a document. They are applied to the stored
+//! text here, and the difference between the old and new text is turned into a single
+//! tree-sitter `InputEdit` so the previous tree can be reused when reparsing.
+
+use tower_lsp::lsp_types::{Position as LspPosition, TextDocumentContentChangeEvent};
+use tree_sitter::{InputEdit, Point};
+
+/// Apply `changes` in order to `text`. A change without a range replaces the whole text.
+///
+/// `utf8` selects how the `character` of a position is counted: UTF-8 bytes when the
+/// client negotiated that encoding, UTF-16 code units otherwise.
+pub fn apply_changes(
+    mut text: String,
+    changes: Vec<TextDocumentContentChangeEvent>,
+    utf8: bool,
+) -> String {
+    for change in changes {
+        match change.range {
+            Some(range) => {
+                let start = offset_at(&text, range.start, utf8);
+                let end = offset_at(&text, range.end, utf8).max(start);
+                text.replace_range(start..end, &change.text);
+            }
+            None => text = change.text,
+        }
+    }
+    text
+}
+
+/// Byte offset of `position` in `text`, clamped to the end of its line and of the text.
+pub fn offset_at(text: &str, position: LspPosition, utf8: bool) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(idx) => line_start += idx + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + idx;
+        }
+        units += if utf8 { c.len_utf8() } else { c.len_utf16() };
+    }
+    line_start + line.len()
+}
+
+/// The edit turning `old` into `new`, or `None` when they are equal.
+pub fn input_edit(old: &str, new: &str) -> Option<InputEdit> {
+    if old == new {
+        return None;
+    }
+
+    let mut prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(x, y)| x == y)
+        .count();
+    while !old.is_char_boundary(prefix) || !new.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let mut suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(max_suffix)
+        .take_while(|(x, y)| x == y)
+        .count();
+    while !old.is_char_boundary(old.len() - suffix) || !new.is_char_boundary(new.len() - suffix) {
+        suffix -= 1;
+    }
+
+    let old_end_byte = old.len() - suffix;
+    let new_end_byte = new.len() - suffix;
+    Some(InputEdit {
+        start_byte: prefix,
+        old_end_byte,
+        new_end_byte,
+        start_position: point_at(old, prefix),
+        old_end_position: point_at(old, old_end_byte),
+        new_end_position: point_at(new, new_end_byte),
+    })
+}
+
+/// Row and byte column of `offset` in `text`.
+fn point_at(text: &str, offset: usize) -> Point {
+    let before = &text[..offset];
+    let row = before.matches('\n').count();
+    let column = offset - before.rfind('\n').map_or(0, |x| x + 1);
+    Point { row, column }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::Range;
+    use tree_sitter::Parser;
+
+    fn change(range: ((u32, u32), (u32, u32)), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                LspPosition::new(range.0 .0, range.0 .1),
+                LspPosition::new(range.1 .0, range.1 .1),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn applies_ranged_changes_in_order() {
+        let text = "move r0 1\nj start\n".to_string();
+        let text = apply_changes(
+            text,
+            vec![
+                change(((0, 8), (0, 9)), "42"),
+                change(((1, 2), (1, 7)), "loop"),
+                change(((2, 0), (2, 0)), "yield\n"),
+            ],
+            true,
+        );
+        assert_eq!(text, "move r0 42\nj loop\nyield\n");
+    }
+
+    #[test]
+    fn utf16_columns_skip_wide_characters() {
+        let text = "# \u{1F600} x\n";
+        assert_eq!(offset_at(text, LspPosition::new(0, 5), false), 7);
+        assert_eq!(offset_at(text, LspPosition::new(0, 7), true), 7);
+        assert_eq!(offset_at(text, LspPosition::new(0, 99), false), 8);
+        assert_eq!(offset_at(text, LspPosition::new(5, 0), false), text.len());
+    }
+
+    #[test]
+    fn reparse_with_edit_matches_fresh_parse() {
+        let old = "alias sensor d0\nl r0 sensor Temperature\nj 0\n";
+        let new = "alias sensor d0\nl r0 sensor Pressure\ns db Setting r0\nj 0\n";
+
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let mut tree = parser.parse(old, None).unwrap();
+        tree.edit(&input_edit(old, new).unwrap());
+        let reparsed = parser.parse(new, Some(&tree)).unwrap();
+        let fresh = parser.parse(new, None).unwrap();
+
+        assert_eq!(reparsed.root_node().to_sexp(), fresh.root_node().to_sexp());
+        assert!(input_edit(new, new).is_none());
+    }
+}
@@ -622,7 +622,7 @@ pub(crate) fn create_enhanced_instruction_hover_with_history(
     instruction: &str,
     instruction_node: tree_sitter::Node,
     content: &str,
-    register_analyzer: &crate::additional_features::RegisterAnalyzer,
+    register_analyzer: &crate::register_analysis::RegisterAnalyzer,
 ) -> Vec<tower_lsp::lsp_types::MarkedString> {
     use tower_lsp::lsp_types::MarkedString;
 
@@ -0,0 +1,255 @@
+//! Ranges, definitions and type data shared by the diagnostics and the editing features.
+//!
+//! [`Position`] and [`Range`] wrap the LSP types so they convert to and from tree-sitter points,
+//! and [`TypeData`] holds the defines, aliases and labels collected from a script.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use tree_sitter::{Node, Query, QueryCursor};
+
+use crate::hash_utils::{compute_crc32, extract_hash_argument, is_hash_function_call};
+use crate::instructions;
+
+#[derive(Debug, Clone)]
+pub struct DefinitionData<T> {
+    pub range: Range,
+    pub value: T,
+}
+
+impl<T> DefinitionData<T> {
+    pub fn new(range: Range, value: T) -> Self {
+        DefinitionData { range, value }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AliasValue {
+    Register(String),
+    Device(String),
+}
+
+impl Display for AliasValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            AliasValue::Register(s) => s,
+            AliasValue::Device(s) => s,
+        };
+        s.fmt(f)
+    }
+}
+
+impl From<String> for AliasValue {
+    fn from(value: String) -> Self {
+        use AliasValue::*;
+        if value.starts_with("d") {
+            Device(value)
+        } else {
+            Register(value)
+        }
+    }
+}
+
+pub trait HasType {
+    fn get_type(&self) -> instructions::DataType;
+}
+
+impl HasType for AliasValue {
+    fn get_type(&self) -> instructions::DataType {
+        match *self {
+            AliasValue::Register(_) => instructions::DataType::Register,
+            AliasValue::Device(_) => instructions::DataType::Device,
+        }
+    }
+}
+
+impl HasType for DefinitionData<f64> {
+    fn get_type(&self) -> instructions::DataType {
+        instructions::DataType::Number
+    }
+}
+
+impl HasType for DefinitionData<u8> {
+    fn get_type(&self) -> instructions::DataType {
+        instructions::DataType::Number
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DefineValue {
+    pub original: String,
+    pub resolved_numeric: Option<i32>,
+}
+
+impl DefineValue {
+    fn resolve_numeric(text: &str) -> Option<i32> {
+        if let Ok(value) = text.trim().parse::<i32>() {
+            return Some(value);
+        }
+        if is_hash_function_call(text) {
+            if let Some(arg) = extract_hash_argument(text) {
+                return Some(compute_crc32(&arg));
+            }
+        }
+        None
+    }
+
+    pub fn resolved_numeric(&self) -> Option<i32> {
+        self.resolved_numeric
+    }
+}
+
+impl From<String> for DefineValue {
+    fn from(value: String) -> Self {
+        let resolved_numeric = Self::resolve_numeric(&value);
+        Self {
+            original: value,
+            resolved_numeric,
+        }
+    }
+}
+
+impl std::fmt::Display for DefineValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.original.fmt(f)
+    }
+}
+
+impl HasType for DefineValue {
+    fn get_type(&self) -> instructions::DataType {
+        instructions::DataType::Number
+    }
+}
+
+impl<T> HasType for DefinitionData<T>
+where
+    T: HasType,
+{
+    fn get_type(&self) -> instructions::DataType {
+        self.value.get_type()
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct TypeData {
+    pub defines: HashMap<String, DefinitionData<DefineValue>>,
+    pub aliases: HashMap<String, DefinitionData<AliasValue>>,
+    pub labels: HashMap<String, DefinitionData<u8>>,
+}
+
+impl TypeData {
+    pub fn get_range(&self, name: &str) -> Option<Range> {
+        if let Some(definition_data) = self.defines.get(name) {
+            return Some(definition_data.range.clone());
+        }
+        if let Some(definition_data) = self.aliases.get(name) {
+            return Some(definition_data.range.clone());
+        }
+        if let Some(definition_data) = self.labels.get(name) {
+            return Some(definition_data.range.clone());
+        }
+        None
+    }
+}
+
+pub trait NodeEx: Sized {
+    fn find_parent(&self, kind: &str) -> Option<Self>;
+    fn query<'a>(&'a self, query: &str, content: impl AsRef<[u8]>) -> Option<Node<'a>>;
+}
+
+impl<'a> NodeEx for Node<'a> {
+    fn find_parent(&self, kind: &str) -> Option<Self> {
+        let mut cur = self.clone();
+        while cur.kind() != kind {
+            cur = cur.parent()?;
+        }
+        Some(cur)
+    }
+
+    fn query(&self, query: &str, content: impl AsRef<[u8]>) -> Option<Node<'a>> {
+        let mut cursor = QueryCursor::new();
+        let query = match Query::new(tree_sitter_ic10::language(), query) {
+            Ok(q) => q,
+            Err(_e) => {
+                // If the node type in the query doesn't exist in this parser build, fail gracefully
+                return None;
+            }
+        };
+
+        let mut captures = cursor.captures(&query, self.clone(), content.as_ref());
+        captures
+            .next()
+            .map(|x| x.0.captures)
+            .and_then(|x| x.get(0))
+            .map(|x| x.node)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Position(pub tower_lsp::lsp_types::Position);
+
+#[derive(Clone, Debug)]
+pub struct Range(pub tower_lsp::lsp_types::Range);
+
+impl Range {
+    pub fn contains(&self, position: Position) -> bool {
+        let (start_line, start_char) = (self.0.start.line, self.0.start.character);
+        let (end_line, end_char) = (self.0.end.line, self.0.end.character);
+        let (line, character) = (position.0.line, position.0.character);
+
+        (line > start_line && line < end_line)
+            || (line == start_line && character >= start_char)
+            || (line == end_line && character <= end_char)
+    }
+}
+
+impl From<tree_sitter::Point> for Position {
+    fn from(value: tree_sitter::Point) -> Self {
+        Position(tower_lsp::lsp_types::Position::new(
+            value.row as u32,
+            value.column as u32,
+        ))
+    }
+}
+
+impl From<tower_lsp::lsp_types::Position> for Position {
+    fn from(value: tower_lsp::lsp_types::Position) -> Self {
+        Position(value)
+    }
+}
+
+impl From<Position> for tower_lsp::lsp_types::Position {
+    fn from(value: Position) -> Self {
+        value.0
+    }
+}
+
+impl From<Position> for tree_sitter::Point {
+    fn from(value: Position) -> Self {
+        tree_sitter::Point {
+            row: value.0.line as usize,
+            column: value.0.character as usize,
+        }
+    }
+}
+
+impl From<tree_sitter::Range> for Range {
+    fn from(value: tree_sitter::Range) -> Self {
+        Range(tower_lsp::lsp_types::Range::new(
+            Position::from(value.start_point).into(),
+            Position::from(value.end_point).into(),
+        ))
+    }
+}
+
+impl From<tower_lsp::lsp_types::Range> for Range {
+    fn from(value: tower_lsp::lsp_types::Range) -> Self {
+        Range(value)
+    }
+}
+
+impl From<Range> for tower_lsp::lsp_types::Range {
+    fn from(value: Range) -> Self {
+        value.0
+    }
+}
@@ -0,0 +1,196 @@
+//! Workspace-wide symbol index.
+//!
+//! Every `.ic10` file below the workspace folders is parsed once and its labels, defines
+//! and aliases are kept here, so `workspace/symbol` can answer for scripts that are not
+//! open in the editor. Open documents overwrite their entry with the live buffer contents.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::{SymbolInformation, Url};
+use tree_sitter::Parser;
+
+/// Directory names that never hold user scripts and are not descended into.
+const SKIPPED_DIRECTORIES: &[&str] = &[".git", ".vscode", "node_modules", "target"];
+
+#[derive(Default)]
+pub struct WorkspaceIndex {
+    folders: Vec<PathBuf>,
+    symbols: HashMap<Url, Vec<SymbolInformation>>,
+}
+
+impl WorkspaceIndex {
+    pub fn folders(&self) -> &[PathBuf] {
+        &self.folders
+    }
+
+    pub fn add_folder(&mut self, folder: PathBuf) {
+        if !self.folders.contains(&folder) {
+            self.folders.push(folder);
+        }
+    }
+
+    /// Forget a workspace folder together with every file indexed below it.
+    pub fn remove_folder(&mut self, folder: &Path) {
+        self.folders.retain(|x| x != folder);
+        self.symbols.retain(|uri, _| {
+            uri.to_file_path()
+                .map_or(true, |path| !path.starts_with(folder))
+        });
+    }
+
+    pub fn contains(&self, uri: &Url) -> bool {
+        self.symbols.contains_key(uri)
+    }
+
+    pub fn insert(&mut self, uri: Url, symbols: Vec<SymbolInformation>) {
+        self.symbols.insert(uri, symbols);
+    }
+
+    pub fn remove(&mut self, uri: &Url) {
+        self.symbols.remove(uri);
+    }
+
+    /// Symbols whose name fuzzily matches `query`, sorted by name then file.
+    pub fn query(&self, query: &str) -> Vec<SymbolInformation> {
+        let mut ret: Vec<SymbolInformation> = self
+            .symbols
+            .values()
+            .flatten()
+            .filter(|symbol| fuzzy_match(query, &symbol.name))
+            .cloned()
+            .collect();
+        ret.sort_by(|x, y| {
+            x.name
+                .cmp(&y.name)
+                .then_with(|| x.location.uri.as_str().cmp(y.location.uri.as_str()))
+        });
+        ret
+    }
+}
+
+/// Recursively list the `.ic10` files below `root`.
+pub fn find_ic10_files(root: &Path) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                let skipped = path
+                    .file_name()
+                    .and_then(|x| x.to_str())
+                    .is_some_and(|x| SKIPPED_DIRECTORIES.contains(&x));
+                if !skipped {
+                    pending.push(path);
+                }
+            } else if is_ic10_file(&path) {
+                ret.push(path);
+            }
+        }
+    }
+    ret.sort();
+    ret
+}
+
+pub fn is_ic10_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| x.eq_ignore_ascii_case("ic10"))
+}
+
+/// Parse `content` and return the symbols it declares.
+pub fn index_text(uri: &Url, content: &str) -> Vec<SymbolInformation> {
+    let mut parser = Parser::new();
+    parser
+        .set_language(tree_sitter_ic10::language())
+        .expect("Could not set language");
+    let Some(tree) = parser.parse(content, None) else {
+        return Vec::new();
+    };
+    crate::collect_document_symbols(uri, &tree, content)
+}
+
+/// Read and index every script below `folders`. Unreadable files are skipped.
+pub fn scan_folders(folders: &[PathBuf]) -> Vec<(Url, Vec<SymbolInformation>)> {
+    let mut ret = Vec::new();
+    for folder in folders {
+        for path in find_ic10_files(folder) {
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok(uri) = Url::from_file_path(&path) else {
+                continue;
+            };
+            let symbols = index_text(&uri, &content);
+            ret.push((uri, symbols));
+        }
+    }
+    ret
+}
+
+/// Case-insensitive subsequence match, the same filtering editors apply to symbol lists.
+fn fuzzy_match(query: &str, name: &str) -> bool {
+    let mut name_chars = name.chars().map(|c| c.to_ascii_lowercase());
+    query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .all(|q| name_chars.any(|c| c == q))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_labels_defines_and_aliases() {
+        let uri = Url::parse("file:///scripts/airlock.ic10").unwrap();
+        let symbols = index_text(
+            &uri,
+            "define Pump HASH(\"StructureVolumePump\")\nalias sensor d0\nstart:\nj start\n",
+        );
+        let names: Vec<_> = symbols.iter().map(|x| x.name.as_str()).collect();
+        assert!(names.contains(&"Pump"));
+        assert!(names.contains(&"sensor"));
+        assert!(names.contains(&"start"));
+    }
+
+    #[test]
+    fn query_matches_across_files() {
+        let mut index = WorkspaceIndex::default();
+        let a = Url::parse("file:///scripts/a.ic10").unwrap();
+        let b = Url::parse("file:///scripts/b.ic10").unwrap();
+        index.insert(a.clone(), index_text(&a, "mainLoop:\nj mainLoop\n"));
+        index.insert(b.clone(), index_text(&b, "define MaxPressure 100\n"));
+
+        let found = index.query("mloop");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].location.uri, a);
+
+        let found = index.query("maxpr");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].location.uri, b);
+
+        assert_eq!(index.query("").len(), 2);
+    }
+
+    #[test]
+    fn removing_folder_drops_its_files() {
+        let mut index = WorkspaceIndex::default();
+        let folder = std::env::temp_dir().join("ic10-index-test");
+        let uri = Url::from_file_path(folder.join("base.ic10")).unwrap();
+        index.add_folder(folder.clone());
+        index.insert(uri.clone(), Vec::new());
+        index.remove_folder(&folder);
+        assert!(!index.contains(&uri));
+        assert!(index.folders().is_empty());
+    }
+}
@@ -0,0 +1,361 @@
+//! Programmable chips in a world save.
+//!
+//! A save folder holds the world as `world.xml`, whose `<AllThings>` lists every object as a
+//! `<ThingSaveData>` with a `<ReferenceId>`, `<PrefabName>` and usually a `<WorldPosition>`. A
+//! chip is a thing with a `<SourceCode>`; its `<ParentReferenceId>` names the housing it sits in.
+//! The housing's device pins are read from its list of `<long>` reference ids in an element
+//! named like `Devices`, in pin order, with `0` for an unset pin.
+//!
+//! Sources are written back by replacing only the chip's `<SourceCode>` element, so everything
+//! else in the save is kept byte for byte.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::script_library::{escape, unescape};
+
+/// File name of the world inside a save folder.
+pub const WORLD_FILE: &str = "world.xml";
+
+/// The world file of `path`, which is either the file itself or its save folder.
+pub fn world_file(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        path.join(WORLD_FILE)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+#[derive(Debug)]
+pub struct Chip {
+    pub reference_id: String,
+    pub prefab: String,
+    /// Custom name or prefab of the housing the chip sits in
+    pub housing: Option<String>,
+    pub position: Option<[f64; 3]>,
+    /// Device connected to each pin, by custom name or prefab
+    pub pins: Vec<Option<String>>,
+    pub source: String,
+    /// Byte range of the whole `<SourceCode>` element
+    source_span: Range<usize>,
+}
+
+impl Chip {
+    /// File name to export the chip's source to; the reference id makes it unique.
+    pub fn file_name(&self) -> String {
+        let name: String = self
+            .housing
+            .as_deref()
+            .unwrap_or(&self.prefab)
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("{}-{}.ic10", name, self.reference_id)
+    }
+
+    /// Multi-line description for listings.
+    pub fn describe(&self) -> String {
+        let mut ret = format!("#{} {}", self.reference_id, self.prefab);
+        if let Some(housing) = &self.housing {
+            ret += &format!(" in {}", housing);
+        }
+        if let Some([x, y, z]) = self.position {
+            ret += &format!(" at ({:.1}, {:.1}, {:.1})", x, y, z);
+        }
+        ret += &format!(", {} lines\n", self.source.lines().count());
+        for (idx, pin) in self.pins.iter().enumerate() {
+            if let Some(device) = pin {
+                ret += &format!("  d{}: {}\n", idx, device);
+            }
+        }
+        ret
+    }
+}
+
+/// Every chip of a world file.
+pub fn chips(xml: &str) -> Result<Vec<Chip>, String> {
+    let root = parse(xml)?;
+    let mut things: Vec<&Element> = Vec::new();
+    root.collect("ThingSaveData", &mut things);
+    let by_id: HashMap<&str, &Element> = things
+        .iter()
+        .filter_map(|x| Some((x.child_text("ReferenceId")?, *x)))
+        .collect();
+    let name = |thing: &Element| {
+        thing
+            .child_text("CustomName")
+            .filter(|x| !x.is_empty())
+            .or_else(|| thing.child_text("PrefabName"))
+            .unwrap_or("?")
+            .to_string()
+    };
+
+    let mut ret = Vec::new();
+    for thing in &things {
+        let Some(source) = thing.child("SourceCode") else {
+            continue;
+        };
+        let parent = thing
+            .child_text("ParentReferenceId")
+            .and_then(|x| by_id.get(x));
+        let pins = parent
+            .and_then(|x| x.children.iter().find(|x| is_device_list(x)))
+            .map(|list| {
+                list.children
+                    .iter()
+                    .map(|x| by_id.get(x.text.as_str()).map(|x| name(x)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        ret.push(Chip {
+            reference_id: thing.child_text("ReferenceId").unwrap_or("?").to_string(),
+            prefab: thing.child_text("PrefabName").unwrap_or("?").to_string(),
+            housing: parent.map(|x| name(x)),
+            position: position(thing).or_else(|| parent.and_then(|x| position(x))),
+            pins,
+            source: source.text.clone(),
+            source_span: source.span.clone(),
+        });
+    }
+    Ok(ret)
+}
+
+/// `xml` with the sources of the chips in `sources`, keyed by reference id, replaced. Fails if a
+/// reference id names no chip.
+pub fn inject(xml: &str, sources: &HashMap<String, String>) -> Result<String, String> {
+    let chips = chips(xml)?;
+    for id in sources.keys() {
+        if !chips.iter().any(|x| &x.reference_id == id) {
+            return Err(format!("The save has no chip #{}", id));
+        }
+    }
+    let mut spans: Vec<(&Range<usize>, &String)> = chips
+        .iter()
+        .filter_map(|x| Some((&x.source_span, sources.get(&x.reference_id)?)))
+        .collect();
+    spans.sort_by_key(|x| std::cmp::Reverse(x.0.start));
+
+    let mut ret = xml.to_string();
+    for (span, source) in spans {
+        ret.replace_range(
+            span.clone(),
+            &format!("<SourceCode>{}</SourceCode>", escape(source)),
+        );
+    }
+    Ok(ret)
+}
+
+/// Reference id of an exported chip file, the number after the last `-` of its name.
+pub fn reference_id_of(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    let id = stem.rsplit('-').next()?;
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_digit())).then(|| id.to_string())
+}
+
+fn is_device_list(element: &Element) -> bool {
+    element.name.contains("Device")
+        && !element.children.is_empty()
+        && element
+            .children
+            .iter()
+            .all(|x| matches!(x.name.as_str(), "long" | "int"))
+}
+
+fn position(thing: &Element) -> Option<[f64; 3]> {
+    let position = thing.child("WorldPosition")?;
+    let axis = |name| position.child_text(name)?.parse().ok();
+    Some([axis("x")?, axis("y")?, axis("z")?])
+}
+
+/// An XML element with the byte range it spans in the document.
+#[derive(Debug)]
+struct Element {
+    name: String,
+    children: Vec<Element>,
+    text: String,
+    span: Range<usize>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|x| x.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|x| x.text.trim())
+    }
+
+    fn collect<'a>(&'a self, name: &str, out: &mut Vec<&'a Element>) {
+        for child in &self.children {
+            if child.name == name {
+                out.push(child);
+            } else {
+                child.collect(name, out);
+            }
+        }
+    }
+}
+
+/// Parse the elements of a document; attributes are skipped and text is unescaped.
+fn parse(xml: &str) -> Result<Element, String> {
+    let mut stack = vec![Element {
+        name: String::new(),
+        children: Vec::new(),
+        text: String::new(),
+        span: 0..xml.len(),
+    }];
+    let mut pos = 0;
+    while let Some(found) = xml[pos..].find('<') {
+        let start = pos + found;
+        stack.last_mut().unwrap().text += &unescape(&xml[pos..start]);
+        let rest = &xml[start..];
+        let skip_to = |end: &str| {
+            rest.find(end)
+                .map(|x| start + x + end.len())
+                .ok_or_else(|| format!("Unterminated markup at byte {}", start))
+        };
+        if rest.starts_with("<?") {
+            pos = skip_to("?>")?;
+        } else if rest.starts_with("<!--") {
+            pos = skip_to("-->")?;
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata
+                .find("]]>")
+                .ok_or_else(|| format!("Unterminated CDATA at byte {}", start))?;
+            stack.last_mut().unwrap().text += &cdata[..end];
+            pos = start + "<![CDATA[".len() + end + 3;
+        } else if rest.starts_with("<!") {
+            pos = skip_to(">")?;
+        } else if let Some(closing) = rest.strip_prefix("</") {
+            let end = start + rest.find('>').ok_or("Unterminated closing tag")? + 1;
+            let name = closing[..end - start - 3].trim();
+            let mut element = stack
+                .pop()
+                .filter(|_| !stack.is_empty())
+                .ok_or_else(|| format!("Unexpected </{}> at byte {}", name, start))?;
+            if element.name != name {
+                return Err(format!(
+                    "Expected </{}>, found </{}> at byte {}",
+                    element.name, name, start
+                ));
+            }
+            element.span.end = end;
+            stack.last_mut().unwrap().children.push(element);
+            pos = end;
+        } else {
+            let end = start + rest.find('>').ok_or("Unterminated tag")? + 1;
+            let tag = &xml[start + 1..end - 1];
+            let self_closing = tag.ends_with('/');
+            let name = tag
+                .trim_end_matches('/')
+                .split(|c: char| c.is_whitespace())
+                .next()
+                .unwrap_or("")
+                .to_string();
+            let element = Element {
+                name,
+                children: Vec::new(),
+                text: String::new(),
+                span: start..end,
+            };
+            if self_closing {
+                stack.last_mut().unwrap().children.push(element);
+            } else {
+                stack.push(element);
+            }
+            pos = end;
+        }
+    }
+    if stack.len() != 1 {
+        return Err(format!("Unclosed <{}>", stack.last().unwrap().name));
+    }
+    Ok(stack.pop().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORLD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<WorldData xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <AllThings>
+    <ThingSaveData xsi:type="DeviceSaveData">
+      <ReferenceId>11</ReferenceId>
+      <PrefabName>StructureGasSensor</PrefabName>
+      <CustomName />
+    </ThingSaveData>
+    <ThingSaveData xsi:type="CircuitHousingSaveData">
+      <ReferenceId>20</ReferenceId>
+      <PrefabName>StructureCircuitHousing</PrefabName>
+      <CustomName>Furnace Control</CustomName>
+      <WorldPosition><x>1.5</x><y>-2</y><z>10.25</z></WorldPosition>
+      <Devices><long>0</long><long>11</long></Devices>
+    </ThingSaveData>
+    <ThingSaveData xsi:type="ProgrammableChipSaveData">
+      <ReferenceId>21</ReferenceId>
+      <PrefabName>ItemIntegratedCircuit10</PrefabName>
+      <ParentReferenceId>20</ParentReferenceId>
+      <SourceCode>l r0 d1 Temperature&#xA;brgt r0 500 -1</SourceCode>
+    </ThingSaveData>
+    <ThingSaveData xsi:type="ProgrammableChipSaveData">
+      <ReferenceId>30</ReferenceId>
+      <PrefabName>ItemIntegratedCircuit10</PrefabName>
+      <SourceCode />
+    </ThingSaveData>
+  </AllThings>
+</WorldData>
+"#;
+
+    #[test]
+    fn chips_are_listed_with_housing_location_and_pins() {
+        let chips = chips(WORLD).unwrap();
+        assert_eq!(chips.len(), 2);
+        let chip = &chips[0];
+        assert_eq!(chip.source, "l r0 d1 Temperature\nbrgt r0 500 -1");
+        assert_eq!(chip.position, Some([1.5, -2.0, 10.25]));
+        assert_eq!(chip.file_name(), "Furnace_Control-21.ic10");
+        assert_eq!(
+            chip.describe(),
+            "#21 ItemIntegratedCircuit10 in Furnace Control at (1.5, -2.0, 10.2), 2 lines\n  d1: StructureGasSensor\n"
+        );
+        assert_eq!(chips[1].source, "");
+        assert_eq!(
+            reference_id_of(Path::new("out/Furnace_Control-21.ic10")),
+            Some("21".to_string())
+        );
+    }
+
+    #[test]
+    fn injected_sources_replace_only_the_source_elements() {
+        let sources = HashMap::from([
+            ("21".to_string(), "yield\nj 0 # a < b".to_string()),
+            ("30".to_string(), "sleep 1".to_string()),
+        ]);
+        let updated = inject(WORLD, &sources).unwrap();
+        let chips = chips(&updated).unwrap();
+        assert_eq!(chips[0].source, "yield\nj 0 # a < b");
+        assert_eq!(chips[1].source, "sleep 1");
+        assert!(updated.contains("<SourceCode>yield\nj 0 # a &lt; b</SourceCode>"));
+        assert_eq!(
+            updated
+                .replace("<SourceCode>yield\nj 0 # a &lt; b</SourceCode>", "")
+                .replace("<SourceCode>sleep 1</SourceCode>", ""),
+            WORLD
+                .replace(
+                    "<SourceCode>l r0 d1 Temperature&#xA;brgt r0 500 -1</SourceCode>",
+                    ""
+                )
+                .replace("<SourceCode />", "")
+        );
+
+        let missing = HashMap::from([("99".to_string(), String::new())]);
+        assert!(inject(WORLD, &missing).is_err());
+    }
+}